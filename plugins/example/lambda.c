@@ -0,0 +1,77 @@
+/*
+ * Example plugin: the lambda map z = c z (1 - z) from z = 1/2, with an
+ * escape radius set by --plugin-params (defaults to 2).
+ *
+ *     cc -O2 -shared -fPIC -I plugins -o liblambda.so plugins/example/lambda.c
+ *     cargo run -- --plugin ./liblambda.so --plugin-params 8
+ */
+#include <stdlib.h>
+
+#include "mandelbrot_plugin.h"
+
+static const MandelbrotPluginInfo INFO = {
+    .abi_version = MANDELBROT_PLUGIN_ABI,
+    .name = "lambda",
+    .description = "z = c z (1 - z)",
+    .center_re = 1.0,
+    .center_im = 0.0,
+    .scale = 5.0,
+};
+
+typedef struct {
+    double radius2;
+} Lambda;
+
+const MandelbrotPluginInfo *mandelbrot_plugin_info(void) {
+    return &INFO;
+}
+
+int32_t mandelbrot_plugin_init(const char *params, void **state) {
+    Lambda *lambda = malloc(sizeof(Lambda));
+    if (lambda == NULL) {
+        return 1;
+    }
+    double radius = params[0] != '\0' ? atof(params) : 2.0;
+    if (radius <= 0.0) {
+        free(lambda);
+        return 2;
+    }
+    lambda->radius2 = radius * radius;
+    *state = lambda;
+    return 0;
+}
+
+void mandelbrot_plugin_free(void *state) {
+    free(state);
+}
+
+static void iterate(const Lambda *lambda, double re, double im, uint32_t max_iter,
+                    MandelbrotSample *out) {
+    double zr = 0.5, zi = 0.0;
+    uint32_t n = 0;
+    while (zr * zr + zi * zi <= lambda->radius2 && n < max_iter) {
+        /* w = z (1 - z), z = c w */
+        double wr = zr - (zr * zr - zi * zi);
+        double wi = zi - 2.0 * zr * zi;
+        zr = re * wr - im * wi;
+        zi = re * wi + im * wr;
+        n++;
+    }
+    out->iter = n;
+    out->converged = 0;
+    out->fraction = 0.0f;
+    out->root = MANDELBROT_NO_ROOT;
+}
+
+void mandelbrot_plugin_iterate_pixel(const void *state, double re, double im, uint32_t max_iter,
+                                     MandelbrotSample *out) {
+    iterate(state, re, im, max_iter, out);
+}
+
+/* Rows are what a vectorized formula would work on, this one just loops. */
+void mandelbrot_plugin_iterate_row(const void *state, const double *re, const double *im,
+                                   size_t count, uint32_t max_iter, MandelbrotSample *out) {
+    for (size_t i = 0; i < count; i++) {
+        iterate(state, re[i], im[i], max_iter, &out[i]);
+    }
+}
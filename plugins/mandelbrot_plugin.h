@@ -0,0 +1,68 @@
+/*
+ * Plugin interface of the mandelbrot viewer, loaded with --plugin <PATH>.
+ *
+ * A plugin is a shared library exporting these C functions:
+ *
+ * - mandelbrot_plugin_info (required): static description of the plugin.
+ * - mandelbrot_plugin_init (optional): called once after loading with the
+ *   text of --plugin-params, an empty string when unset. It returns 0 on
+ *   success and stores in *state a pointer handed back to every other call.
+ * - mandelbrot_plugin_free (optional): releases the state before unloading.
+ * - mandelbrot_plugin_iterate_pixel or mandelbrot_plugin_iterate_row (one of
+ *   them is required): evaluate the formula at one point, or at `count`
+ *   points at once. When both are exported the renderer hands batches of
+ *   points to iterate_row, single points go to iterate_pixel.
+ *
+ * The iterate functions are called from several threads at once with the
+ * same state: they must not modify it.
+ */
+#ifndef MANDELBROT_PLUGIN_H
+#define MANDELBROT_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Bumped on every incompatible change of this file. */
+#define MANDELBROT_PLUGIN_ABI 1
+
+/* Root of the samples without one. */
+#define MANDELBROT_NO_ROOT 0xFFFFFFFFu
+
+typedef struct {
+    /* MANDELBROT_PLUGIN_ABI of the header the plugin was built with. */
+    uint32_t abi_version;
+    /* Short name, shown in the logs. */
+    const char *name;
+    /* One line description, may be NULL. */
+    const char *description;
+    /* Center and extent of the shorter side of the view opened first. */
+    double center_re;
+    double center_im;
+    double scale;
+} MandelbrotPluginInfo;
+
+typedef struct {
+    /* Iterations before the orbit escaped or converged, max_iter if neither. */
+    uint32_t iter;
+    /* 1 if the orbit converged, 0 if it escaped or reached max_iter. */
+    uint32_t converged;
+    /* Where in the last iteration a converged orbit crossed the threshold,
+     * from 0 to 1, for smooth coloring. */
+    float fraction;
+    /* Root the orbit converged to, colored apart, or MANDELBROT_NO_ROOT. */
+    uint32_t root;
+} MandelbrotSample;
+
+const MandelbrotPluginInfo *mandelbrot_plugin_info(void);
+
+int32_t mandelbrot_plugin_init(const char *params, void **state);
+
+void mandelbrot_plugin_free(void *state);
+
+void mandelbrot_plugin_iterate_pixel(const void *state, double re, double im, uint32_t max_iter,
+                                     MandelbrotSample *out);
+
+void mandelbrot_plugin_iterate_row(const void *state, const double *re, const double *im,
+                                   size_t count, uint32_t max_iter, MandelbrotSample *out);
+
+#endif
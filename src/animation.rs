@@ -0,0 +1,217 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::complex::Complex;
+
+/// Shape of the transition from a keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl FromStr for Easing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "ease-in" => Ok(Self::EaseIn),
+            "ease-out" => Ok(Self::EaseOut),
+            "ease-in-out" => Ok(Self::EaseInOut),
+            _ => Err(format!(
+                "unknown easing '{}' (expected linear, ease-in, ease-out or ease-in-out)",
+                s
+            )),
+        }
+    }
+}
+
+impl Easing {
+    /// Eased progress for `t` in `[0, 1]`.
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// State of the view at a point in time. `easing` shapes the transition to
+/// the next keyframe.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    /// Seconds from the start.
+    pub time: f64,
+    pub center: Complex<f64>,
+    pub scale: f64,
+    /// Degrees.
+    pub rotation: f64,
+    pub max_iter: u32,
+    pub palette_offset: f32,
+    pub easing: Easing,
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::Io(err) => write!(f, "{}", err),
+            AnimationError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+impl From<std::io::Error> for AnimationError {
+    fn from(err: std::io::Error) -> Self {
+        AnimationError::Io(err)
+    }
+}
+
+/// Keyframes sorted by time, the view is interpolated between them.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Animation {
+    /// Load a keyframe file, see [`Animation::parse`].
+    pub fn load(path: &Path, initial: Keyframe) -> Result<Self, AnimationError> {
+        Self::parse(&std::fs::read_to_string(path)?, initial)
+    }
+
+    /// One keyframe per line, a time in seconds followed by `key=value`
+    /// settings among `center=re,im`, `scale`, `rotation` (degrees),
+    /// `max-iter`, `palette-offset` and `easing` (towards the next keyframe):
+    ///
+    /// ```text
+    /// # Zoom on the seahorse valley
+    /// 0   center=-0.5,0 scale=3 easing=ease-in-out
+    /// 10  center=-0.743643,0.131825 scale=1e-4 rotation=90 max-iter=4096
+    /// ```
+    ///
+    /// Settings not given keep the value of the previous keyframe, the first
+    /// keyframe starts from `initial`. Text after `#` is a comment.
+    pub fn parse(text: &str, initial: Keyframe) -> Result<Self, AnimationError> {
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let error = |message: String| AnimationError::Parse {
+                line: line_no,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut tokens = line.split_whitespace();
+            let Some(time) = tokens.next() else {
+                continue;
+            };
+
+            let mut keyframe = keyframes.last().copied().unwrap_or(initial);
+            keyframe.time = time
+                .parse()
+                .map_err(|_| error(format!("invalid time '{}'", time)))?;
+            if keyframes.last().is_some_and(|k| keyframe.time <= k.time) {
+                return Err(error("keyframe times must increase".into()));
+            }
+
+            for token in tokens {
+                let (key, value) = token
+                    .split_once('=')
+                    .ok_or_else(|| error(format!("expected key=value, got '{}'", token)))?;
+                let invalid = || error(format!("invalid {} '{}'", key, value));
+                match key {
+                    "center" => {
+                        let (re, im) = value.split_once(',').ok_or_else(invalid)?;
+                        keyframe.center = Complex::new(
+                            re.parse().map_err(|_| invalid())?,
+                            im.parse().map_err(|_| invalid())?,
+                        );
+                    }
+                    "scale" => {
+                        keyframe.scale = value
+                            .parse()
+                            .ok()
+                            .filter(|&s: &f64| s > 0.0)
+                            .ok_or_else(invalid)?;
+                    }
+                    "rotation" => keyframe.rotation = value.parse().map_err(|_| invalid())?,
+                    "max-iter" => {
+                        keyframe.max_iter =
+                            value.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?;
+                    }
+                    "palette-offset" => {
+                        keyframe.palette_offset = value.parse().map_err(|_| invalid())?;
+                    }
+                    "easing" => keyframe.easing = value.parse().map_err(error)?,
+                    _ => return Err(error(format!("unknown setting '{}'", key))),
+                }
+            }
+            keyframes.push(keyframe);
+        }
+
+        if keyframes.is_empty() {
+            return Err(AnimationError::Parse {
+                line: 0,
+                message: "no keyframe".into(),
+            });
+        }
+        Ok(Self { keyframes })
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Interpolated view at `time`, clamped to the keyframes.
+    pub fn at(&self, time: f64) -> Keyframe {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0];
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1];
+        }
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = from
+            .easing
+            .apply((time - from.time) / (to.time - from.time));
+
+        // Zoom at a constant speed, log-linearly
+        let scale = from.scale * (to.scale / from.scale).powf(t);
+
+        // Move the center so the point ending up in the middle stays still
+        // on screen while zooming, rather than sliding across at the end
+        let w = if (from.scale / to.scale).ln().abs() > 1e-9 {
+            (from.scale - scale) / (from.scale - to.scale)
+        } else {
+            t
+        };
+        let center = from.center + (to.center - from.center) * w;
+
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Keyframe {
+            time,
+            center,
+            scale,
+            rotation: lerp(from.rotation, to.rotation),
+            max_iter: lerp(from.max_iter as f64, to.max_iter as f64).round() as u32,
+            palette_offset: lerp(from.palette_offset as f64, to.palette_offset as f64) as f32,
+            easing: from.easing,
+        }
+    }
+}
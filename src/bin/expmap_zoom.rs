@@ -0,0 +1,238 @@
+//! Rebuild the frames of a zoom video from an exponential map strip written
+//! by `mandelbrot --exp-map`, without computing the fractal again.
+
+use std::f64::consts::TAU;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: expmap_zoom <STRIP> <OUTPUT_DIR> [OPTIONS]
+
+Options:
+    --size <WIDTHxHEIGHT>       Size of the frames (defaults to 640x480)
+    --frames <N>                Number of frames, spread over the whole strip (defaults to 300)
+    -h, --help                  Print this help";
+
+/// Largest number of sub-samples per side when a frame pixel covers several
+/// strip pixels, near the zoom center.
+const MAX_SUBSAMPLES: u32 = 8;
+
+struct Args {
+    strip: PathBuf,
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    frames: u32,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut paths = Vec::new();
+    let (mut width, mut height, mut frames) = (640, 480, 300);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for '{}'", arg));
+        match arg.as_str() {
+            "--size" => {
+                let size = value()?;
+                let parsed = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                (width, height) = parsed
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or(format!("invalid size '{}', expected WIDTHxHEIGHT", size))?;
+            }
+            "--frames" => {
+                let value = value()?;
+                frames = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or(format!("invalid frame count '{}'", value))?;
+            }
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => return Err(format!("unknown argument '{}'", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [strip, output] = <[PathBuf; 2]>::try_from(paths)
+        .map_err(|_| "expected a strip and an output directory".to_string())?;
+    Ok(Some(Args {
+        strip,
+        output,
+        width,
+        height,
+        frames,
+    }))
+}
+
+/// The strip, in linear light to resample it.
+struct Strip {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl Strip {
+    fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut reader = BufReader::new(File::open(path)?);
+
+        // Header fields, skipping comment lines
+        let mut fields = Vec::new();
+        while fields.len() < 4 {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid("truncated PPM header"));
+            }
+            let line = line.split('#').next().unwrap_or("");
+            fields.extend(line.split_whitespace().map(str::to_string));
+        }
+        if fields[0] != "P6" || fields[3] != "255" {
+            return Err(invalid("expected an 8 bit binary PPM"));
+        }
+        let parse = |s: &str| s.parse::<u32>().map_err(|_| invalid("invalid PPM size"));
+        let (width, height) = (parse(&fields[1])?, parse(&fields[2])?);
+
+        let mut rgb = vec![0; width as usize * height as usize * 3];
+        reader.read_exact(&mut rgb)?;
+        let pixels = rgb
+            .chunks_exact(3)
+            .map(|p| [0, 1, 2].map(|c| srgb_to_linear(p[c])))
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Bilinear sample at a continuous position, pixel centers at `+ 0.5`.
+    /// Columns wrap around (they are angles), rows are clamped.
+    fn sample(&self, column: f64, row: f64) -> [f32; 3] {
+        let (x, y) = (
+            column - 0.5,
+            (row - 0.5).clamp(0.0, self.height as f64 - 1.0),
+        );
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+        let wrap = |x: f64| x.rem_euclid(self.width as f64) as usize;
+        let clamp = |y: f64| (y as usize).min(self.height as usize - 1);
+        let at = |x: f64, y: f64| self.pixels[clamp(y) * self.width as usize + wrap(x)];
+
+        let (a, b) = (at(x0, y0), at(x0 + 1.0, y0));
+        let (c, d) = (at(x0, y0 + 1.0), at(x0 + 1.0, y0 + 1.0));
+        [0, 1, 2].map(|i| {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            top + (bottom - top) * fy
+        })
+    }
+}
+
+/// Render the frame `zoom` strip rows deep: the outer ring of the strip at
+/// row `zoom` touches the frame corners.
+fn render_frame(strip: &Strip, width: u32, height: u32, zoom: f64) -> Vec<u8> {
+    let delta = TAU / strip.width as f64;
+    let half_diagonal = (width as f64).hypot(height as f64) / 2.0;
+
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for py in 0..height {
+        for px in 0..width {
+            let dx = px as f64 + 0.5 - width as f64 / 2.0;
+            let dy = py as f64 + 0.5 - height as f64 / 2.0;
+
+            // A pixel at distance r spans 1 / (r delta) strip pixels, average
+            // enough sub-samples not to alias near the center
+            let span = 1.0 / (dx.hypot(dy) * delta);
+            let n = (span.ceil() as u32).clamp(1, MAX_SUBSAMPLES);
+            let mut sum = [0.0; 3];
+            for j in 0..n {
+                for i in 0..n {
+                    let sx = dx + (i as f64 + 0.5) / n as f64 - 0.5;
+                    let sy = dy + (j as f64 + 0.5) / n as f64 - 0.5;
+                    let row = zoom + (half_diagonal / sx.hypot(sy)).ln() / delta;
+                    let column = sy.atan2(sx).rem_euclid(TAU) / delta;
+                    let color = strip.sample(column, row);
+                    (0..3).for_each(|c| sum[c] += color[c]);
+                }
+            }
+            let samples = (n * n) as f32;
+            rgb.extend(sum.map(|c| linear_to_srgb(c / samples)));
+        }
+    }
+    rgb
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let strip = Strip::load(&args.strip)
+        .map_err(|err| format!("Failed to load {}: {}", args.strip.display(), err))?;
+
+    // The last frame still resolves half a pixel at its center
+    let delta = TAU / strip.width as f64;
+    let half_diagonal = (args.width as f64).hypot(args.height as f64) / 2.0;
+    let depth = strip.height as f64 - (half_diagonal / 0.5).ln() / delta;
+    if depth <= 0.0 {
+        return Err(format!(
+            "The strip is too short for {}x{} frames, render it with a larger zoom",
+            args.width, args.height
+        ));
+    }
+
+    fs::create_dir_all(&args.output)
+        .map_err(|err| format!("Failed to create {}: {}", args.output.display(), err))?;
+    for frame in 0..args.frames {
+        let zoom = depth * frame as f64 / (args.frames - 1).max(1) as f64;
+        let rgb = render_frame(&strip, args.width, args.height, zoom);
+
+        let path = args.output.join(format!("frame_{:05}.ppm", frame));
+        let write = || -> io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            write!(writer, "P6\n{} {}\n255\n", args.width, args.height)?;
+            writer.write_all(&rgb)
+        };
+        write().map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+        println!("Frame {}/{}", frame + 1, args.frames);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,757 @@
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::color::{self, Dither, LinearColor};
+use crate::complex::Complex;
+use crate::formula;
+use crate::gradient::Gradient;
+use crate::mandelbrot::ViewPort;
+use crate::palette;
+
+/// Points `c` are sampled in the disk of this radius, orbits of the points
+/// outside escape at once.
+const SAMPLE_RADIUS: f64 = 2.0;
+/// Points traced by a batch, each batch has its own random generator.
+const BATCH_SIZE: u64 = 4096;
+/// Spreads the batch numbers over the seeds of their generators.
+const BATCH_SEED_STEP: u64 = 0x9e37_79b9_7f4a_7c15;
+/// Share of the Metropolis proposals drawn uniformly rather than close to
+/// the current point, so no region is left out.
+const UNIFORM_PROPOSALS: f64 = 0.2;
+/// Uniform points tried to start a Metropolis chain before giving up.
+const CHAIN_START_TRIES: u32 = 100_000;
+/// Steps a Metropolis chain makes before adding its hits: it starts from a
+/// uniform point, not from one drawn in proportion to its contribution.
+const CHAIN_BURN_IN: u64 = 256;
+/// Fixed point unit of the Metropolis hit weights, `WEIGHT_SCALE / n` for a
+/// point with `n` orbit points in the view. Small enough for a pixel to take
+/// 2⁴⁰ hits of the heaviest weight, large enough to keep the weights within
+/// 1% below 160 000 orbit points.
+const WEIGHT_SCALE: u64 = 1 << 24;
+
+/// Orbits accumulated by a density render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DensityMode {
+    /// Orbits escaping before the maximum iterations.
+    #[default]
+    Buddhabrot,
+    /// Orbits that never escape, those of the points of the set.
+    AntiBuddhabrot,
+}
+
+impl FromStr for DensityMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buddhabrot" => Ok(Self::Buddhabrot),
+            "anti-buddhabrot" => Ok(Self::AntiBuddhabrot),
+            _ => Err(format!(
+                "unknown density mode '{}' (expected buddhabrot or anti-buddhabrot)",
+                s
+            )),
+        }
+    }
+}
+
+/// How the points `c` are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DensitySampling {
+    /// Independently and uniformly, most points fall outside a zoomed view.
+    #[default]
+    Uniform,
+    /// Metropolis–Hastings chains favoring the points whose orbits cross the
+    /// view, their hits weighted to give the uniform image back.
+    Metropolis,
+}
+
+impl FromStr for DensitySampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Self::Uniform),
+            "metropolis" => Ok(Self::Metropolis),
+            _ => Err(format!(
+                "unknown density sampling '{}' (expected uniform or metropolis)",
+                s
+            )),
+        }
+    }
+}
+
+/// How the hit counts of a channel are brought to `[0, 1]`, relative to the
+/// most visited pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneCurve {
+    Linear,
+    #[default]
+    Sqrt,
+    /// `ln(1 + count) / ln(1 + max)`, brings out the faintest orbits.
+    Log,
+    /// `(count / max)^(1 / gamma)`, gamma 2 is the square root.
+    Gamma(f32),
+}
+
+impl FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "sqrt" => Ok(Self::Sqrt),
+            "log" => Ok(Self::Log),
+            _ => match s.strip_prefix("gamma=").map(str::parse::<f32>) {
+                Some(Ok(gamma)) if gamma > 0.0 && gamma.is_finite() => Ok(Self::Gamma(gamma)),
+                Some(_) => Err(format!("invalid gamma '{}', expected gamma=G above 0", s)),
+                None => Err(format!(
+                    "unknown tone curve '{}' (expected linear, sqrt, log or gamma=G)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl ToneCurve {
+    /// Brightness of `count` hits, `max` being the most of any pixel.
+    pub fn apply(self, count: f64, max: f64) -> f32 {
+        let max = max.max(1.0) as f32;
+        let count = count as f32;
+        match self {
+            ToneCurve::Linear => count / max,
+            ToneCurve::Sqrt => (count / max).sqrt(),
+            ToneCurve::Log => count.ln_1p() / max.ln_1p(),
+            ToneCurve::Gamma(gamma) => (count / max).powf(1.0 / gamma),
+        }
+    }
+}
+
+/// Hit counts of the orbits whose escape iteration is within a window, and
+/// how they are drawn. The channels of a Nebulabrot are added together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Channel {
+    pub min_iter: u32,
+    pub max_iter: u32,
+    pub curve: ToneCurve,
+    /// Color added at full brightness, through the gradient when `None`.
+    pub color: Option<LinearColor>,
+}
+
+impl Channel {
+    /// Every orbit up to `max_iter`, through the gradient.
+    pub fn new(max_iter: u32) -> Self {
+        Self {
+            min_iter: 0,
+            max_iter,
+            curve: ToneCurve::default(),
+            color: None,
+        }
+    }
+
+    fn contains(&self, iter: u32) -> bool {
+        (self.min_iter..=self.max_iter).contains(&iter)
+    }
+}
+
+/// Written `MIN-MAX [CURVE] [#RRGGBB]`, the curve defaulting to sqrt.
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let window = words.next().unwrap_or_default();
+        let Some((min_iter, max_iter)) = window
+            .split_once('-')
+            .and_then(|(min, max)| Some((min.parse().ok()?, max.parse().ok()?)))
+            .filter(|(min, max)| min <= max)
+        else {
+            return Err(format!(
+                "invalid channel '{}', expected 'MIN-MAX [CURVE] [#RRGGBB]'",
+                s
+            ));
+        };
+        let mut channel = Channel {
+            min_iter,
+            max_iter,
+            ..Channel::new(max_iter)
+        };
+        for word in words {
+            if let Some(hex) = word.strip_prefix('#') {
+                let color = palette::parse_hex(hex)
+                    .ok_or_else(|| format!("invalid channel color '{}'", word))?;
+                channel.color = Some(color.into());
+            } else {
+                channel.curve = word.parse()?;
+            }
+        }
+        Ok(channel)
+    }
+}
+
+/// Affine map from the complex plane to window positions, the inverse of
+/// the linear part of a [`ViewPort`].
+#[derive(Debug, Clone, Copy)]
+struct Projection {
+    origin: (f64, f64),
+    re: (f64, f64),
+    im: (f64, f64),
+}
+
+impl Projection {
+    fn new(view: &ViewPort, width: u32, height: u32) -> Self {
+        let origin = view.view_to_point(Complex::new(0.0, 0.0), width, height);
+        let one = view.view_to_point(Complex::new(1.0, 0.0), width, height);
+        let i = view.view_to_point(Complex::new(0.0, 1.0), width, height);
+        Self {
+            origin,
+            re: (one.0 - origin.0, one.1 - origin.1),
+            im: (i.0 - origin.0, i.1 - origin.1),
+        }
+    }
+
+    fn apply(&self, z: Complex<f64>) -> (f64, f64) {
+        (
+            self.origin.0 + z.re * self.re.0 + z.im * self.im.0,
+            self.origin.1 + z.re * self.re.1 + z.im * self.im.1,
+        )
+    }
+}
+
+/// Progress of a density render.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Statistics {
+    pub sampling: DensitySampling,
+    /// Points traced, or steps of the Metropolis chains past their burn-in.
+    pub samples: u64,
+    /// Points drawn uniformly, by the uniform sampling, to start the chains
+    /// or as Metropolis proposals.
+    pub uniform: u64,
+    /// Uniform points with orbit points in the view.
+    pub contributing: u64,
+    /// Orbit points in the view of the uniform points.
+    pub contribution: u64,
+    pub proposed: u64,
+    pub accepted: u64,
+}
+
+impl Statistics {
+    pub fn acceptance_rate(&self) -> f64 {
+        self.accepted as f64 / self.proposed.max(1) as f64
+    }
+
+    /// Share of the uniform points whose orbits cross the view.
+    pub fn contributing_rate(&self) -> f64 {
+        self.contributing as f64 / self.uniform.max(1) as f64
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sampling {
+            DensitySampling::Uniform => write!(
+                f,
+                "{} points traced, {:.2}% crossing the view",
+                self.samples,
+                100.0 * self.contributing_rate()
+            ),
+            DensitySampling::Metropolis => write!(
+                f,
+                "{} steps, {:.1}% of {} proposals accepted, {:.2}% of {} uniform points \
+                 crossing the view",
+                self.samples,
+                100.0 * self.acceptance_rate(),
+                self.proposed,
+                100.0 * self.contributing_rate(),
+                self.uniform
+            ),
+        }
+    }
+}
+
+/// Counters behind [`Statistics`], shared by the threads.
+#[derive(Debug, Default)]
+struct Counters {
+    samples: AtomicU64,
+    uniform: AtomicU64,
+    contributing: AtomicU64,
+    contribution: AtomicU64,
+    proposed: AtomicU64,
+    accepted: AtomicU64,
+}
+
+/// Buffers of a thread.
+struct Scratch {
+    orbit: Vec<Complex<f64>>,
+    /// Histogram cells hit by the last orbit traced.
+    cells: Vec<usize>,
+    /// Cells hit by the current point of a chain.
+    current: Vec<usize>,
+    /// Channels whose window contains the escape of the last orbit traced.
+    channels: Vec<usize>,
+}
+
+/// Buddhabrot: the density of the orbits of `z² + c` over random points
+/// `c`, as a hit count per pixel and channel.
+///
+/// The points are traced by batches, numbered in order, each drawn from a
+/// generator seeded by the seed and its number: the same batches give the
+/// same image whatever the number of threads tracing them. The histogram is
+/// shared by the threads, which can run while it is displayed.
+///
+/// With Metropolis sampling each batch is a chain whose points are visited
+/// in proportion to their contribution, the number of points of their orbit
+/// in the view. Each step adds the hits of the current point divided by its
+/// contribution, so that the histogram estimates the uniform one; it is
+/// scaled back to counts by the mean contribution of the uniform points.
+pub struct Buddhabrot {
+    mode: DensityMode,
+    sampling: DensitySampling,
+    width: u32,
+    height: u32,
+    projection: Projection,
+    /// Extent of the Metropolis mutations, from a pixel to the view.
+    mutation: (f64, f64),
+    max_iter: u32,
+    seed: u64,
+    channels: Vec<Channel>,
+    gradient: Gradient,
+    dither: Dither,
+    /// Counts of every channel, pixel after pixel, in `WEIGHT_SCALE` units
+    /// with Metropolis sampling.
+    histogram: Vec<AtomicU64>,
+    next_batch: AtomicU64,
+    counters: Counters,
+}
+
+impl Buddhabrot {
+    /// Orbits are traced up to the end of the last window of `channels`.
+    pub fn new(
+        mode: DensityMode,
+        width: u32,
+        height: u32,
+        view: &ViewPort,
+        channels: Vec<Channel>,
+        seed: u64,
+        gradient: &Gradient,
+    ) -> Self {
+        let cells = width as usize * height as usize * channels.len();
+        Self {
+            mode,
+            sampling: DensitySampling::default(),
+            width,
+            height,
+            projection: Projection::new(view, width, height),
+            mutation: (
+                view.pixel_size(width, height),
+                view.scale().min(SAMPLE_RADIUS),
+            ),
+            max_iter: channels
+                .iter()
+                .map(|channel| channel.max_iter)
+                .max()
+                .unwrap_or(0),
+            seed,
+            channels,
+            gradient: gradient.clone(),
+            dither: Dither::default(),
+            histogram: (0..cells).map(|_| AtomicU64::new(0)).collect(),
+            next_batch: AtomicU64::new(0),
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    pub fn set_sampling(&mut self, sampling: DensitySampling) {
+        self.sampling = sampling;
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Statistics {
+            sampling: self.sampling,
+            samples: counter(&self.counters.samples),
+            uniform: counter(&self.counters.uniform),
+            contributing: counter(&self.counters.contributing),
+            contribution: counter(&self.counters.contribution),
+            proposed: counter(&self.counters.proposed),
+            accepted: counter(&self.counters.accepted),
+        }
+    }
+
+    /// Trace points on `threads` threads until `samples` were traced in
+    /// total, or chain steps made.
+    pub fn run(&self, threads: usize, samples: u64) {
+        self.run_batches(threads, Some(samples), &AtomicBool::new(false));
+    }
+
+    /// Trace points on `threads` threads until `stop` is set.
+    pub fn run_until(&self, threads: usize, stop: &AtomicBool) {
+        self.run_batches(threads, None, stop);
+    }
+
+    fn run_batches(&self, threads: usize, samples: Option<u64>, stop: &AtomicBool) {
+        thread::scope(|s| {
+            for _ in 0..threads.max(1) {
+                s.spawn(|| {
+                    let mut scratch = Scratch {
+                        orbit: Vec::with_capacity(self.max_iter as usize),
+                        cells: Vec::new(),
+                        current: Vec::new(),
+                        channels: Vec::new(),
+                    };
+                    while !stop.load(Ordering::Relaxed) {
+                        let batch = self.next_batch.fetch_add(1, Ordering::Relaxed);
+                        let count = match samples {
+                            Some(total) => total
+                                .saturating_sub(batch.saturating_mul(BATCH_SIZE))
+                                .min(BATCH_SIZE),
+                            None => BATCH_SIZE,
+                        };
+                        if count == 0 {
+                            break;
+                        }
+                        let mut rng =
+                            StdRng::seed_from_u64(self.seed ^ batch.wrapping_mul(BATCH_SEED_STEP));
+                        let made = match self.sampling {
+                            DensitySampling::Uniform => {
+                                self.uniform_batch(&mut rng, count, &mut scratch);
+                                count
+                            }
+                            DensitySampling::Metropolis => {
+                                self.metropolis_batch(&mut rng, count, &mut scratch)
+                            }
+                        };
+                        self.counters.samples.fetch_add(made, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Trace `count` uniform points.
+    fn uniform_batch(&self, rng: &mut StdRng, count: u64, scratch: &mut Scratch) {
+        for _ in 0..count {
+            let c = uniform_point(rng);
+            let contribution = self.uniform_contribution(c, scratch);
+            if contribution > 0 {
+                self.add(&scratch.cells, 1);
+            }
+        }
+    }
+
+    /// Make `count` steps of a Metropolis chain started from a uniform point
+    /// crossing the view, after [`CHAIN_BURN_IN`] steps adding nothing. The
+    /// proposals are either uniform or a mutation of the current point, both
+    /// symmetric: a proposal is accepted with the ratio of its contribution
+    /// to the current one. Returns the steps made, 0 if no point to start
+    /// from was found.
+    fn metropolis_batch(&self, rng: &mut StdRng, count: u64, scratch: &mut Scratch) -> u64 {
+        let start = (0..CHAIN_START_TRIES).find_map(|_| {
+            let c = uniform_point(rng);
+            let contribution = self.uniform_contribution(c, scratch);
+            (contribution > 0).then_some((c, contribution))
+        });
+        let Some((mut current, mut contribution)) = start else {
+            return 0;
+        };
+        std::mem::swap(&mut scratch.current, &mut scratch.cells);
+
+        let mut accepted = 0;
+        for step in 0..CHAIN_BURN_IN + count {
+            let (proposal, proposed) = if rng.gen_bool(UNIFORM_PROPOSALS) {
+                let c = uniform_point(rng);
+                (c, self.uniform_contribution(c, scratch))
+            } else {
+                let c = self.mutate(current, rng);
+                (c, self.contribution(c, scratch))
+            };
+            if proposed > 0 && rng.gen::<f64>() * (contribution as f64) < proposed as f64 {
+                current = proposal;
+                contribution = proposed;
+                std::mem::swap(&mut scratch.current, &mut scratch.cells);
+                accepted += 1;
+            }
+            if step >= CHAIN_BURN_IN {
+                self.add(&scratch.current, WEIGHT_SCALE / contribution);
+            }
+        }
+        self.counters
+            .proposed
+            .fetch_add(CHAIN_BURN_IN + count, Ordering::Relaxed);
+        self.counters
+            .accepted
+            .fetch_add(accepted, Ordering::Relaxed);
+        count
+    }
+
+    /// Random step from `c`, in any direction, of a length between the two
+    /// mutation extents, evenly spread on a logarithmic scale.
+    fn mutate(&self, c: Complex<f64>, rng: &mut StdRng) -> Complex<f64> {
+        let (min, max) = self.mutation;
+        let length = max * (min / max).powf(rng.gen::<f64>());
+        let (sin, cos) = rng.gen_range(0.0..TAU).sin_cos();
+        c + Complex::new(length * cos, length * sin)
+    }
+
+    /// [`Self::contribution`] of a uniform point, counted in the statistics.
+    fn uniform_contribution(&self, c: Complex<f64>, scratch: &mut Scratch) -> u64 {
+        let contribution = self.contribution(c, scratch);
+        self.counters.uniform.fetch_add(1, Ordering::Relaxed);
+        if contribution > 0 {
+            self.counters.contributing.fetch_add(1, Ordering::Relaxed);
+            self.counters
+                .contribution
+                .fetch_add(contribution, Ordering::Relaxed);
+        }
+        contribution
+    }
+
+    /// Trace `c` and gather the histogram cells its orbit hits in
+    /// `scratch.cells`: the number of orbit points in the view, 0 if the
+    /// orbit is not kept, escapes out of every channel window or `c` is out
+    /// of the sampled disk.
+    fn contribution(&self, c: Complex<f64>, scratch: &mut Scratch) -> u64 {
+        scratch.cells.clear();
+        if c.norm() > SAMPLE_RADIUS * SAMPLE_RADIUS {
+            return 0;
+        }
+        let Some(iter) = self.trace(c, &mut scratch.orbit) else {
+            return 0;
+        };
+        let channels = &mut scratch.channels;
+        channels.clear();
+        channels.extend((0..self.channels.len()).filter(|&k| self.channels[k].contains(iter)));
+        if channels.is_empty() {
+            return 0;
+        }
+
+        let (width, height) = (self.width as f64, self.height as f64);
+        let mut points = 0;
+        for &z in &scratch.orbit {
+            let (x, y) = self.projection.apply(z);
+            if x >= 0.0 && x < width && y >= 0.0 && y < height {
+                let pixel = y as usize * self.width as usize + x as usize;
+                points += 1;
+                scratch
+                    .cells
+                    .extend(channels.iter().map(|&k| pixel * self.channels.len() + k));
+            }
+        }
+        points
+    }
+
+    /// Iterate `c` into `orbit`, and the iteration it escaped at if the
+    /// orbit is one the mode keeps, the maximum for those never escaping.
+    fn trace(&self, c: Complex<f64>, orbit: &mut Vec<Complex<f64>>) -> Option<u32> {
+        orbit.clear();
+        if formula::in_cardioid_or_bulb(c) {
+            // Never escapes, no need to iterate it for the Buddhabrot
+            if self.mode == DensityMode::Buddhabrot {
+                return None;
+            }
+        }
+        let mut z = Complex::new(0.0, 0.0);
+        for n in 1..=self.max_iter {
+            z = z * z + c;
+            if z.norm() > 4.0 {
+                return (self.mode == DensityMode::Buddhabrot).then_some(n);
+            }
+            orbit.push(z);
+        }
+        (self.mode == DensityMode::AntiBuddhabrot).then_some(self.max_iter)
+    }
+
+    /// Add `weight` to each of `cells`, saturating rather than wrapping on
+    /// runs long enough to fill a cell.
+    fn add(&self, cells: &[usize], weight: u64) {
+        for &cell in cells {
+            let count = &self.histogram[cell];
+            let add = |n: u64| Some(n.saturating_add(weight));
+            let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, add);
+        }
+    }
+
+    /// Factor bringing the histogram to hit counts.
+    fn count_scale(&self) -> f64 {
+        match self.sampling {
+            DensitySampling::Uniform => 1.0,
+            DensitySampling::Metropolis => {
+                let statistics = self.statistics();
+                let mean = statistics.contribution as f64 / statistics.uniform.max(1) as f64;
+                mean / WEIGHT_SCALE as f64
+            }
+        }
+    }
+
+    /// Color of every pixel, the sum of its channels, each toned by its
+    /// curve relative to the most visited pixel of the channel. The curves
+    /// give the brightness as displayed, in sRGB.
+    fn colors(&self) -> Vec<LinearColor> {
+        let scale = self.count_scale();
+        let counts: Vec<f64> = self
+            .histogram
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed) as f64 * scale)
+            .collect();
+        let channels = self.channels.len();
+        let max: Vec<f64> = (0..channels)
+            .map(|k| {
+                counts
+                    .iter()
+                    .skip(k)
+                    .step_by(channels)
+                    .copied()
+                    .fold(0.0, f64::max)
+            })
+            .collect();
+
+        counts
+            .chunks_exact(channels)
+            .map(|pixel| {
+                let mut sum = LinearColor::BLACK;
+                for (k, channel) in self.channels.iter().enumerate() {
+                    let t = channel.curve.apply(pixel[k], max[k]);
+                    sum += match channel.color {
+                        Some(full) => full * color::srgb_to_linear(t),
+                        None => self.gradient.sample(t),
+                    };
+                }
+                LinearColor { a: 1.0, ..sum }
+            })
+            .collect()
+    }
+
+    /// Draw the histogram to an RGBA frame.
+    pub fn render(&self, frame: &mut [u8]) {
+        for (i, (pixel, color)) in frame.chunks_exact_mut(4).zip(self.colors()).enumerate() {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            let color = color.to_pixel(self.dither.threshold(x, y));
+            pixel.copy_from_slice(&color.to_rgba());
+        }
+    }
+
+    /// The histogram as 16 bit sRGB channels, pixel after pixel.
+    pub fn render16(&self) -> Vec<u16> {
+        self.colors()
+            .into_iter()
+            .flat_map(|color| {
+                let [r, g, b, _] = color.to_srgb();
+                [r, g, b].map(|c| (c * 65535.0).round() as u16)
+            })
+            .collect()
+    }
+}
+
+/// Point drawn uniformly in the sampled disk.
+fn uniform_point(rng: &mut StdRng) -> Complex<f64> {
+    loop {
+        let c = Complex::new(
+            rng.gen_range(-SAMPLE_RADIUS..SAMPLE_RADIUS),
+            rng.gen_range(-SAMPLE_RADIUS..SAMPLE_RADIUS),
+        );
+        if c.norm() <= SAMPLE_RADIUS * SAMPLE_RADIUS {
+            return c;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buddhabrot(view: ViewPort, size: u32, max_iter: u32, seed: u64) -> Buddhabrot {
+        let gradient = Gradient::evenly_spaced(&[LinearColor::BLACK]);
+        Buddhabrot::new(
+            DensityMode::Buddhabrot,
+            size,
+            size,
+            &view,
+            vec![Channel::new(max_iter)],
+            seed,
+            &gradient,
+        )
+    }
+
+    /// Hit counts of every pixel of a small zoom, `samples` points traced
+    /// with `sampling`.
+    fn counts(sampling: DensitySampling, samples: u64, seed: u64) -> (Vec<f64>, Statistics) {
+        let mut buddhabrot = buddhabrot(ViewPort::new(Complex::new(-0.3, 0.6), 0.5), 16, 100, seed);
+        buddhabrot.set_sampling(sampling);
+        buddhabrot.run(1, samples);
+        let scale = buddhabrot.count_scale();
+        let counts = buddhabrot
+            .histogram
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed) as f64 * scale)
+            .collect();
+        (counts, buddhabrot.statistics())
+    }
+
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() as f64;
+        let mean = |x: &[f64]| x.iter().sum::<f64>() / n;
+        let (mean_a, mean_b) = (mean(a), mean(b));
+        let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+        for (x, y) in a.iter().zip(b) {
+            let (x, y) = (x - mean_a, y - mean_b);
+            ab += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        ab / (aa * bb).sqrt()
+    }
+
+    #[test]
+    fn metropolis_matches_uniform() {
+        let chains = 160;
+        let samples = chains * BATCH_SIZE;
+        let (uniform, _) = counts(DensitySampling::Uniform, samples, 1);
+        let (metropolis, statistics) = counts(DensitySampling::Metropolis, samples, 2);
+        assert_eq!(statistics.samples, samples);
+        assert_eq!(statistics.proposed, samples + chains * CHAIN_BURN_IN);
+
+        let r = correlation(&uniform, &metropolis);
+        assert!(r > 0.9, "correlation {}", r);
+        let total = |counts: &[f64]| counts.iter().sum::<f64>();
+        let ratio = total(&metropolis) / total(&uniform);
+        assert!((ratio - 1.0).abs() < 0.1, "ratio {}", ratio);
+    }
+
+    #[test]
+    fn full_cells_saturate() {
+        let buddhabrot = buddhabrot(ViewPort::new(Complex::new(0.0, 0.0), 2.0), 2, 10, 0);
+        buddhabrot.histogram[1].store(u64::MAX - WEIGHT_SCALE / 2, Ordering::Relaxed);
+        buddhabrot.add(&[1, 1, 2], WEIGHT_SCALE / 3);
+        let counts: Vec<u64> = buddhabrot
+            .histogram
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        assert_eq!(counts, [0, u64::MAX, WEIGHT_SCALE / 3, 0]);
+    }
+
+    #[test]
+    fn chains_without_a_start_make_no_steps() {
+        // Far outside the set: no orbit crosses the view
+        let mut buddhabrot = buddhabrot(ViewPort::new(Complex::new(40.0, 40.0), 1.0), 4, 10, 0);
+        buddhabrot.set_sampling(DensitySampling::Metropolis);
+        buddhabrot.run(1, 10);
+        let statistics = buddhabrot.statistics();
+        assert_eq!(statistics.samples, 0);
+        assert_eq!(statistics.uniform, CHAIN_START_TRIES as u64);
+    }
+}
@@ -0,0 +1,525 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::deflate::{self, ZlibEncoder};
+use crate::mandelbrot::{Iterations, Sample};
+use crate::png::crc32;
+
+const MAGIC: &[u8; 8] = b"MBCKPT04";
+/// Sample flag: the orbit converged, its fraction follows.
+const CONVERGED: u8 = 1;
+/// Sample flag: the orbit reached a root, its index follows.
+const HAS_ROOT: u8 = 2;
+/// Longest time between two flushes of the checkpoint to the disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+/// Payload length and checksum of a tile record.
+const RECORD_HEADER: u64 = 12;
+/// Larger parameter blocks are not ours.
+const MAX_PARAMS: u64 = 1 << 20;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Format(String),
+    /// The checkpoint was saved by a render with other parameters.
+    Mismatch {
+        key: String,
+        saved: String,
+        current: String,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::Format(message) => write!(f, "{}", message),
+            CheckpointError::Mismatch {
+                key,
+                saved,
+                current,
+            } => write!(
+                f,
+                "the checkpoint was saved with {}={}, not {}={}",
+                key, saved, key, current
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+/// Progress of a tiled render saved to disk: the render parameters, then the
+/// iteration counts of each completed tile, compressed, in render order.
+///
+/// A record is appended as each tile completes and the file is flushed to
+/// the disk every `SYNC_INTERVAL`. Records are checksummed, one cut short by
+/// a crash is dropped on resume and its tile computed again.
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    /// Tiles in the file.
+    tiles: u64,
+    /// Tiles read back since the resume.
+    read: u64,
+    last_sync: Instant,
+}
+
+impl Checkpoint {
+    /// Start the checkpoint of a render described by `params`, `key=value`
+    /// lines. An existing file is never overwritten, it may hold hours of
+    /// work.
+    pub fn create(path: &Path, params: &str) -> Result<Self, CheckpointError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => CheckpointError::Format(
+                    "already exists, continue it with --resume or remove it".into(),
+                ),
+                _ => err.into(),
+            })?;
+        file.write_all(MAGIC)?;
+        file.write_all(&(params.len() as u64).to_le_bytes())?;
+        file.write_all(params.as_bytes())?;
+        file.sync_data()?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            tiles: 0,
+            read: 0,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Open the checkpoint of an interrupted render, which must have been
+    /// started with the same `params`.
+    pub fn resume(path: &Path, params: &str) -> Result<Self, CheckpointError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let not_checkpoint =
+            || CheckpointError::Format(format!("{} is not a checkpoint", path.display()));
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic).map_err(|_| not_checkpoint())?;
+        let length = read_u64(&mut file).map_err(|_| not_checkpoint())?;
+        if magic != *MAGIC || length > MAX_PARAMS {
+            return Err(not_checkpoint());
+        }
+        let mut saved = vec![0; length as usize];
+        file.read_exact(&mut saved).map_err(|_| not_checkpoint())?;
+        let saved = String::from_utf8(saved).map_err(|_| not_checkpoint())?;
+        compare_params(&saved, params)?;
+
+        // Count the complete records, dropping a torn one at the end
+        let start = file.stream_position()?;
+        let size = file.metadata()?.len();
+        let mut end = start;
+        let mut tiles = 0;
+        while let Some(payload) = read_record(&mut file, size - end)? {
+            end += RECORD_HEADER + payload.len() as u64;
+            tiles += 1;
+        }
+        if end < size {
+            log::warn!(
+                "Dropping {} bytes of incomplete tile at the end of the checkpoint",
+                size - end
+            );
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(start))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            tiles,
+            read: 0,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Number of tiles saved.
+    pub fn tiles(&self) -> u64 {
+        self.tiles
+    }
+
+    /// Iterations of the next saved tile, `None` once they have all been read
+    /// back and the remaining tiles must be computed.
+    pub fn next_saved(&mut self) -> io::Result<Option<Iterations>> {
+        if self.read == self.tiles {
+            return Ok(None);
+        }
+        let payload = read_record(&mut self.file, u64::MAX)?
+            .ok_or_else(|| invalid_data("checkpoint changed while reading it"))?;
+        let (index, iterations) = decode(&payload)?;
+        if index != self.read {
+            return Err(invalid_data(format!(
+                "checkpoint has tile {} in place of tile {}",
+                index, self.read
+            )));
+        }
+        self.read += 1;
+        Ok(Some(iterations))
+    }
+
+    /// Save the iterations of the next tile, after the saved ones have all
+    /// been read.
+    pub fn append(&mut self, iterations: &Iterations) -> io::Result<()> {
+        debug_assert_eq!(self.read, self.tiles, "saved tiles left to read");
+        let payload = encode(self.tiles, iterations);
+        let mut record = Vec::with_capacity(RECORD_HEADER as usize + payload.len());
+        record.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        record.extend_from_slice(&crc32(&[&payload]).to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.file.write_all(&record)?;
+        self.tiles += 1;
+        self.read += 1;
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.file.sync_data()?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// The render is complete, the checkpoint is removed.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Payload of the record at the current position, `None` at the end of the
+/// file or for an incomplete or corrupt record, `remaining` bytes from the
+/// end of the file.
+fn read_record(file: &mut File, remaining: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; RECORD_HEADER as usize];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let length = u64::from_le_bytes(header[..8].try_into().expect("8 bytes"));
+    let crc = u32::from_le_bytes(header[8..].try_into().expect("4 bytes"));
+    if length > remaining.saturating_sub(RECORD_HEADER) {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0; length as usize];
+    match file.read_exact(&mut payload) {
+        Ok(()) if crc32(&[&payload]) == crc => Ok(Some(payload)),
+        Ok(()) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Tile index, then the samples, the refined pixels and the refined
+/// samples, each as their count and values, zlib compressed. Samples are
+/// their iteration count and flags, followed by their root with `HAS_ROOT`
+/// and the bits of their convergence fraction with `CONVERGED`.
+fn encode(index: u64, iterations: &Iterations) -> Vec<u8> {
+    let samples = |bytes: &mut Vec<u8>, samples: &[Sample]| {
+        bytes.extend_from_slice(&(samples.len() as u64).to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.iter.to_le_bytes());
+            let flags = if sample.converged { CONVERGED } else { 0 }
+                | if sample.root.is_some() { HAS_ROOT } else { 0 };
+            bytes.push(flags);
+            if let Some(root) = sample.root {
+                bytes.extend_from_slice(&root.to_le_bytes());
+            }
+            if sample.converged {
+                bytes.extend_from_slice(&sample.fraction.to_bits().to_le_bytes());
+            }
+        }
+    };
+
+    let mut bytes = Vec::with_capacity(32 + iterations.samples.len() * 5);
+    bytes.extend_from_slice(&index.to_le_bytes());
+    samples(&mut bytes, &iterations.samples);
+    bytes.extend_from_slice(&(iterations.refined_pixels.len() as u64).to_le_bytes());
+    for pixel in &iterations.refined_pixels {
+        bytes.extend_from_slice(&pixel.to_le_bytes());
+    }
+    samples(&mut bytes, &iterations.refined_samples);
+
+    let mut encoder = ZlibEncoder::new();
+    encoder.write(&bytes);
+    let mut payload = encoder.take_output();
+    payload.extend(encoder.finish());
+    payload
+}
+
+fn decode(payload: &[u8]) -> io::Result<(u64, Iterations)> {
+    let bytes = deflate::zlib_decompress(payload)
+        .map_err(|err| invalid_data(format!("checkpoint tile is corrupt: {}", err)))?;
+    let mut bytes = bytes.as_slice();
+
+    let index = read_u64(&mut bytes)?;
+    let samples = read_samples(&mut bytes)?;
+    let count = read_u64(&mut bytes)?;
+    if count > bytes.len() as u64 / 4 {
+        return Err(invalid_data("checkpoint tile is truncated"));
+    }
+    let refined_pixels = (0..count)
+        .map(|_| read_u32(&mut bytes))
+        .collect::<io::Result<_>>()?;
+    let iterations = Iterations {
+        samples,
+        refined_pixels,
+        refined_samples: read_samples(&mut bytes)?,
+    };
+    Ok((index, iterations))
+}
+
+fn read_samples(bytes: &mut &[u8]) -> io::Result<Vec<Sample>> {
+    let count = read_u64(bytes)?;
+    // Each sample takes at least 5 bytes
+    if count > bytes.len() as u64 / 5 {
+        return Err(invalid_data("checkpoint tile is truncated"));
+    }
+    let mut samples = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut sample = Sample {
+            iter: read_u32(bytes)?,
+            ..Sample::default()
+        };
+        let mut flags = [0];
+        bytes.read_exact(&mut flags)?;
+        if flags[0] & HAS_ROOT != 0 {
+            let mut root = [0; 2];
+            bytes.read_exact(&mut root)?;
+            sample.root = Some(u16::from_le_bytes(root));
+        }
+        if flags[0] & CONVERGED != 0 {
+            sample.converged = true;
+            sample.fraction = f32::from_bits(read_u32(bytes)?);
+        }
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// The first parameter of `current` whose value is not the `saved` one.
+fn compare_params(saved: &str, current: &str) -> Result<(), CheckpointError> {
+    let split = |line: &str| {
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+        (key.to_owned(), value.to_owned())
+    };
+    let saved: Vec<(String, String)> = saved.lines().map(split).collect();
+    for (key, value) in current.lines().map(split) {
+        let saved = saved
+            .iter()
+            .find(|(saved_key, _)| *saved_key == key)
+            .map_or("<unset>", |(_, value)| value.as_str());
+        if saved != value {
+            return Err(CheckpointError::Mismatch {
+                key,
+                saved: saved.to_owned(),
+                current: value,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path in the temporary directory, removed when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "mandelbrot-checkpoint-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn tile(seed: u32) -> Iterations {
+        let sample = |i: u32| Sample {
+            iter: seed * 1000 + i,
+            converged: i.is_multiple_of(3),
+            fraction: if i.is_multiple_of(3) {
+                i as f32 / 100.0
+            } else {
+                0.0
+            },
+            root: i.is_multiple_of(2).then_some(i as u16),
+        };
+        Iterations {
+            samples: (0..64).map(sample).collect(),
+            refined_pixels: vec![3, 7, seed],
+            refined_samples: (0..12).map(|i| sample(i + 100)).collect(),
+        }
+    }
+
+    /// A record as `append` writes it.
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let mut record = (payload.len() as u64).to_le_bytes().to_vec();
+        record.extend_from_slice(&crc32(&[payload]).to_le_bytes());
+        record.extend_from_slice(payload);
+        record
+    }
+
+    /// First record read back from a file holding `bytes`.
+    fn read_back(name: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        let path = TempPath::new(name);
+        fs::write(&path.0, bytes).unwrap();
+        let mut file = File::open(&path.0).unwrap();
+        read_record(&mut file, bytes.len() as u64).unwrap()
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let iterations = tile(5);
+        let (index, decoded) = decode(&encode(42, &iterations)).unwrap();
+        assert_eq!(index, 42);
+        assert_eq!(decoded, iterations);
+
+        let empty = Iterations::default();
+        assert_eq!(decode(&encode(0, &empty)).unwrap(), (0, empty));
+    }
+
+    #[test]
+    fn samples_without_root_or_fraction_take_five_bytes() {
+        let iterations = Iterations {
+            samples: vec![Sample::from(7); 10],
+            ..Iterations::default()
+        };
+        let bytes = deflate::zlib_decompress(&encode(0, &iterations)).unwrap();
+        assert_eq!(bytes.len(), 8 + (8 + 10 * 5) + 8 + 8);
+    }
+
+    #[test]
+    fn decode_rejects_corrupt_payloads() {
+        let payload = encode(1, &tile(1));
+        assert!(decode(&payload[..payload.len() - 1]).is_err());
+
+        let mut bytes = deflate::zlib_decompress(&payload).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        let mut encoder = ZlibEncoder::new();
+        encoder.write(&bytes);
+        let mut truncated = encoder.take_output();
+        truncated.extend(encoder.finish());
+        assert!(decode(&truncated).is_err());
+    }
+
+    #[test]
+    fn read_record_drops_torn_records() {
+        let payload = encode(0, &tile(0));
+        let record = record(&payload);
+        assert_eq!(read_back("whole", &record), Some(payload));
+
+        // Cut in the header, in the payload, or a length past the end
+        assert_eq!(read_back("header", &record[..6]), None);
+        assert_eq!(read_back("payload", &record[..record.len() - 1]), None);
+        let mut long = record.clone();
+        long[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(read_back("length", &long), None);
+
+        let mut flipped = record.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(read_back("crc", &flipped), None);
+    }
+
+    #[test]
+    fn compare_params_reports_the_first_mismatch() {
+        let saved = "size=100x100\nmax-iter=500\n";
+        assert!(compare_params(saved, "size=100x100\nmax-iter=500\n").is_ok());
+        match compare_params(saved, "size=100x100\nmax-iter=800\ndither=None\n") {
+            Err(CheckpointError::Mismatch {
+                key,
+                saved,
+                current,
+            }) => assert_eq!((&*key, &*saved, &*current), ("max-iter", "500", "800")),
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+        // A parameter added since the checkpoint was saved
+        match compare_params(saved, "size=100x100\nmax-iter=500\ndither=None\n") {
+            Err(CheckpointError::Mismatch { key, saved, .. }) => {
+                assert_eq!((&*key, &*saved), ("dither", "<unset>"))
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resume_after_a_crash_reads_the_complete_tiles() {
+        let path = TempPath::new("resume");
+        let params = "size=64x64\nmax-iter=100\n";
+
+        let mut checkpoint = Checkpoint::create(&path.0, params).unwrap();
+        checkpoint.append(&tile(0)).unwrap();
+        checkpoint.append(&tile(1)).unwrap();
+        // Killed while writing the third tile
+        let torn = record(&encode(2, &tile(2)));
+        checkpoint.file.write_all(&torn[..torn.len() / 2]).unwrap();
+        drop(checkpoint);
+
+        assert!(matches!(
+            Checkpoint::create(&path.0, params),
+            Err(CheckpointError::Format(_))
+        ));
+        assert!(matches!(
+            Checkpoint::resume(&path.0, "size=64x64\nmax-iter=200\n"),
+            Err(CheckpointError::Mismatch { .. })
+        ));
+
+        let mut checkpoint = Checkpoint::resume(&path.0, params).unwrap();
+        assert_eq!(checkpoint.tiles(), 2);
+        assert_eq!(checkpoint.next_saved().unwrap(), Some(tile(0)));
+        assert_eq!(checkpoint.next_saved().unwrap(), Some(tile(1)));
+        assert_eq!(checkpoint.next_saved().unwrap(), None);
+        checkpoint.append(&tile(2)).unwrap();
+        drop(checkpoint);
+
+        let mut checkpoint = Checkpoint::resume(&path.0, params).unwrap();
+        assert_eq!(checkpoint.tiles(), 3);
+        for i in 0..3 {
+            assert_eq!(checkpoint.next_saved().unwrap(), Some(tile(i)));
+        }
+        checkpoint.finish().unwrap();
+        assert!(!path.0.exists());
+    }
+}
@@ -0,0 +1,521 @@
+use std::path::PathBuf;
+
+use crate::buddhabrot::{Channel, DensityMode, DensitySampling};
+use crate::color::Dither;
+use crate::complex::Complex;
+use crate::deep::DoubleDouble;
+use crate::export::FrameFormat;
+use crate::expression::Program;
+use crate::formula::{BailoutMode, FormulaKind};
+use crate::gif::GifPalette;
+use crate::gradient::{ColorSpace, Interpolation, WrapMode};
+use crate::mandelbrot::PixelColor;
+use crate::newton::Polynomial;
+use crate::plane::PlaneKind;
+use crate::sampling::{SamplePattern, MAX_AA};
+
+pub const USAGE: &str = "\
+Usage: mandelbrot [OPTIONS]
+
+Options:
+    --size <WIDTHxHEIGHT>       Size of the window or rendered frames (defaults to 800x600)
+    --center <RE,IM>            Center of the view (defaults to -0.5,0), about 32 digits are
+                                kept for --exp-map
+    --scale <SIZE>              Extent of the shorter side of the view (defaults to 3)
+    --rotation <DEGREES>        Rotation of the view
+    --transform <A,B,C,D>       Linear transform (row-major 2×2 matrix) applied to the view
+    --max-iter <N>              Maximum iterations (defaults to 1024)
+    --formula <NAME>            Fractal: mandelbrot, multibrot, burning-ship, tricorn, celtic,
+                                buffalo, perpendicular, newton, nova, cos, phoenix, magnet1
+                                or magnet2
+    --power <D>                 Exponent of the multibrot z^D + c, above 1 (defaults to 3)
+    --polynomial <P>            Polynomial of newton and nova, e.g. 'z^5 + (1-2i)z - 3'
+                                (defaults to z^3 - 1)
+    --relaxation <RE,IM>        Factor of the newton and nova steps (defaults to 1)
+    --expression <TEXT>         Formula written as text, in place of --formula, e.g.
+                                'z0 = pixel; z = z^3 + c*sin(z); bailout |z| > 16'
+    --param <NAME=RE,IM>        Value of a parameter of the expression (repeatable)
+    --plugin <PATH>             Formula from a shared library, in place of --formula (see
+                                plugins/mandelbrot_plugin.h)
+    --plugin-params <TEXT>      Text given to the plugin when it starts
+    --phoenix-p <RE,IM>         Factor of the previous z in the phoenix (defaults to -0.5)
+    --bailout <MODE>            Stop orbits on escape, convergence or both, in place of the
+                                formula's own (newton and nova always converge)
+    --plane <KIND>              Non-linear plane transform: identity, inversion, log-polar or
+                                sphere
+    --plane-mu <RE,IM>          Pole of the inversion, center of the log-polar map
+    --plane-shift <RE,IM>       Added after the inversion or sphere projection
+    --plane-tilt <DEGREES>      Tilt of the sphere, 180 looks at infinity
+    --palette <FILE>            Load the palette from a .map, .ggr, .ugr or .grad file
+    --cosine-palette <TERMS>    Generate a cosine palette from 12 comma separated terms
+    --random-palette <SEED>     Generate a random palette from a seed
+    --palette-offset <OFFSET>   Shift the palette, in palette lengths
+    --palette-period <ITERS>    Iterations per palette cycle (defaults to the max iterations)
+    --palette-wrap <MODE>       Palette wrapping: repeat, mirror or clamp
+    --interpolation <MODE>      Gradient interpolation: linear, smoothstep or cubic
+    --color-space <SPACE>       Gradient color space: srgb, linear, oklab, oklch or hsv
+    --dither <MODE>             Dithering when encoding colors: none, ordered or blue-noise
+    --aa <N>                    Supersample each pixel with N×N samples, N up to 16
+    --aa-pattern <PATTERN>      Sample pattern: grid, rotated or jittered
+    --aa-seed <SEED>            Seed of the jittered sample pattern
+    --aa-adaptive <THRESHOLD>   Only supersample pixels whose color differs from a neighbour
+                                by more than THRESHOLD (sRGB, 0 to 1)
+    --aa-iter-threshold <N>     Also supersample pixels whose iteration count differs from a
+                                neighbour by more than N
+    --aa-debug                  Highlight the pixels refined by adaptive anti-aliasing
+    --exp-map <FILE>            Render the exponential map of a zoom on --center to a PPM strip
+                                and exit, the outer ring radius is --scale
+    --exp-map-width <N>         Width of the strip, in pixels (defaults to 1024)
+    --exp-map-zoom <FACTOR>     Zoom factor covered by the strip (defaults to 1e6)
+    --animate <KEYFRAMES>       Render the animation described by a keyframe file and exit
+    --frames <N>                Number of frames (defaults to the duration times the fps)
+    --fps <N>                   Frames per second (defaults to 30)
+    --format <FORMAT>           Frame format: png, ppm, y4m or gif
+    --output <PATH>             Directory of the frames (defaults to frames), or file of the Y4M
+                                stream (defaults to -, the standard output), GIF (defaults to
+                                animation.gif) or density image (defaults to density.png)
+    --gif-palette <MODE>        GIF colors: per-frame or global
+    --gif-loops <N>             Times the GIF plays, 0 for forever (default)
+    --poster <FILE>             Render a --size image tile by tile to a PNG, or a BigTIFF for
+                                .tif and .tiff files, and exit
+    --tile-size <N>             Side of the poster tiles and height of the exp-map bands, a
+                                multiple of 16 (defaults to 256)
+    --checkpoint <FILE>         Save the progress of the poster or exp-map to FILE, removed once
+                                done
+    --resume                    Continue the render saved in --checkpoint
+    --script <FILE>             Run a Rhai script driving renders, the other options set up
+                                its universes
+    --density <MODE>            Render the orbit density of buddhabrot or anti-buddhabrot,
+                                traced until the window is closed
+    --samples <N>               Trace N random points, write the density to --output and exit
+    --density-seed <SEED>       Seed of the traced points (defaults to 0)
+    --density-sampling <MODE>   Draw the points uniformly or by metropolis chains favoring the
+                                view, for zooms
+    --channel <SPEC>            Density channel 'MIN-MAX [CURVE] [#RRGGBB]' counting the orbits
+                                escaping between MIN and MAX iterations, toned by linear, sqrt
+                                (default), log or gamma=G, in the given color (repeatable, red,
+                                green then blue by default)
+    --bit-depth <N>             Bits per channel of the density image: 8 (default) or 16
+    -h, --help                  Print this help";
+
+#[derive(Debug)]
+pub struct Args {
+    pub size: Option<(u32, u32)>,
+    pub center: Option<Complex<DoubleDouble>>,
+    pub scale: Option<f64>,
+    pub rotation: f64,
+    pub transform: Option<[[f64; 2]; 2]>,
+    pub max_iter: Option<u32>,
+    pub formula: FormulaKind,
+    pub power: f64,
+    pub polynomial: Polynomial,
+    pub relaxation: Complex<f64>,
+    pub phoenix: Complex<f64>,
+    pub expression: Option<Program>,
+    pub params: Vec<(String, Complex<f64>)>,
+    pub plugin: Option<PathBuf>,
+    pub plugin_params: String,
+    pub bailout: Option<BailoutMode>,
+    pub plane: PlaneKind,
+    pub plane_mu: Complex<f64>,
+    pub plane_shift: Complex<f64>,
+    pub plane_tilt: f64,
+    pub palette: Option<PathBuf>,
+    pub cosine_palette: Option<String>,
+    pub random_palette: Option<u64>,
+    pub palette_offset: f32,
+    pub palette_period: Option<f32>,
+    pub palette_wrap: WrapMode,
+    pub interpolation: Interpolation,
+    pub color_space: ColorSpace,
+    pub dither: Dither,
+    pub aa: u32,
+    pub aa_pattern: SamplePattern,
+    pub aa_seed: u64,
+    pub aa_adaptive: Option<f32>,
+    pub aa_iter_threshold: Option<u32>,
+    pub aa_debug: bool,
+    pub exp_map: Option<PathBuf>,
+    pub exp_map_width: u32,
+    pub exp_map_zoom: f64,
+    pub animate: Option<PathBuf>,
+    pub frames: Option<u32>,
+    pub fps: u32,
+    pub format: FrameFormat,
+    pub output: Option<PathBuf>,
+    pub gif_palette: GifPalette,
+    pub gif_loops: u32,
+    pub poster: Option<PathBuf>,
+    pub script: Option<PathBuf>,
+    pub tile_size: u32,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub density: Option<DensityMode>,
+    pub samples: Option<u64>,
+    pub density_seed: u64,
+    pub density_sampling: DensitySampling,
+    pub channels: Vec<Channel>,
+    pub bit_depth: u8,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            size: None,
+            center: None,
+            scale: None,
+            rotation: 0.0,
+            transform: None,
+            max_iter: None,
+            formula: FormulaKind::default(),
+            power: 3.0,
+            polynomial: Polynomial::default(),
+            relaxation: Complex::new(1.0, 0.0),
+            phoenix: Complex::new(-0.5, 0.0),
+            expression: None,
+            params: Vec::new(),
+            plugin: None,
+            plugin_params: String::new(),
+            bailout: None,
+            plane: PlaneKind::default(),
+            plane_mu: Complex::new(0.0, 0.0),
+            plane_shift: Complex::new(0.0, 0.0),
+            plane_tilt: 0.0,
+            palette: None,
+            cosine_palette: None,
+            random_palette: None,
+            palette_offset: 0.0,
+            palette_period: None,
+            palette_wrap: WrapMode::default(),
+            interpolation: Interpolation::default(),
+            color_space: ColorSpace::default(),
+            dither: Dither::default(),
+            aa: 1,
+            aa_pattern: SamplePattern::default(),
+            aa_seed: 0,
+            aa_adaptive: None,
+            aa_iter_threshold: None,
+            aa_debug: false,
+            exp_map: None,
+            exp_map_width: 1024,
+            exp_map_zoom: 1e6,
+            animate: None,
+            frames: None,
+            fps: 30,
+            format: FrameFormat::default(),
+            output: None,
+            gif_palette: GifPalette::default(),
+            gif_loops: 0,
+            poster: None,
+            script: None,
+            tile_size: 256,
+            checkpoint: None,
+            resume: false,
+            density: None,
+            samples: None,
+            density_seed: 0,
+            density_sampling: DensitySampling::default(),
+            channels: Vec::new(),
+            bit_depth: 8,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => parsed.size = Some(parse_size(&value(&mut args, &arg)?)?),
+                "--center" => {
+                    parsed.center = Some(parse_precise_complex(&value(&mut args, &arg)?)?);
+                }
+                "--scale" => parsed.scale = Some(parse_value(&mut args, &arg)?),
+                "--rotation" => parsed.rotation = parse_value(&mut args, &arg)?,
+                "--transform" => {
+                    let values = parse_list(&value(&mut args, &arg)?)?;
+                    let [a, b, c, d] = values[..] else {
+                        return Err("--transform needs 4 comma separated values".into());
+                    };
+                    parsed.transform = Some([[a, b], [c, d]]);
+                }
+                "--max-iter" => parsed.max_iter = Some(parse_value(&mut args, &arg)?),
+                "--formula" => parsed.formula = value(&mut args, &arg)?.parse()?,
+                "--power" => parsed.power = parse_value(&mut args, &arg)?,
+                "--polynomial" => parsed.polynomial = value(&mut args, &arg)?.parse()?,
+                "--relaxation" => parsed.relaxation = parse_complex(&value(&mut args, &arg)?)?,
+                "--expression" => parsed.expression = Some(value(&mut args, &arg)?.parse()?),
+                "--param" => {
+                    let param = value(&mut args, &arg)?;
+                    let Some((name, value)) = param.split_once('=') else {
+                        return Err(format!("--param needs NAME=RE,IM, not '{}'", param));
+                    };
+                    parsed
+                        .params
+                        .push((name.trim().to_owned(), parse_complex(value)?));
+                }
+                "--plugin" => parsed.plugin = Some(value(&mut args, &arg)?.into()),
+                "--plugin-params" => parsed.plugin_params = value(&mut args, &arg)?,
+                "--phoenix-p" => parsed.phoenix = parse_complex(&value(&mut args, &arg)?)?,
+                "--bailout" => parsed.bailout = Some(value(&mut args, &arg)?.parse()?),
+                "--plane" => parsed.plane = value(&mut args, &arg)?.parse()?,
+                "--plane-mu" => parsed.plane_mu = parse_complex(&value(&mut args, &arg)?)?,
+                "--plane-shift" => parsed.plane_shift = parse_complex(&value(&mut args, &arg)?)?,
+                "--plane-tilt" => parsed.plane_tilt = parse_value(&mut args, &arg)?,
+                "--palette" => parsed.palette = Some(value(&mut args, &arg)?.into()),
+                "--cosine-palette" => parsed.cosine_palette = Some(value(&mut args, &arg)?),
+                "--random-palette" => {
+                    parsed.random_palette = Some(parse_value(&mut args, &arg)?);
+                }
+                "--palette-offset" => parsed.palette_offset = parse_value(&mut args, &arg)?,
+                "--palette-period" => parsed.palette_period = Some(parse_value(&mut args, &arg)?),
+                "--palette-wrap" => parsed.palette_wrap = value(&mut args, &arg)?.parse()?,
+                "--interpolation" => parsed.interpolation = value(&mut args, &arg)?.parse()?,
+                "--color-space" => parsed.color_space = value(&mut args, &arg)?.parse()?,
+                "--dither" => parsed.dither = value(&mut args, &arg)?.parse()?,
+                "--aa" => parsed.aa = parse_value(&mut args, &arg)?,
+                "--aa-pattern" => parsed.aa_pattern = value(&mut args, &arg)?.parse()?,
+                "--aa-seed" => parsed.aa_seed = parse_value(&mut args, &arg)?,
+                "--aa-adaptive" => parsed.aa_adaptive = Some(parse_value(&mut args, &arg)?),
+                "--aa-iter-threshold" => {
+                    parsed.aa_iter_threshold = Some(parse_value(&mut args, &arg)?);
+                }
+                "--aa-debug" => parsed.aa_debug = true,
+                "--exp-map" => parsed.exp_map = Some(value(&mut args, &arg)?.into()),
+                "--exp-map-width" => parsed.exp_map_width = parse_value(&mut args, &arg)?,
+                "--exp-map-zoom" => parsed.exp_map_zoom = parse_value(&mut args, &arg)?,
+                "--animate" => parsed.animate = Some(value(&mut args, &arg)?.into()),
+                "--frames" => parsed.frames = Some(parse_value(&mut args, &arg)?),
+                "--fps" => parsed.fps = parse_value(&mut args, &arg)?,
+                "--format" => parsed.format = value(&mut args, &arg)?.parse()?,
+                "--output" => parsed.output = Some(value(&mut args, &arg)?.into()),
+                "--gif-palette" => parsed.gif_palette = value(&mut args, &arg)?.parse()?,
+                "--gif-loops" => parsed.gif_loops = parse_value(&mut args, &arg)?,
+                "--poster" => parsed.poster = Some(value(&mut args, &arg)?.into()),
+                "--script" => parsed.script = Some(value(&mut args, &arg)?.into()),
+                "--tile-size" => parsed.tile_size = parse_value(&mut args, &arg)?,
+                "--checkpoint" => parsed.checkpoint = Some(value(&mut args, &arg)?.into()),
+                "--resume" => parsed.resume = true,
+                "--density" => parsed.density = Some(value(&mut args, &arg)?.parse()?),
+                "--samples" => parsed.samples = Some(parse_value(&mut args, &arg)?),
+                "--density-seed" => parsed.density_seed = parse_value(&mut args, &arg)?,
+                "--density-sampling" => {
+                    parsed.density_sampling = value(&mut args, &arg)?.parse()?
+                }
+                "--channel" => parsed.channels.push(value(&mut args, &arg)?.parse()?),
+                "--bit-depth" => parsed.bit_depth = parse_value(&mut args, &arg)?,
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+
+        let palettes = [
+            parsed.palette.is_some(),
+            parsed.cosine_palette.is_some(),
+            parsed.random_palette.is_some(),
+        ];
+        if palettes.iter().filter(|&&p| p).count() > 1 {
+            return Err(
+                "--palette, --cosine-palette and --random-palette are mutually exclusive".into(),
+            );
+        }
+
+        if !(1..=MAX_AA).contains(&parsed.aa) {
+            return Err(format!("--aa takes 1 to {} samples per side", MAX_AA));
+        }
+
+        if parsed.aa_iter_threshold.is_some() && parsed.aa_adaptive.is_none() {
+            return Err("--aa-iter-threshold needs --aa-adaptive".into());
+        }
+
+        if parsed.max_iter == Some(0) {
+            return Err("--max-iter needs at least one iteration".into());
+        }
+
+        if !(parsed.power > 1.0 && parsed.power.is_finite()) {
+            return Err("--power needs to be above 1".into());
+        }
+
+        if parsed.bailout.is_some()
+            && matches!(parsed.formula, FormulaKind::Newton | FormulaKind::Nova)
+        {
+            return Err("--bailout does not apply to newton and nova".into());
+        }
+
+        if parsed.plugin.is_some() && parsed.expression.is_some() {
+            return Err("--plugin and --expression cannot be used together".into());
+        }
+
+        if !parsed.plugin_params.is_empty() && parsed.plugin.is_none() {
+            return Err("--plugin-params needs --plugin".into());
+        }
+
+        if let Some(program) = &mut parsed.expression {
+            for (name, value) in &parsed.params {
+                program.set_param(name, *value)?;
+            }
+            if let Some(mode) = parsed.bailout {
+                program.set_bailout(program.bailout().with_mode(mode));
+            }
+        } else if !parsed.params.is_empty() {
+            return Err("--param needs --expression".into());
+        }
+
+        if parsed.exp_map.is_some()
+            && (parsed.formula != FormulaKind::Mandelbrot
+                || parsed.expression.is_some()
+                || parsed.plugin.is_some())
+        {
+            return Err("--exp-map only supports the mandelbrot formula".into());
+        }
+
+        if parsed.exp_map_width == 0 || parsed.exp_map_zoom <= 1.0 {
+            return Err("--exp-map needs a width and a zoom factor above 1".into());
+        }
+
+        if parsed.fps == 0 || parsed.frames == Some(0) {
+            return Err("--fps and --frames need to be at least 1".into());
+        }
+
+        if parsed.tile_size == 0 || !parsed.tile_size.is_multiple_of(16) {
+            return Err("--tile-size needs a multiple of 16".into());
+        }
+
+        if parsed.checkpoint.is_some() && parsed.poster.is_none() && parsed.exp_map.is_none() {
+            return Err("--checkpoint needs --poster or --exp-map".into());
+        }
+
+        if parsed.resume && parsed.checkpoint.is_none() {
+            return Err("--resume needs --checkpoint".into());
+        }
+
+        if parsed.density.is_some() {
+            if parsed.formula != FormulaKind::Mandelbrot
+                || parsed.expression.is_some()
+                || parsed.plugin.is_some()
+            {
+                return Err("--density only supports the mandelbrot formula".into());
+            }
+            if parsed.plane != PlaneKind::Identity {
+                return Err("--density does not support --plane".into());
+            }
+        } else if parsed.samples.is_some() || !parsed.channels.is_empty() {
+            return Err("--samples and --channel need --density".into());
+        }
+
+        if parsed.samples == Some(0) {
+            return Err("--samples needs at least one point".into());
+        }
+
+        if let Some(max_iter) = parsed.max_iter {
+            if parsed
+                .channels
+                .iter()
+                .any(|channel| channel.max_iter > max_iter)
+            {
+                return Err("--channel windows need to end within --max-iter".into());
+            }
+        }
+
+        // A single channel runs through the palette, several are added in
+        // red, green and blue
+        if parsed.channels.len() > 1 {
+            let primaries = [PixelColor::RED, PixelColor::GREEN, PixelColor::BLUE];
+            for (k, channel) in parsed.channels.iter_mut().enumerate() {
+                if channel.color.is_none() {
+                    let primary = primaries
+                        .get(k)
+                        .ok_or("--channel needs a color past the third channel")?;
+                    channel.color = Some((*primary).into());
+                }
+            }
+        }
+
+        match parsed.bit_depth {
+            8 => {}
+            16 if parsed.samples.is_some() => {}
+            16 => return Err("--bit-depth 16 needs --samples".into()),
+            _ => return Err("--bit-depth needs 8 or 16".into()),
+        }
+
+        let outputs = [
+            parsed.exp_map.is_some(),
+            parsed.animate.is_some(),
+            parsed.poster.is_some(),
+            parsed.script.is_some(),
+            parsed.density.is_some(),
+        ];
+        if outputs.iter().filter(|&&o| o).count() > 1 {
+            return Err(
+                "--exp-map, --animate, --poster, --script and --density are mutually exclusive"
+                    .into(),
+            );
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("missing value for '{}'", name))
+}
+
+fn parse_value<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    name: &str,
+) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = value(args, name)?;
+    value
+        .parse()
+        .map_err(|err| format!("invalid value '{}' for '{}': {}", value, name, err))
+}
+
+/// Parse a comma separated list of numbers.
+fn parse_list(value: &str) -> Result<Vec<f64>, String> {
+    value
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|err| format!("invalid number '{}': {}", v, err))
+        })
+        .collect()
+}
+
+/// Parse a complex number written `re,im`.
+fn parse_complex(value: &str) -> Result<Complex<f64>, String> {
+    match parse_list(value)?[..] {
+        [re, im] => Ok(Complex::new(re, im)),
+        _ => Err(format!(
+            "invalid complex number '{}', expected 're,im'",
+            value
+        )),
+    }
+}
+
+/// Parse a complex number written `re,im` without going through `f64`.
+fn parse_precise_complex(value: &str) -> Result<Complex<DoubleDouble>, String> {
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [re, im] => Ok(Complex::new(re.parse()?, im.parse()?)),
+        _ => Err(format!(
+            "invalid complex number '{}', expected 're,im'",
+            value
+        )),
+    }
+}
+
+/// Parse an image size written `WIDTHxHEIGHT`.
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    value
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .ok_or_else(|| format!("invalid size '{}', expected WIDTHxHEIGHT", value))
+}
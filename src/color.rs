@@ -0,0 +1,276 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::mandelbrot::PixelColor;
+
+/// Color in linear light, the channels are not clamped so they can be summed
+/// and averaged freely. Only converted to sRGB `PixelColor` for display.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinearColor {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl LinearColor {
+    pub const BLACK: LinearColor = LinearColor::new(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// From sRGB encoded channels in `[0, 1]`, alpha is kept as is.
+    pub fn from_srgb([r, g, b, a]: [f32; 4]) -> Self {
+        Self::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    /// To sRGB encoded channels, clamped to `[0, 1]`.
+    pub fn to_srgb(self) -> [f32; 4] {
+        [
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+            self.a.clamp(0.0, 1.0),
+        ]
+    }
+
+    /// Encode to 8 bit sRGB. `threshold` in `[0, 1)` decides where values are
+    /// rounded, 0.5 being plain rounding and anything else dithering.
+    pub fn to_pixel(self, threshold: f32) -> PixelColor {
+        let [r, g, b, a] = self
+            .to_srgb()
+            .map(|c| (c * 255.0 + threshold).floor().clamp(0.0, 255.0) as u8);
+        PixelColor::new(r, g, b, a)
+    }
+}
+
+impl From<PixelColor> for LinearColor {
+    fn from(color: PixelColor) -> Self {
+        Self::from_srgb(color.to_f32())
+    }
+}
+
+impl std::ops::Add for LinearColor {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(
+            self.r + rhs.r,
+            self.g + rhs.g,
+            self.b + rhs.b,
+            self.a + rhs.a,
+        )
+    }
+}
+
+impl std::ops::AddAssign for LinearColor {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::ops::Mul<f32> for LinearColor {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.r * rhs, self.g * rhs, self.b * rhs, self.a * rhs)
+    }
+}
+
+impl std::ops::Div<f32> for LinearColor {
+    type Output = Self;
+    fn div(self, rhs: f32) -> Self {
+        Self::new(self.r / rhs, self.g / rhs, self.b / rhs, self.a / rhs)
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Dithering applied when encoding to 8 bit, to hide banding in smooth
+/// gradients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    #[default]
+    None,
+    /// 8×8 Bayer matrix.
+    Ordered,
+    /// Tiled void-and-cluster blue noise.
+    BlueNoise,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "ordered" => Ok(Self::Ordered),
+            "blue-noise" => Ok(Self::BlueNoise),
+            _ => Err(format!(
+                "unknown dither '{}' (expected none, ordered or blue-noise)",
+                s
+            )),
+        }
+    }
+}
+
+impl Dither {
+    /// Rounding threshold in `[0, 1)` for the pixel at `(x, y)`.
+    pub fn threshold(self, x: u32, y: u32) -> f32 {
+        match self {
+            Dither::None => 0.5,
+            Dither::Ordered => {
+                let rank = BAYER_8X8[(y % 8) as usize][(x % 8) as usize];
+                (rank as f32 + 0.5) / 64.0
+            }
+            Dither::BlueNoise => {
+                let size = BLUE_NOISE_SIZE as u32;
+                let idx = (y % size) * size + x % size;
+                blue_noise()[idx as usize]
+            }
+        }
+    }
+}
+
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+const BLUE_NOISE_SIZE: usize = 32;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+const BLUE_NOISE_SEED: u64 = 0x5eed;
+
+/// Blue noise thresholds, generated once with the void-and-cluster method.
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| {
+        let n = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE);
+        ranks
+            .into_iter()
+            .map(|rank| (rank as f32 + 0.5) / n as f32)
+            .collect()
+    })
+}
+
+/// Rank every cell of a `size`×`size` torus so that the first `k` ranks are
+/// always evenly spread, whatever `k`.
+fn void_and_cluster(size: usize) -> Vec<usize> {
+    let n = size * size;
+
+    // Gaussian energy contributed by a set cell at each toroidal offset
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+        })
+        .collect();
+
+    struct Pattern<'a> {
+        size: usize,
+        kernel: &'a [f32],
+        set: Vec<bool>,
+        energy: Vec<f32>,
+    }
+
+    impl Pattern<'_> {
+        fn toggle(&mut self, p: usize, on: bool) {
+            self.set[p] = on;
+            let sign = if on { 1.0 } else { -1.0 };
+            let (px, py) = (p % self.size, p / self.size);
+            for (q, energy) in self.energy.iter_mut().enumerate() {
+                let dx = (q % self.size + self.size - px) % self.size;
+                let dy = (q / self.size + self.size - py) % self.size;
+                *energy += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+
+        fn tightest_cluster(&self) -> usize {
+            (0..self.set.len())
+                .filter(|&p| self.set[p])
+                .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .unwrap_or(0)
+        }
+
+        fn largest_void(&self) -> usize {
+            (0..self.set.len())
+                .filter(|&p| !self.set[p])
+                .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .unwrap_or(0)
+        }
+    }
+
+    let mut pattern = Pattern {
+        size,
+        kernel: &kernel,
+        set: vec![false; n],
+        energy: vec![0.0; n],
+    };
+
+    // Random initial pattern, relaxed until no cluster can move to a void
+    let mut rng = StdRng::seed_from_u64(BLUE_NOISE_SEED);
+    let initial = n / 10;
+    while pattern.set.iter().filter(|&&s| s).count() < initial {
+        let p = rng.gen_range(0..n);
+        if !pattern.set[p] {
+            pattern.toggle(p, true);
+        }
+    }
+    for _ in 0..n {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster, false);
+        let void = pattern.largest_void();
+        if void == cluster {
+            pattern.toggle(cluster, true);
+            break;
+        }
+        pattern.toggle(void, true);
+    }
+
+    let mut ranks = vec![0; n];
+    let initial_set = pattern.set.clone();
+    let initial_energy = pattern.energy.clone();
+
+    // Ranks below the initial count: remove the tightest clusters first
+    for rank in (0..initial).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster, false);
+        ranks[cluster] = rank;
+    }
+
+    // Remaining ranks: fill the largest voids
+    pattern.set = initial_set;
+    pattern.energy = initial_energy;
+    for rank in initial..n {
+        let void = pattern.largest_void();
+        pattern.toggle(void, true);
+        ranks[void] = rank;
+    }
+
+    ranks
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Complex<T> {
     pub re: T,
     pub im: T,
@@ -56,7 +56,88 @@ impl Complex<f64> {
     }
 
     pub fn cos(&self) -> Self {
-        (Self::I * *self).exp() + (-Self::I * *self).exp() / 2.0
+        ((Self::I * *self).exp() + (-Self::I * *self).exp()) / 2.0
+    }
+
+    pub fn sin(&self) -> Self {
+        Self::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn tan(&self) -> Self {
+        self.sin() / self.cos()
+    }
+
+    pub fn sinh(&self) -> Self {
+        Self::new(
+            self.re.sinh() * self.im.cos(),
+            self.re.cosh() * self.im.sin(),
+        )
+    }
+
+    pub fn cosh(&self) -> Self {
+        Self::new(
+            self.re.cosh() * self.im.cos(),
+            self.re.sinh() * self.im.sin(),
+        )
+    }
+
+    pub fn tanh(&self) -> Self {
+        self.sinh() / self.cosh()
+    }
+
+    /// Natural logarithm, principal branch.
+    pub fn ln(&self) -> Self {
+        Self::new(0.5 * self.norm().ln(), self.im.atan2(self.re))
+    }
+
+    /// Principal square root.
+    pub fn sqrt(&self) -> Self {
+        self.powf(0.5)
+    }
+
+    /// Complex power `exp(w ln z)`, principal branch. `0^0` is 1, and `0^w`
+    /// is infinity for a negative real part of `w`, like dividing by zero,
+    /// and 0 otherwise.
+    pub fn pow(&self, w: Self) -> Self {
+        if self.norm() == 0.0 {
+            return if w.norm() == 0.0 {
+                Self::new(1.0, 0.0)
+            } else if w.re < 0.0 {
+                Self::new(f64::INFINITY, 0.0)
+            } else {
+                Self::new(0.0, 0.0)
+            };
+        }
+        (w * self.ln()).exp()
+    }
+
+    /// Integer power, by repeated squaring.
+    pub fn powi(&self, n: i32) -> Self {
+        let mut base = if n < 0 {
+            Self::new(1.0, 0.0) / *self
+        } else {
+            *self
+        };
+        let mut result = Self::new(1.0, 0.0);
+        let mut n = n.unsigned_abs();
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        result
+    }
+
+    /// Real power, through the polar form (principal branch).
+    pub fn powf(&self, exponent: f64) -> Self {
+        let r = self.norm().sqrt().powf(exponent);
+        let (sin, cos) = (self.im.atan2(self.re) * exponent).sin_cos();
+        Self::new(r * cos, r * sin)
     }
 }
 
@@ -128,17 +209,15 @@ pub mod op {
         }
     }
 
-    impl<
-            T: Mul<T, Output = T>
-                + Add<T, Output = T>
-                + Sub<T, Output = T>
-                + Div<T, Output = T>
-                + Copy,
-        > Div for Complex<T>
-    {
-        type Output = Complex<T>;
-        fn div(self, rhs: Complex<T>) -> Complex<T> {
+    impl Div for Complex<f64> {
+        type Output = Complex<f64>;
+        /// Dividing by zero gives infinity, the pole of the Riemann sphere,
+        /// rather than NaN: orbits hitting it escape.
+        fn div(self, rhs: Complex<f64>) -> Complex<f64> {
             let norm = rhs.norm();
+            if norm == 0.0 {
+                return Complex::new(f64::INFINITY, 0.0);
+            }
             Complex {
                 re: (self.re * rhs.re + self.im * rhs.im) / norm,
                 im: (self.im * rhs.re - self.re * rhs.im) / norm,
@@ -156,3 +235,36 @@ pub mod op {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pow_of_zero() {
+        let zero = Complex::new(0.0, 0.0);
+        let pow = |re: f64, im: f64| zero.pow(Complex::new(re, im));
+        assert_eq!(pow(0.0, 0.0), Complex::new(1.0, 0.0));
+        assert_eq!(pow(2.5, 1.0), zero);
+        assert_eq!(pow(-1.5, 0.0), Complex::new(f64::INFINITY, 0.0));
+        assert_eq!(pow(-1.5, 0.0), Complex::new(1.0, 0.0) / zero);
+    }
+
+    #[test]
+    fn pow_matches_powi_and_powf() {
+        let z = Complex::new(0.3, -1.2);
+        for n in [-3, -1, 0, 2, 5] {
+            let (pow, powi) = (z.pow(Complex::new(n as f64, 0.0)), z.powi(n));
+            assert!(
+                (pow - powi).norm() < 1e-24,
+                "{}: {:?} != {:?}",
+                n,
+                pow,
+                powi
+            );
+        }
+        let (pow, powf) = (z.pow(Complex::new(0.5, 0.0)), z.powf(0.5));
+        assert!((pow - powf).norm() < 1e-24);
+        assert!((z.sqrt() * z.sqrt() - z).norm() < 1e-24);
+    }
+}
@@ -0,0 +1,188 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+use crate::complex::Complex;
+
+/// Unevaluated sum of two `f64`, about 32 significant digits. Enough to hold
+/// the center of zooms well past the `f64` limit of about 1e-13.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl DoubleDouble {
+    pub const fn new(value: f64) -> Self {
+        Self { hi: value, lo: 0.0 }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+}
+
+/// `a + b` exactly, as a rounded sum and its error.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+/// [`two_sum`] when `|a| >= |b|`.
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+fn renormalize((hi, lo): (f64, f64)) -> DoubleDouble {
+    let (hi, lo) = quick_two_sum(hi, lo);
+    DoubleDouble { hi, lo }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        let (s, e) = two_sum(self.hi, rhs.hi);
+        let (t, f) = two_sum(self.lo, rhs.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        renormalize((s, e + f))
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let p = self.hi * rhs.hi;
+        let e = self.hi.mul_add(rhs.hi, -p);
+        renormalize((p, e + (self.hi * rhs.lo + self.lo * rhs.hi)))
+    }
+}
+
+impl Div for DoubleDouble {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        // Long division, each quotient digit corrects the remainder
+        let q1 = self.hi / rhs.hi;
+        let r = self - rhs * Self::new(q1);
+        let q2 = r.hi / rhs.hi;
+        let r = r - rhs * Self::new(q2);
+        let q3 = r.hi / rhs.hi;
+        renormalize(quick_two_sum(q1, q2)) + Self::new(q3)
+    }
+}
+
+impl FromStr for DoubleDouble {
+    type Err = String;
+
+    /// Decimal number with an optional exponent, e.g. `-1.25e-3`. Parsed
+    /// digit by digit so no precision is lost through `f64`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid number '{}'", s);
+        let text = s.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i32>().map_err(|_| invalid())?),
+            None => (text, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(invalid());
+        }
+
+        let ten = Self::new(10.0);
+        let mut value = Self::default();
+        for c in int.chars().chain(frac.chars()) {
+            let digit = c.to_digit(10).ok_or_else(invalid)?;
+            value = value * ten + Self::new(digit as f64);
+        }
+
+        let exponent = exponent - frac.len() as i32;
+        let mut scale = Self::new(1.0);
+        for _ in 0..exponent.unsigned_abs() {
+            scale = scale * ten;
+        }
+        let value = if exponent < 0 {
+            value / scale
+        } else {
+            value * scale
+        };
+
+        Ok(if negative { -value } else { value })
+    }
+}
+
+impl Complex<DoubleDouble> {
+    pub fn to_f64(self) -> Complex<f64> {
+        Complex::new(self.re.to_f64(), self.im.to_f64())
+    }
+}
+
+/// Orbit of the center of a deep zoom, computed once in extended precision.
+/// Every pixel is then iterated as a small `f64` offset from it
+/// (perturbation), which keeps full precision however deep the zoom.
+pub struct ReferenceOrbit {
+    orbit: Vec<Complex<f64>>,
+}
+
+impl ReferenceOrbit {
+    pub fn new(center: Complex<DoubleDouble>, max_iter: u32) -> Self {
+        let mut z = Complex::new(DoubleDouble::default(), DoubleDouble::default());
+        let mut orbit = vec![z.to_f64()];
+        for _ in 0..max_iter {
+            z = z * z + center;
+            let point = z.to_f64();
+            orbit.push(point);
+            if point.norm() > 4.0 {
+                break;
+            }
+        }
+        log::debug!("Reference orbit: {} iterations", orbit.len() - 1);
+
+        Self { orbit }
+    }
+
+    /// Iterations before `center + dc` escapes, `max_iter` if it does not.
+    pub fn iterate(&self, dc: Complex<f64>, max_iter: u32) -> u32 {
+        let mut dz = Complex::new(0.0, 0.0);
+        let mut m = 0;
+        for n in 0..max_iter {
+            // z² + c around the reference: dz' = 2 Z dz + dz² + dc
+            dz = self.orbit[m] * dz * 2.0 + dz * dz + dc;
+            m += 1;
+
+            let z = self.orbit[m] + dz;
+            if z.norm() > 4.0 {
+                return n + 1;
+            }
+
+            // Rebase on the start of the orbit when the offset gets larger
+            // than the orbit itself (it would lose precision) or when the
+            // reference escaped before the pixel
+            if z.norm() < dz.norm() || m == self.orbit.len() - 1 {
+                dz = z;
+                m = 0;
+            }
+        }
+        max_iter
+    }
+}
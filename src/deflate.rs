@@ -0,0 +1,409 @@
+//! Streaming zlib (deflate) compression with LZ77 matching and the fixed
+//! Huffman codes, enough for PNG without pulling a dependency.
+
+/// Input compressed per deflate block.
+const BLOCK_SIZE: usize = 1 << 18;
+/// Largest distance a match can reach back.
+const WINDOW: usize = 1 << 15;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: usize = 48;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const NONE: u32 = u32::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Bits packed least significant first, as deflate wants them.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.bits(0, 8 - self.count);
+        }
+    }
+
+    fn literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, length: usize, distance: usize) {
+        let l = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        self.literal(257 + l as u32);
+        self.bits(
+            (length - LENGTH_BASE[l] as usize) as u32,
+            LENGTH_EXTRA[l] as u32,
+        );
+
+        let d = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.code(d as u32, 5);
+        self.bits(
+            (distance - DISTANCE_BASE[d] as usize) as u32,
+            DISTANCE_EXTRA[d] as u32,
+        );
+    }
+}
+
+pub struct ZlibEncoder {
+    /// Up to `WINDOW` bytes of history followed by the pending input.
+    data: Vec<u8>,
+    pending: usize,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    bits: BitWriter,
+    adler: (u32, u32),
+}
+
+impl ZlibEncoder {
+    pub fn new() -> Self {
+        let mut bits = BitWriter::default();
+        // 32K window, default compression
+        bits.bits(0x78, 8);
+        bits.bits(0x9c, 8);
+
+        Self {
+            data: Vec::new(),
+            pending: 0,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: Vec::new(),
+            bits,
+            adler: (1, 0),
+        }
+    }
+
+    pub fn write(&mut self, input: &[u8]) {
+        self.adler = adler32(self.adler, input);
+
+        self.data.extend_from_slice(input);
+        if self.data.len() - self.pending >= BLOCK_SIZE {
+            self.compress_block(false);
+        }
+    }
+
+    /// Compressed bytes produced so far, removed from the encoder.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bits.out)
+    }
+
+    /// Compress what is left and return the last bytes of the stream.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.compress_block(true);
+        self.bits.align();
+        let (a, b) = self.adler;
+        self.bits
+            .out
+            .extend_from_slice(&((b << 16) | a).to_be_bytes());
+        self.bits.out
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = &self.data[pos..pos + MIN_MATCH];
+        (((d[0] as usize) << 10) ^ ((d[1] as usize) << 5) ^ d[2] as usize) & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let h = self.hash(pos);
+            self.prev[pos] = self.head[h];
+            self.head[h] = pos as u32;
+        }
+    }
+
+    /// Longest earlier match for the bytes at `pos`, as `(length, distance)`.
+    fn longest_match(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.data.len() {
+            return None;
+        }
+        let max = MAX_MATCH.min(self.data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || pos - candidate as usize > WINDOW {
+                break;
+            }
+            let start = candidate as usize;
+            let length = self.data[start..start + max]
+                .iter()
+                .zip(&self.data[pos..pos + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, pos - start);
+                if length == max {
+                    break;
+                }
+            }
+            candidate = self.prev[start];
+        }
+        (best.0 >= MIN_MATCH).then_some(best)
+    }
+
+    fn compress_block(&mut self, last: bool) {
+        self.bits.bits(last as u32, 1);
+        self.bits.bits(1, 2);
+
+        // Hash chains over the history, then greedy matching of the input
+        self.head.fill(NONE);
+        self.prev = vec![NONE; self.data.len()];
+        for pos in 0..self.pending {
+            self.insert(pos);
+        }
+        let mut pos = self.pending;
+        while pos < self.data.len() {
+            match self.longest_match(pos) {
+                Some((length, distance)) => {
+                    self.bits.copy(length, distance);
+                    for p in pos..pos + length {
+                        self.insert(p);
+                    }
+                    pos += length;
+                }
+                None => {
+                    self.bits.literal(self.data[pos] as u32);
+                    self.insert(pos);
+                    pos += 1;
+                }
+            }
+        }
+        self.bits.literal(256);
+
+        let keep = self.data.len().min(WINDOW);
+        self.data.drain(..self.data.len() - keep);
+        self.pending = self.data.len();
+    }
+}
+
+/// Adler-32 sums `(a, b)` updated with `input`.
+fn adler32((mut a, mut b): (u32, u32), input: &[u8]) -> (u32, u32) {
+    for chunk in input.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (a, b)
+}
+
+/// Bits read least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or("zlib stream is truncated")?;
+        let bit = (byte >> (self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    /// Huffman codes are stored most significant bit first.
+    fn code(&mut self, len: u32) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..len {
+            code = (code << 1) | self.bit()?;
+        }
+        Ok(code)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    /// Symbol of the fixed literal/length code.
+    fn literal(&mut self) -> Result<u32, String> {
+        let code = self.code(7)?;
+        if code < 24 {
+            return Ok(256 + code);
+        }
+        let code = (code << 1) | self.bit()?;
+        match code {
+            0x30..=0xbf => Ok(code - 0x30),
+            0xc0..=0xc7 => Ok(280 + code - 0xc0),
+            _ => Ok(144 + ((code << 1) | self.bit()?) - 0x190),
+        }
+    }
+}
+
+/// Decompress a zlib stream made of stored or fixed Huffman blocks, all
+/// that `ZlibEncoder` writes.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6
+        || data[0] & 0x0f != 8
+        || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+    {
+        return Err("not a zlib stream".into());
+    }
+    let mut bits = BitReader {
+        data: &data[..data.len() - 4],
+        pos: 16,
+    };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let start = bits.pos / 8;
+                let header = bits
+                    .data
+                    .get(start..start + 4)
+                    .ok_or("zlib stream is truncated")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("stored block length is corrupt".into());
+                }
+                let block = bits
+                    .data
+                    .get(start + 4..start + 4 + len as usize)
+                    .ok_or("zlib stream is truncated")?;
+                out.extend_from_slice(block);
+                bits.pos = (start + 4 + len as usize) * 8;
+            }
+            1 => loop {
+                let symbol = bits.literal()?;
+                match symbol {
+                    0..=255 => out.push(symbol as u8),
+                    256 => break,
+                    257..=285 => {
+                        let l = (symbol - 257) as usize;
+                        let length =
+                            LENGTH_BASE[l] as usize + bits.bits(LENGTH_EXTRA[l] as u32)? as usize;
+                        let d = bits.code(5)? as usize;
+                        if d >= DISTANCE_BASE.len() {
+                            return Err("invalid distance code".into());
+                        }
+                        let distance = DISTANCE_BASE[d] as usize
+                            + bits.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+                        if distance > out.len() {
+                            return Err("distance reaches before the start".into());
+                        }
+                        let start = out.len() - distance;
+                        for i in 0..length {
+                            out.push(out[start + i]);
+                        }
+                    }
+                    _ => return Err("invalid length code".into()),
+                }
+            },
+            _ => return Err("only stored and fixed Huffman blocks are supported".into()),
+        }
+        if last {
+            break;
+        }
+    }
+
+    let (a, b) = adler32((1, 0), &out);
+    let adler = u32::from_be_bytes(data[data.len() - 4..].try_into().expect("4 bytes"));
+    if adler != (b << 16) | a {
+        return Err("zlib checksum mismatch".into());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(input: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new();
+        let mut out = Vec::new();
+        for chunk in input.chunks(100_000) {
+            encoder.write(chunk);
+            out.extend(encoder.take_output());
+        }
+        out.extend(encoder.finish());
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = b"the quick brown fox jumps over the lazy dog. ".repeat(500);
+        // Several blocks, with matches reaching across them
+        let mut noise = Vec::new();
+        let mut x = 1u32;
+        for _ in 0..600_000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            noise.push((x >> 24) as u8 & 0x0f);
+        }
+        for input in [&b""[..], b"a", &text, &noise] {
+            let compressed = compress(input);
+            assert_eq!(zlib_decompress(&compressed).unwrap(), input);
+        }
+        assert!(compress(&text).len() < text.len() / 20);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        // "hello" in a single stored block
+        let mut stream = vec![0x78, 0x01, 0x01, 5, 0, !5, !0];
+        stream.extend_from_slice(b"hello");
+        let (a, b) = adler32((1, 0), b"hello");
+        stream.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        assert_eq!(zlib_decompress(&stream).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn corrupt_streams() {
+        let compressed = compress(b"some text, some text, some text");
+        let mut bad_checksum = compressed.clone();
+        *bad_checksum.last_mut().unwrap() ^= 1;
+        assert!(zlib_decompress(&bad_checksum).is_err());
+        assert!(zlib_decompress(&compressed[..compressed.len() - 6]).is_err());
+        assert!(zlib_decompress(b"not zlib").is_err());
+    }
+}
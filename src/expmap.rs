@@ -0,0 +1,135 @@
+use std::f64::consts::{FRAC_PI_2, TAU};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::checkpoint::Checkpoint;
+use crate::complex::Complex;
+use crate::deep::DoubleDouble;
+use crate::export;
+use crate::mandelbrot::{MandelbrotUniverse, ViewPort};
+use crate::plane::PlaneTransform;
+use crate::poster::Progress;
+
+/// Exponential map of a zoom: a strip `width` pixels wide where each row is
+/// a ring around the zoom center, the radius shrinking by `e^delta` per row
+/// with `delta = 2π / width` so pixels stay square. Row 0 is the outer ring
+/// of radius `radius`. Any frame of the zoom can be rebuilt from the strip.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpMap {
+    width: u32,
+    height: u32,
+    radius: f64,
+}
+
+impl ExpMap {
+    /// Strip from `radius` down to `radius / zoom`.
+    pub fn new(width: u32, radius: f64, zoom: f64) -> Self {
+        let delta = TAU / width as f64;
+        let height = (zoom.ln() / delta).ceil().max(1.0) as u32;
+        Self {
+            width,
+            height,
+            radius,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Log radius change between two rows.
+    pub fn delta(&self) -> f64 {
+        TAU / self.width as f64
+    }
+
+    /// View of a log-polar plane around the origin giving the strip layout:
+    /// the columns sweep the angle, the rows the log radius. The formula
+    /// gets the offset from the zoom center, so the center itself can be
+    /// held at any precision by the formula.
+    pub fn view(&self) -> ViewPort {
+        let delta = self.delta();
+        let center = Complex::new(
+            self.radius.ln() + (0.5 - self.height as f64 / 2.0) * delta,
+            std::f64::consts::PI,
+        );
+        let mut view = ViewPort::new(center, self.width.min(self.height) as f64 * delta);
+        // Window x → angle, window y → decreasing log radius
+        view.set_rotation(FRAC_PI_2);
+        view.set_plane(PlaneTransform::LogPolar {
+            center: Complex::new(0.0, 0.0),
+        });
+        view
+    }
+
+    /// Compute the strip with `universe` in bands of `band` rows and write
+    /// it as a PPM, the settings are recorded in the header comments. The
+    /// bands are saved to `checkpoint` as they complete, or read back from
+    /// it, and the checkpoint is removed once the strip is written.
+    pub fn render(
+        &self,
+        universe: &mut MandelbrotUniverse,
+        band: u32,
+        mut checkpoint: Option<Checkpoint>,
+        center: Complex<DoubleDouble>,
+        path: &Path,
+    ) -> io::Result<()> {
+        let bands = self.height.div_ceil(band) as u64;
+        if let Some(checkpoint) = checkpoint.as_ref().filter(|c| c.tiles() > 0) {
+            log::info!(
+                "Resuming from {} bands out of {}",
+                checkpoint.tiles(),
+                bands
+            );
+        }
+
+        let view = self.view();
+        let row_len = self.width as usize * 4;
+        let mut rgba = vec![0; row_len * self.height as usize];
+        let mut progress = Progress::new(self.width as u64 * self.height as u64);
+        for y in (0..self.height).step_by(band as usize) {
+            let height = band.min(self.height - y);
+            let pixels = self.width as u64 * height as u64;
+            universe.set_size(self.width, height);
+
+            let saved = match &mut checkpoint {
+                Some(checkpoint) => checkpoint.next_saved()?,
+                None => None,
+            };
+            if let Some(iterations) = saved {
+                universe.set_iterations(iterations).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("checkpoint band does not fit the strip: {}", err),
+                    )
+                })?;
+                progress.resume(pixels);
+            } else {
+                universe.set_view(view.tile(0, y, self.width, height, self.width, self.height));
+                universe.compute();
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint.append(&universe.iterations())?;
+                }
+                progress.advance(pixels);
+            }
+
+            let start = y as usize * row_len;
+            universe.render(&mut rgba[start..start + height as usize * row_len]);
+        }
+
+        let comments = [
+            format!(
+                "exp-map center {:?},{:?}",
+                center.re.to_f64(),
+                center.im.to_f64()
+            ),
+            format!("exp-map radius {:e} delta {:e}", self.radius, self.delta()),
+        ];
+        let mut writer = BufWriter::new(File::create(path)?);
+        export::write_ppm(&mut writer, self.width, self.height, &rgba, &comments)?;
+        match checkpoint {
+            Some(checkpoint) => checkpoint.finish(),
+            None => Ok(()),
+        }
+    }
+}
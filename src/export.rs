@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::png::PngWriter;
+
+/// Output format of rendered frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameFormat {
+    #[default]
+    Png,
+    Ppm,
+    /// Single YUV4MPEG2 stream holding every frame.
+    Y4m,
+    /// Animated GIF.
+    Gif,
+}
+
+impl FromStr for FrameFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(Self::Png),
+            "ppm" => Ok(Self::Ppm),
+            "y4m" => Ok(Self::Y4m),
+            "gif" => Ok(Self::Gif),
+            _ => Err(format!(
+                "unknown frame format '{}' (expected png, ppm, y4m or gif)",
+                s
+            )),
+        }
+    }
+}
+
+pub fn rgba_to_rgb(rgba: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    rgba.chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+}
+
+/// Write 8 bit RGBA pixels as a binary PPM, alpha is dropped. `comments`
+/// are added to the header, one per line.
+pub fn write_ppm(
+    writer: &mut impl Write,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    comments: &[String],
+) -> io::Result<()> {
+    writeln!(writer, "P6")?;
+    for comment in comments {
+        writeln!(writer, "# {}", comment)?;
+    }
+    writeln!(writer, "{} {}", width, height)?;
+    writeln!(writer, "255")?;
+
+    let rgb: Vec<u8> = rgba_to_rgb(rgba).collect();
+    writer.write_all(&rgb)
+}
+
+/// Write 8 bit RGBA pixels as a PNG, alpha is dropped.
+pub fn write_png(writer: impl Write, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut png = PngWriter::new(writer, width, height)?;
+    for row in rgba.chunks_exact(width as usize * 4) {
+        let rgb: Vec<u8> = rgba_to_rgb(row).collect();
+        png.write_row(&rgb)?;
+    }
+    png.finish()?;
+    Ok(())
+}
+
+/// Write 8 bit RGBA pixels to `path`, a PNG or, with a `.ppm` extension, a
+/// PPM.
+pub fn write_image(path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if is_ppm(path) {
+        write_ppm(&mut writer, width, height, rgba, &[])?;
+        writer.flush()
+    } else {
+        write_png(writer, width, height, rgba)
+    }
+}
+
+/// Write 16 bit RGB pixels to `path`, a PNG or, with a `.ppm` extension, a
+/// PPM.
+pub fn write_image16(path: &Path, width: u32, height: u32, rgb: &[u16]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let bytes: Vec<u8> = rgb.iter().flat_map(|c| c.to_be_bytes()).collect();
+    if is_ppm(path) {
+        write!(writer, "P6\n{} {}\n65535\n", width, height)?;
+        writer.write_all(&bytes)?;
+        writer.flush()
+    } else {
+        let mut png = PngWriter::with_depth(writer, width, height, 16)?;
+        for row in bytes.chunks_exact(width as usize * 6) {
+            png.write_row(row)?;
+        }
+        png.finish()?;
+        Ok(())
+    }
+}
+
+fn is_ppm(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ppm"))
+}
+
+/// YUV4MPEG2 video stream, as read by ffmpeg and most encoders. Frames are
+/// stored as 4:4:4 limited range BT.601 YCbCr.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED",
+            width, height, fps
+        )?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    /// Append a frame of 8 bit RGBA pixels.
+    pub fn write_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        let pixels = self.width as usize * self.height as usize;
+        let mut planes = vec![0; pixels * 3];
+        for (i, pixel) in rgba.chunks_exact(4).take(pixels).enumerate() {
+            let [r, g, b] = [0, 1, 2].map(|c| pixel[c] as f32 / 255.0);
+            let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+            let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+            let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+            planes[i] = y.round() as u8;
+            planes[pixels + i] = cb.round() as u8;
+            planes[2 * pixels + i] = cr.round() as u8;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,960 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::complex::Complex;
+use crate::formula::{iterate, Bailout};
+use crate::mandelbrot::Sample;
+
+/// Slots of the variables, the parameters follow.
+const Z: usize = 0;
+const C: usize = 1;
+const PIXEL: usize = 2;
+const SLOTS: usize = 16;
+const MAX_PARAMS: usize = SLOTS - 3;
+/// Deepest evaluation stack of a compiled expression.
+const MAX_STACK: usize = 32;
+/// Integer powers up to this are computed by multiplication.
+const MAX_INTEGER_POWER: f64 = 64.0;
+/// Deepest nesting of parentheses, calls, signs and powers the parser
+/// recurses into.
+const MAX_NESTING: usize = 100;
+/// Most operands in a source, which bounds the depth of the syntax trees
+/// the compiler recurses into.
+const MAX_OPERANDS: usize = 1000;
+
+/// Formula written as text, compiled to bytecode.
+///
+/// Statements are separated by `;` or line breaks:
+///
+/// - `z = <expr>`: the step, required.
+/// - `z0 = <expr>`: the starting `z`, 0 by default.
+/// - `c = <expr>`: the constant of the formula, the pixel by default. Setting
+///   it to a number and `z0 = pixel` gives a Julia set.
+/// - `param <name> = <number>`: a parameter, which `--param` or the viewer
+///   can change.
+/// - `bailout |z| > <R>`, `bailout |dz| < <E>`, or both joined by `or`: the
+///   orbit escapes past `R`, or converges once a step is shorter than `E`.
+///   `|z| > 2` by default.
+///
+/// Expressions combine numbers (`2.5`, `1e-3`, `0.5i`, `i`), the variables
+/// `z`, `c`, `pixel` and the parameters with `+`, `-`, `*`, `/`, `^` and the
+/// functions in [`Function`]. A number followed by a variable or a
+/// parenthesis is multiplied with it, as in `3z^2`.
+#[derive(Clone)]
+pub struct Program {
+    source: String,
+    /// Names and values of the parameters, in their slot order.
+    params: Vec<(String, Complex<f64>)>,
+    constants: Vec<Complex<f64>>,
+    step: Vec<Op>,
+    start: Option<Vec<Op>>,
+    c: Option<Vec<Op>>,
+    bailout: Bailout,
+}
+
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("source", &self.source)
+            .field("params", &self.params)
+            .field("bailout", &self.bailout)
+            .finish()
+    }
+}
+
+/// Bytecode of a stack machine. Operations pop their operands and push their
+/// result.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// Push a constant, by index.
+    Const(u16),
+    /// Push a variable or parameter, by slot.
+    Load(u8),
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Powi(i32),
+    Call(Function),
+}
+
+/// Functions of one complex argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    /// Natural logarithm.
+    Log,
+    Sqrt,
+    Conj,
+    /// Modulus, as a real number.
+    Abs,
+    Re,
+    Im,
+}
+
+impl Function {
+    const NAMES: [(&'static str, Function); 13] = [
+        ("sin", Function::Sin),
+        ("cos", Function::Cos),
+        ("tan", Function::Tan),
+        ("sinh", Function::Sinh),
+        ("cosh", Function::Cosh),
+        ("tanh", Function::Tanh),
+        ("exp", Function::Exp),
+        ("log", Function::Log),
+        ("sqrt", Function::Sqrt),
+        ("conj", Function::Conj),
+        ("abs", Function::Abs),
+        ("re", Function::Re),
+        ("im", Function::Im),
+    ];
+
+    fn from_name(name: &str) -> Option<Function> {
+        Self::NAMES
+            .iter()
+            .find(|(function, _)| *function == name)
+            .map(|&(_, function)| function)
+    }
+
+    fn apply(self, z: Complex<f64>) -> Complex<f64> {
+        match self {
+            Function::Sin => z.sin(),
+            Function::Cos => z.cos(),
+            Function::Tan => z.tan(),
+            Function::Sinh => z.sinh(),
+            Function::Cosh => z.cosh(),
+            Function::Tanh => z.tanh(),
+            Function::Exp => z.exp(),
+            Function::Log => z.ln(),
+            Function::Sqrt => z.sqrt(),
+            Function::Conj => z.conj(),
+            Function::Abs => Complex::new(z.norm().sqrt(), 0.0),
+            Function::Re => Complex::new(z.re, 0.0),
+            Function::Im => Complex::new(z.im, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+/// Syntax tree of an expression.
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(Complex<f64>),
+    /// Variable or parameter, checked once every statement is known.
+    Variable {
+        name: String,
+        position: usize,
+    },
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Step(Expr),
+    Start(Expr),
+    Constant(Expr),
+    Param { name: String, value: Expr },
+    Bailout(Bailout),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Imaginary(f64),
+    Ident(String),
+    Symbol(char),
+    /// `;` or a line break.
+    End,
+}
+
+/// Error at a character of the source.
+struct SyntaxError {
+    message: String,
+    position: usize,
+}
+
+type ParseResult<T> = Result<T, SyntaxError>;
+
+fn syntax_error<T>(position: usize, message: impl Into<String>) -> ParseResult<T> {
+    Err(SyntaxError {
+        message: message.into(),
+        position,
+    })
+}
+
+/// Tokens and the character each starts at.
+fn tokenize(source: &str) -> ParseResult<Vec<(Token, usize)>> {
+    let text: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let start = pos;
+        let ch = text[pos];
+        if ch == '\n' || ch == ';' {
+            tokens.push((Token::End, start));
+            pos += 1;
+        } else if ch.is_whitespace() {
+            pos += 1;
+        } else if ch.is_ascii_digit() || ch == '.' {
+            while pos < text.len()
+                && (text[pos].is_ascii_digit()
+                    || text[pos] == '.'
+                    || (matches!(text[pos], 'e' | 'E')
+                        && text.get(pos + 1).is_some_and(|&next| {
+                            next.is_ascii_digit() || next == '+' || next == '-'
+                        }))
+                    || (matches!(text[pos], '+' | '-') && matches!(text[pos - 1], 'e' | 'E')))
+            {
+                pos += 1;
+            }
+            let number: String = text[start..pos].iter().collect();
+            let Ok(value) = number.parse::<f64>() else {
+                return syntax_error(start, format!("invalid number '{}'", number));
+            };
+            // `2i` is imaginary, `2im(z)` is 2 times im(z)
+            let imaginary = text.get(pos) == Some(&'i')
+                && !text
+                    .get(pos + 1)
+                    .is_some_and(|&next| next.is_alphanumeric() || next == '_');
+            if imaginary {
+                pos += 1;
+                tokens.push((Token::Imaginary(value), start));
+            } else {
+                tokens.push((Token::Number(value), start));
+            }
+        } else if ch.is_alphabetic() || ch == '_' {
+            while pos < text.len() && (text[pos].is_alphanumeric() || text[pos] == '_') {
+                pos += 1;
+            }
+            tokens.push((Token::Ident(text[start..pos].iter().collect()), start));
+        } else if "+-*/^()|=<>".contains(ch) {
+            tokens.push((Token::Symbol(ch), start));
+            pos += 1;
+        } else {
+            return syntax_error(start, format!("unexpected '{}'", ch));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Position reported at the end of the source.
+    end: usize,
+    /// Current nesting, bounded so deep input does not overflow the stack.
+    depth: usize,
+    /// Operands parsed so far.
+    operands: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(_, pos)| pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> ParseResult<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            syntax_error(self.position(), format!("expected '{}'", symbol))
+        }
+    }
+
+    fn statements(&mut self) -> ParseResult<Vec<(Statement, usize)>> {
+        let mut statements = Vec::new();
+        loop {
+            while self.peek() == Some(&Token::End) {
+                self.pos += 1;
+            }
+            if self.peek().is_none() {
+                return Ok(statements);
+            }
+            let position = self.position();
+            statements.push((self.statement()?, position));
+            match self.peek() {
+                None | Some(Token::End) => {}
+                Some(_) => {
+                    return syntax_error(self.position(), "expected the end of the statement")
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let position = self.position();
+        let Some(Token::Ident(name)) = self.next() else {
+            return syntax_error(position, "expected z, z0, c, param or bailout");
+        };
+        match name.as_str() {
+            "z" | "z0" | "c" => {
+                self.expect('=')?;
+                let value = self.expr()?;
+                Ok(match name.as_str() {
+                    "z" => Statement::Step(value),
+                    "z0" => Statement::Start(value),
+                    _ => Statement::Constant(value),
+                })
+            }
+            "param" => {
+                let position = self.position();
+                let Some(Token::Ident(name)) = self.next() else {
+                    return syntax_error(position, "expected the name of the parameter");
+                };
+                if is_reserved(&name) {
+                    return syntax_error(position, format!("'{}' is reserved", name));
+                }
+                self.expect('=')?;
+                let value = self.expr()?;
+                Ok(Statement::Param { name, value })
+            }
+            "bailout" => self.bailout(),
+            _ => syntax_error(position, "expected z, z0, c, param or bailout"),
+        }
+    }
+
+    /// `|z| > R`, `|dz| < E`, or both joined by `or`.
+    fn bailout(&mut self) -> ParseResult<Statement> {
+        let mut bailout = Bailout {
+            radius: None,
+            epsilon: None,
+        };
+        loop {
+            let position = self.position();
+            self.expect('|')?;
+            let variable = match self.next() {
+                Some(Token::Ident(name)) if name == "z" || name == "dz" => name,
+                _ => return syntax_error(position, "bailout tests |z| > R or |dz| < E"),
+            };
+            self.expect('|')?;
+            let escape = variable == "z";
+            self.expect(if escape { '>' } else { '<' })?;
+            let position = self.position();
+            let value = match self.next() {
+                Some(Token::Number(value)) if value > 0.0 && value.is_finite() => value,
+                _ => return syntax_error(position, "expected a positive number"),
+            };
+            let (slot, name) = if escape {
+                (&mut bailout.radius, "|z|")
+            } else {
+                (&mut bailout.epsilon, "|dz|")
+            };
+            if slot.replace(value).is_some() {
+                return syntax_error(position, format!("{} is tested twice", name));
+            }
+            match self.peek() {
+                Some(Token::Ident(word)) if word == "or" => self.pos += 1,
+                _ => return Ok(Statement::Bailout(bailout)),
+            }
+        }
+    }
+
+    /// Sum of terms.
+    fn expr(&mut self) -> ParseResult<Expr> {
+        let mut left = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    /// Product of factors, juxtaposition included.
+    fn term(&mut self) -> ParseResult<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Mul
+            } else if self.eat('/') {
+                BinaryOp::Div
+            } else if matches!(
+                self.peek(),
+                Some(Token::Ident(name)) if name != "or"
+            ) || self.peek() == Some(&Token::Symbol('('))
+            {
+                BinaryOp::Mul
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    /// Every operand and nested expression goes through here, which keeps
+    /// their count and the depth.
+    fn unary(&mut self) -> ParseResult<Expr> {
+        if self.depth == MAX_NESTING {
+            return syntax_error(self.position(), "expression is too deeply nested");
+        }
+        if self.operands == MAX_OPERANDS {
+            return syntax_error(self.position(), "expression is too long");
+        }
+        self.operands += 1;
+        self.depth += 1;
+        let expr = if self.eat('-') {
+            self.unary().map(|inner| Expr::Neg(Box::new(inner)))
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        expr
+    }
+
+    /// `^` binds tighter than the sign of its base and groups to the right,
+    /// `-z^2` is `-(z^2)`.
+    fn power(&mut self) -> ParseResult<Expr> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            Ok(Expr::Binary(
+                BinaryOp::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> ParseResult<Expr> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(Complex::new(value, 0.0))),
+            Some(Token::Imaginary(value)) => Ok(Expr::Number(Complex::new(0.0, value))),
+            Some(Token::Ident(name)) if name == "i" => Ok(Expr::Number(Complex::new(0.0, 1.0))),
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::Symbol('(')) => {
+                let Some(function) = Function::from_name(&name) else {
+                    let names: Vec<&str> = Function::NAMES.iter().map(|(name, _)| *name).collect();
+                    return syntax_error(
+                        position,
+                        format!(
+                            "unknown function '{}' (expected {})",
+                            name,
+                            names.join(", ")
+                        ),
+                    );
+                };
+                self.expect('(')?;
+                let argument = self.expr()?;
+                self.expect(')')?;
+                Ok(Expr::Call(function, Box::new(argument)))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Variable { name, position }),
+            Some(Token::Symbol('(')) => {
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            Some(Token::Symbol('|')) => syntax_error(position, "write abs(...) for the modulus"),
+            Some(Token::Symbol(symbol)) => {
+                syntax_error(position, format!("unexpected '{}'", symbol))
+            }
+            Some(Token::End) | None => syntax_error(position, "expression is missing"),
+        }
+    }
+}
+
+/// Names that cannot be given to a parameter.
+fn is_reserved(name: &str) -> bool {
+    matches!(
+        name,
+        "z" | "z0" | "c" | "pixel" | "i" | "param" | "bailout" | "or" | "dz"
+    ) || Function::from_name(name).is_some()
+}
+
+/// Value of an expression without variables.
+fn constant_value(expr: &Expr) -> Option<Complex<f64>> {
+    Some(match expr {
+        Expr::Number(value) => *value,
+        Expr::Variable { .. } => return None,
+        Expr::Neg(inner) => -constant_value(inner)?,
+        Expr::Binary(op, left, right) => binary(*op, constant_value(left)?, constant_value(right)?),
+        Expr::Call(function, argument) => function.apply(constant_value(argument)?),
+    })
+}
+
+fn binary(op: BinaryOp, a: Complex<f64>, b: Complex<f64>) -> Complex<f64> {
+    match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Pow => match integer_power(b) {
+            Some(n) => a.powi(n),
+            None => a.pow(b),
+        },
+    }
+}
+
+/// `n` as an integer power small enough to multiply out.
+fn integer_power(n: Complex<f64>) -> Option<i32> {
+    (n.im == 0.0 && n.re.fract() == 0.0 && n.re.abs() <= MAX_INTEGER_POWER).then_some(n.re as i32)
+}
+
+/// Turns the syntax tree of an expression into bytecode.
+struct Compiler<'a> {
+    params: &'a [(String, Complex<f64>)],
+    /// Whether `z` may be read.
+    allow_z: bool,
+    constants: &'a mut Vec<Complex<f64>>,
+    code: Vec<Op>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Compiler<'_> {
+    fn push(&mut self, op: Op, position: usize) -> ParseResult<()> {
+        match op {
+            Op::Const(_) | Op::Load(_) => self.depth += 1,
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => self.depth -= 1,
+            Op::Neg | Op::Powi(_) | Op::Call(_) => {}
+        }
+        self.max_depth = self.max_depth.max(self.depth);
+        if self.max_depth > MAX_STACK {
+            return syntax_error(position, "expression is too deeply nested");
+        }
+        self.code.push(op);
+        Ok(())
+    }
+
+    fn constant(&mut self, value: Complex<f64>, position: usize) -> ParseResult<()> {
+        let index = match self.constants.iter().position(|&known| known == value) {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        let Ok(index) = u16::try_from(index) else {
+            return syntax_error(position, "too many constants");
+        };
+        self.push(Op::Const(index), position)
+    }
+
+    /// Constant parts are computed once, here.
+    fn compile(&mut self, expr: &Expr, position: usize) -> ParseResult<()> {
+        if let Some(value) = constant_value(expr) {
+            return self.constant(value, position);
+        }
+        match expr {
+            Expr::Number(value) => self.constant(*value, position),
+            Expr::Variable { name, position } => {
+                let slot = match name.as_str() {
+                    "z" if self.allow_z => Z,
+                    "z" => return syntax_error(*position, "z is only known in the step"),
+                    "c" => C,
+                    "pixel" => PIXEL,
+                    _ => match self.params.iter().position(|(param, _)| param == name) {
+                        Some(index) => 3 + index,
+                        None => {
+                            return syntax_error(
+                                *position,
+                                format!(
+                                    "unknown variable '{}' (expected z, c, pixel or a parameter)",
+                                    name
+                                ),
+                            )
+                        }
+                    },
+                };
+                self.push(Op::Load(slot as u8), *position)
+            }
+            Expr::Neg(inner) => {
+                self.compile(inner, position)?;
+                self.push(Op::Neg, position)
+            }
+            Expr::Binary(BinaryOp::Pow, base, exponent)
+                if constant_value(exponent).and_then(integer_power).is_some() =>
+            {
+                let exponent = constant_value(exponent)
+                    .and_then(integer_power)
+                    .expect("integer");
+                self.compile(base, position)?;
+                self.push(Op::Powi(exponent), position)
+            }
+            Expr::Binary(op, left, right) => {
+                self.compile(left, position)?;
+                self.compile(right, position)?;
+                let op = match op {
+                    BinaryOp::Add => Op::Add,
+                    BinaryOp::Sub => Op::Sub,
+                    BinaryOp::Mul => Op::Mul,
+                    BinaryOp::Div => Op::Div,
+                    BinaryOp::Pow => Op::Pow,
+                };
+                self.push(op, position)
+            }
+            Expr::Call(function, argument) => {
+                self.compile(argument, position)?;
+                self.push(Op::Call(*function), position)
+            }
+        }
+    }
+}
+
+/// Value of compiled `code` with the variables in `slots`.
+fn run(code: &[Op], constants: &[Complex<f64>], slots: &[Complex<f64>; SLOTS]) -> Complex<f64> {
+    let mut stack = [Complex::new(0.0, 0.0); MAX_STACK];
+    let mut top = 0;
+    for &op in code {
+        match op {
+            Op::Const(index) => {
+                stack[top] = constants[index as usize];
+                top += 1;
+            }
+            Op::Load(slot) => {
+                stack[top] = slots[slot as usize];
+                top += 1;
+            }
+            Op::Neg => stack[top - 1] = -stack[top - 1],
+            Op::Powi(n) => stack[top - 1] = stack[top - 1].powi(n),
+            Op::Call(function) => stack[top - 1] = function.apply(stack[top - 1]),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => {
+                top -= 1;
+                let (a, b) = (stack[top - 1], stack[top]);
+                stack[top - 1] = match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    _ => a.pow(b),
+                };
+            }
+        }
+    }
+    stack[0]
+}
+
+impl FromStr for Program {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Program::compile(s).map_err(|err| {
+            format!(
+                "invalid expression '{}': {} at character {}",
+                s.trim(),
+                err.message,
+                err.position + 1
+            )
+        })
+    }
+}
+
+impl Program {
+    fn compile(source: &str) -> ParseResult<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            end: source.chars().count(),
+            depth: 0,
+            operands: 0,
+        };
+        let statements = parser.statements()?;
+
+        // Parameters first, any statement may use them
+        let mut params: Vec<(String, Complex<f64>)> = Vec::new();
+        for (statement, position) in &statements {
+            if let Statement::Param { name, value } = statement {
+                if params.iter().any(|(known, _)| known == name) {
+                    return syntax_error(
+                        *position,
+                        format!("parameter '{}' is declared twice", name),
+                    );
+                }
+                if params.len() == MAX_PARAMS {
+                    return syntax_error(*position, format!("at most {} parameters", MAX_PARAMS));
+                }
+                let Some(value) = constant_value(value) else {
+                    return syntax_error(*position, "the value of a parameter must be a number");
+                };
+                params.push((name.clone(), value));
+            }
+        }
+
+        let mut constants = Vec::new();
+        let mut step = None;
+        let mut start = None;
+        let mut c = None;
+        let mut bailout = None;
+        for (statement, position) in &statements {
+            let (target, expr, allow_z, name) = match statement {
+                Statement::Step(expr) => (&mut step, expr, true, "z"),
+                Statement::Start(expr) => (&mut start, expr, false, "z0"),
+                Statement::Constant(expr) => (&mut c, expr, false, "c"),
+                Statement::Bailout(value) => {
+                    if bailout.replace(*value).is_some() {
+                        return syntax_error(*position, "bailout is set twice");
+                    }
+                    continue;
+                }
+                Statement::Param { .. } => continue,
+            };
+            if target.is_some() {
+                return syntax_error(*position, format!("{} is set twice", name));
+            }
+            let mut compiler = Compiler {
+                params: &params,
+                allow_z,
+                constants: &mut constants,
+                code: Vec::new(),
+                depth: 0,
+                max_depth: 0,
+            };
+            compiler.compile(expr, *position)?;
+            *target = Some(compiler.code);
+        }
+
+        let Some(step) = step else {
+            return syntax_error(0, "the step 'z = ...' is missing");
+        };
+        Ok(Program {
+            source: source.trim().to_owned(),
+            params,
+            constants,
+            step,
+            start,
+            c,
+            bailout: bailout.unwrap_or(Bailout::ESCAPE),
+        })
+    }
+
+    pub fn bailout(&self) -> Bailout {
+        self.bailout
+    }
+
+    pub fn set_bailout(&mut self, bailout: Bailout) {
+        self.bailout = bailout;
+    }
+
+    /// Names and values of the parameters, in declaration order.
+    pub fn params(&self) -> &[(String, Complex<f64>)] {
+        &self.params
+    }
+
+    pub fn set_param(&mut self, name: &str, value: Complex<f64>) -> Result<(), String> {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some((_, param)) => {
+                *param = value;
+                Ok(())
+            }
+            None => Err(format!("the expression has no parameter '{}'", name)),
+        }
+    }
+
+    pub fn sample(&self, pixel: Complex<f64>, max_iter: u32) -> Sample {
+        let mut slots = [Complex::new(0.0, 0.0); SLOTS];
+        slots[C] = pixel;
+        slots[PIXEL] = pixel;
+        for (slot, (_, value)) in slots[3..].iter_mut().zip(&self.params) {
+            *slot = *value;
+        }
+        if let Some(c) = &self.c {
+            slots[C] = run(c, &self.constants, &slots);
+        }
+        let z = match &self.start {
+            Some(start) => run(start, &self.constants, &slots),
+            None => Complex::new(0.0, 0.0),
+        };
+        iterate(z, max_iter, self.bailout, |z| {
+            slots[Z] = z;
+            run(&self.step, &self.constants, &slots)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formula::EscapeFormula;
+
+    fn program(source: &str) -> Program {
+        source.parse().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Value of the step of `source` at `z`, with `c` and the pixel at 0.
+    fn step(source: &str, z: Complex<f64>) -> Complex<f64> {
+        let program = program(source);
+        let mut slots = [Complex::new(0.0, 0.0); SLOTS];
+        slots[Z] = z;
+        run(&program.step, &program.constants, &slots)
+    }
+
+    fn error(source: &str) -> String {
+        source.parse::<Program>().expect_err(source)
+    }
+
+    #[test]
+    fn precedence() {
+        let i = Complex::new(0.0, 1.0);
+        // -(z^2) is 1 at i, (-z)^2 would be -1
+        assert_eq!(step("z = -z^2", i), Complex::new(1.0, 0.0));
+        // 3(z^2) is 12 at 2, (3z)^2 would be 36
+        assert_eq!(
+            step("z = 3z^2", Complex::new(2.0, 0.0)),
+            Complex::new(12.0, 0.0)
+        );
+        assert_eq!(
+            step("z = 2im(z)", Complex::new(1.0, 3.0)),
+            Complex::new(6.0, 0.0)
+        );
+        assert_eq!(step("z = 1e-3i", i), Complex::new(0.0, 1e-3));
+        assert_eq!(step("z = 2^3^2", i), Complex::new(512.0, 0.0));
+        assert_eq!(step("z = 1 - 2 - 3", i), Complex::new(-4.0, 0.0));
+        assert_eq!(step("z = 8 / 2 / 2", i), Complex::new(2.0, 0.0));
+        assert_eq!(step("z = 2(z + 1)", i), Complex::new(2.0, 2.0));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            error("z = (z^2 + c"),
+            "invalid expression 'z = (z^2 + c': expected ')' at character 13"
+        );
+        assert!(error("z = z^2 + w").ends_with(
+            "unknown variable 'w' (expected z, c, pixel or a parameter) at character 11"
+        ));
+        assert!(error("z = z^2 +").ends_with("expression is missing at character 10"));
+        assert!(error("z0 = z").ends_with("z is only known in the step at character 6"));
+        assert!(error("c = 1").ends_with("the step 'z = ...' is missing at character 1"));
+        assert!(error("z = |z|").ends_with("write abs(...) for the modulus at character 5"));
+    }
+
+    #[test]
+    fn duplicate_statements() {
+        assert!(error("z = z^2 + c; z = z^3 + c").ends_with("z is set twice at character 14"));
+        assert!(error("z = z^2 + c\nz0 = 1\nz0 = 2").ends_with("z0 is set twice at character 20"));
+        assert!(error("param a = 1; param a = 2; z = z^2 + a")
+            .ends_with("parameter 'a' is declared twice at character 14"));
+        assert!(error("z = z^2 + c; bailout |z| > 4; bailout |dz| < 1e-6")
+            .ends_with("bailout is set twice at character 31"));
+        assert!(error("z = z^2 + c; bailout |z| > 4 or |z| > 8")
+            .ends_with("|z| is tested twice at character 39"));
+    }
+
+    #[test]
+    fn constant_folding() {
+        let program = program("z = z + (2*3 - sqrt(4))");
+        assert!(matches!(
+            program.step[..],
+            [Op::Load(0), Op::Const(0), Op::Add]
+        ));
+        assert_eq!(program.constants, [Complex::new(4.0, 0.0)]);
+
+        // Integer powers are multiplied out, others go through the logarithm
+        let program = self::program("z = z^(1 + 1) + z^-3 + z^3^2 + z^2.5 + z^65 + z^i");
+        let powers: Vec<Op> = program
+            .step
+            .iter()
+            .copied()
+            .filter(|op| matches!(op, Op::Pow | Op::Powi(_)))
+            .collect();
+        assert!(matches!(
+            powers[..],
+            [
+                Op::Powi(2),
+                Op::Powi(-3),
+                Op::Powi(9),
+                Op::Pow,
+                Op::Pow,
+                Op::Pow
+            ]
+        ));
+
+        // Constants are shared between statements
+        let program = self::program("z = z^2 + 0.5; c = 0.5; z0 = 0.5");
+        assert_eq!(program.constants, [Complex::new(0.5, 0.0)]);
+    }
+
+    #[test]
+    fn parameters() {
+        let mut program = program("param k = 2 + i; z = z^2 + k*c");
+        assert_eq!(program.params(), [("k".to_owned(), Complex::new(2.0, 1.0))]);
+        program.set_param("k", Complex::new(3.0, 0.0)).unwrap();
+        assert_eq!(program.params()[0].1, Complex::new(3.0, 0.0));
+        assert!(program.set_param("j", Complex::new(0.0, 0.0)).is_err());
+        assert!(error("param sin = 1; z = z").contains("'sin' is reserved"));
+        assert!(error("param k = c; z = z").contains("must be a number"));
+    }
+
+    #[test]
+    fn sample_matches_mandelbrot() {
+        let program = program("z = z^2 + c");
+        let mandelbrot = EscapeFormula::Mandelbrot;
+        for (re, im) in [
+            (0.0, 0.0),
+            (-0.75, 0.1),
+            (0.3, 0.5),
+            (-1.2, 0.25),
+            (0.26, 0.0),
+            (-2.1, 0.0),
+            (1.0, 1.0),
+        ] {
+            let c = Complex::new(re, im);
+            assert_eq!(
+                program.sample(c, 500),
+                mandelbrot.sample(c, 500, Bailout::ESCAPE),
+                "{:?}",
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_limits() {
+        let deep = format!("z = {}z{}", "(".repeat(50_000), ")".repeat(50_000));
+        assert!(error(&deep).contains("expression is too deeply nested"));
+        let signs = format!("z = {}z", "-".repeat(50_000));
+        assert!(error(&signs).contains("expression is too deeply nested"));
+        let long = format!("z = z{}", " + z".repeat(50_000));
+        assert!(error(&long).contains("expression is too long"));
+        let shallow = format!("z = {}z{} + c", "(".repeat(20), ")".repeat(20));
+        assert_eq!(
+            step(&shallow, Complex::new(2.0, 0.0)),
+            Complex::new(2.0, 0.0)
+        );
+    }
+}
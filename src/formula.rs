@@ -0,0 +1,485 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::complex::Complex;
+use crate::expression::Program;
+use crate::mandelbrot::{MandelbrotUniverse, Sample};
+use crate::newton::{Newton, Polynomial};
+use crate::plugin::Plugin;
+
+/// Escape radius of the formulas that do not declare their own.
+const ESCAPE_RADIUS: f64 = 2.0;
+/// Convergence threshold of the formulas that do not declare their own.
+const EPSILON: f64 = 1e-6;
+/// `cos` overflows a few steps past this.
+const COS_RADIUS: f64 = 64.0;
+/// Magnet orbits either converge to 1 or escape past this.
+const MAGNET_RADIUS: f64 = 100.0;
+
+/// Any of the built-in formulas.
+#[derive(Debug, Clone)]
+pub enum Formula {
+    Escape {
+        formula: EscapeFormula,
+        bailout: Bailout,
+    },
+    /// Newton's method or the Nova fractal, see [`Newton`].
+    Newton(Newton),
+    /// Formula written as an expression, see [`Program`].
+    Expression(Program),
+    /// Formula loaded from a shared library, see [`Plugin`].
+    Plugin(Arc<Plugin>),
+}
+
+/// Formula `z = f(z, c)` iterated from `z = 0` until its [`Bailout`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EscapeFormula {
+    /// `z² + c`, the cardioid and period 2 bulb are skipped.
+    #[default]
+    Mandelbrot,
+    /// `z^d + c`, any real power `d` above 1. Integer powers are computed
+    /// by multiplication, others through the polar form.
+    Multibrot { power: f64 },
+    /// `(|re z| + i |im z|)² + c`
+    BurningShip,
+    /// Mandelbar, `conj(z)² + c`
+    Tricorn,
+    /// `|re z²| + i im z² + c`
+    Celtic,
+    /// `|re z²| + i |im z²| + c`
+    Buffalo,
+    /// `re z² - 2i |re z| im z + c`
+    Perpendicular,
+    /// `cos(z) + 1/c`, most orbits converge rather than escape.
+    Cos,
+    /// `z² + c + p z_n-1`, the orbit remembers the previous `z`.
+    Phoenix { p: Complex<f64> },
+    /// Magnet type I, `((z² + c - 1) / (2z + c - 2))²`.
+    Magnet1,
+    /// Magnet type II,
+    /// `((z³ + 3(c - 1)z + (c - 1)(c - 2)) / (3z² + 3(c - 2)z + (c - 1)(c - 2) + 1))²`.
+    Magnet2,
+}
+
+/// When an orbit stops, besides reaching the maximum iterations: it escapes,
+/// it converges, or whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bailout {
+    /// The orbit escaped once `|z|` is above this.
+    pub radius: Option<f64>,
+    /// The orbit converged once a step `|z_n - z_n-1|` is shorter than this.
+    pub epsilon: Option<f64>,
+}
+
+impl Bailout {
+    /// `|z| > 2`, the usual escape-time test.
+    pub const ESCAPE: Bailout = Bailout {
+        radius: Some(ESCAPE_RADIUS),
+        epsilon: None,
+    };
+
+    /// The tests of `mode`, keeping the radius and threshold declared by the
+    /// formula, or the defaults if it has none.
+    pub fn with_mode(self, mode: BailoutMode) -> Bailout {
+        let radius = Some(self.radius.unwrap_or(ESCAPE_RADIUS));
+        let epsilon = Some(self.epsilon.unwrap_or(EPSILON));
+        match mode {
+            BailoutMode::Escape => Bailout {
+                radius,
+                epsilon: None,
+            },
+            BailoutMode::Convergence => Bailout {
+                radius: None,
+                epsilon,
+            },
+            BailoutMode::Both => Bailout { radius, epsilon },
+        }
+    }
+}
+
+/// Stop conditions, as selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BailoutMode {
+    Escape,
+    Convergence,
+    Both,
+}
+
+impl FromStr for BailoutMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "escape" => Ok(Self::Escape),
+            "convergence" => Ok(Self::Convergence),
+            "both" => Ok(Self::Both),
+            _ => Err(format!(
+                "unknown bailout '{}' (expected escape, convergence or both)",
+                s
+            )),
+        }
+    }
+}
+
+/// Kind of formula, as selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormulaKind {
+    #[default]
+    Mandelbrot,
+    Multibrot,
+    BurningShip,
+    Tricorn,
+    Celtic,
+    Buffalo,
+    Perpendicular,
+    Newton,
+    Nova,
+    Cos,
+    Phoenix,
+    Magnet1,
+    Magnet2,
+}
+
+impl FromStr for FormulaKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mandelbrot" => Ok(Self::Mandelbrot),
+            "multibrot" => Ok(Self::Multibrot),
+            "burning-ship" => Ok(Self::BurningShip),
+            "tricorn" | "mandelbar" => Ok(Self::Tricorn),
+            "celtic" => Ok(Self::Celtic),
+            "buffalo" => Ok(Self::Buffalo),
+            "perpendicular" => Ok(Self::Perpendicular),
+            "newton" => Ok(Self::Newton),
+            "nova" => Ok(Self::Nova),
+            "cos" => Ok(Self::Cos),
+            "phoenix" => Ok(Self::Phoenix),
+            "magnet1" => Ok(Self::Magnet1),
+            "magnet2" => Ok(Self::Magnet2),
+            _ => Err(format!(
+                "unknown formula '{}' (expected mandelbrot, multibrot, burning-ship, tricorn, \
+                 celtic, buffalo, perpendicular, newton, nova, cos, phoenix, magnet1 or magnet2)",
+                s
+            )),
+        }
+    }
+}
+
+impl FormulaKind {
+    /// Every kind, in the order the viewer cycles through them.
+    pub const ALL: [FormulaKind; 13] = [
+        Self::Mandelbrot,
+        Self::Multibrot,
+        Self::BurningShip,
+        Self::Tricorn,
+        Self::Celtic,
+        Self::Buffalo,
+        Self::Perpendicular,
+        Self::Newton,
+        Self::Nova,
+        Self::Cos,
+        Self::Phoenix,
+        Self::Magnet1,
+        Self::Magnet2,
+    ];
+}
+
+impl Formula {
+    /// Build a formula of the given kind. `power` is the exponent of the
+    /// multibrot, `polynomial` and `relaxation` (the `a` factor of the step)
+    /// set up Newton's method, `phoenix` is the `p` factor of the phoenix.
+    /// `bailout` replaces the stop conditions the
+    /// formula declares, Newton's method always stops on convergence.
+    pub fn new(
+        kind: FormulaKind,
+        power: f64,
+        polynomial: &Polynomial,
+        relaxation: Complex<f64>,
+        phoenix: Complex<f64>,
+        bailout: Option<BailoutMode>,
+    ) -> Self {
+        let escape = match kind {
+            FormulaKind::Mandelbrot => EscapeFormula::Mandelbrot,
+            FormulaKind::Multibrot => EscapeFormula::Multibrot { power },
+            FormulaKind::BurningShip => EscapeFormula::BurningShip,
+            FormulaKind::Tricorn => EscapeFormula::Tricorn,
+            FormulaKind::Celtic => EscapeFormula::Celtic,
+            FormulaKind::Buffalo => EscapeFormula::Buffalo,
+            FormulaKind::Perpendicular => EscapeFormula::Perpendicular,
+            FormulaKind::Cos => EscapeFormula::Cos,
+            FormulaKind::Phoenix => EscapeFormula::Phoenix { p: phoenix },
+            FormulaKind::Magnet1 => EscapeFormula::Magnet1,
+            FormulaKind::Magnet2 => EscapeFormula::Magnet2,
+            FormulaKind::Newton | FormulaKind::Nova => {
+                let nova = kind == FormulaKind::Nova;
+                return Formula::Newton(Newton::new(polynomial.clone(), relaxation, nova));
+            }
+        };
+        let declared = escape.bailout();
+        Formula::Escape {
+            formula: escape,
+            bailout: bailout.map_or(declared, |mode| declared.with_mode(mode)),
+        }
+    }
+
+    /// Center and scale of a view framing the whole set.
+    pub fn default_view(&self) -> (Complex<f64>, f64) {
+        match self {
+            Formula::Escape { formula, .. } => formula.default_view(),
+            Formula::Newton(newton) => newton.default_view(),
+            Formula::Expression(_) => (Complex::new(0.0, 0.0), 4.0),
+            Formula::Plugin(plugin) => plugin.default_view(),
+        }
+    }
+
+    /// The parameter of the formula that can be changed in the viewer: `p`
+    /// of the phoenix, the relaxation of Newton's method, the first parameter
+    /// of an expression.
+    pub fn parameter(&self) -> Option<Complex<f64>> {
+        match self {
+            Formula::Escape {
+                formula: EscapeFormula::Phoenix { p },
+                ..
+            } => Some(*p),
+            Formula::Escape { .. } => None,
+            Formula::Newton(newton) => Some(newton.relaxation()),
+            Formula::Expression(program) => program.params().first().map(|&(_, value)| value),
+            Formula::Plugin(_) => None,
+        }
+    }
+
+    /// Change the parameter, for the formulas that have one.
+    pub fn set_parameter(&mut self, value: Complex<f64>) {
+        match self {
+            Formula::Escape {
+                formula: EscapeFormula::Phoenix { p },
+                ..
+            } => *p = value,
+            Formula::Escape { .. } => {}
+            Formula::Newton(newton) => newton.set_relaxation(value),
+            Formula::Expression(program) => {
+                if let Some((name, _)) = program.params().first() {
+                    let name = name.clone();
+                    program.set_param(&name, value).expect("declared parameter");
+                }
+            }
+            Formula::Plugin(_) => {}
+        }
+    }
+
+    pub fn sample(&self, c: Complex<f64>, max_iter: u32) -> Sample {
+        match self {
+            Formula::Escape { formula, bailout } => formula.sample(c, max_iter, *bailout),
+            Formula::Newton(newton) => newton.sample(c, max_iter),
+            Formula::Expression(program) => program.sample(c, max_iter),
+            Formula::Plugin(plugin) => plugin.sample(c, max_iter),
+        }
+    }
+
+    /// Make `universe` compute this formula, by rows of points for the
+    /// plugins taking them.
+    pub fn use_in(&self, universe: &mut MandelbrotUniverse) {
+        let formula = self.clone();
+        universe.set_function(move |c, max_iter| formula.sample(c, max_iter));
+        if let Formula::Plugin(plugin) = self {
+            if plugin.takes_rows() {
+                let plugin = plugin.clone();
+                universe.set_row_function(move |points, max_iter, out| {
+                    plugin.sample_row(points, max_iter, out)
+                });
+            }
+        }
+    }
+}
+
+impl EscapeFormula {
+    /// Center and scale of a view framing the whole set.
+    pub fn default_view(&self) -> (Complex<f64>, f64) {
+        match self {
+            Self::Mandelbrot => (Complex::new(-0.5, 0.0), 3.0),
+            Self::Celtic | Self::Perpendicular => (Complex::new(-0.5, 0.0), 3.4),
+            Self::Multibrot { .. } => (Complex::new(0.0, 0.0), 3.0),
+            // The imaginary axis points down, the ship is upright
+            Self::BurningShip => (Complex::new(-0.4, -0.5), 3.0),
+            Self::Tricorn => (Complex::new(-0.2, 0.0), 4.4),
+            Self::Buffalo => (Complex::new(-0.6, -0.3), 3.2),
+            Self::Cos => (Complex::new(0.2, 0.0), 2.4),
+            Self::Phoenix { .. } => (Complex::new(-0.4, 0.0), 2.6),
+            Self::Magnet1 => (Complex::new(1.3, 0.0), 5.0),
+            Self::Magnet2 => (Complex::new(1.05, 0.0), 3.6),
+        }
+    }
+
+    /// Stop conditions of the formula.
+    pub fn bailout(&self) -> Bailout {
+        match self {
+            Self::Cos => Bailout {
+                radius: Some(COS_RADIUS),
+                epsilon: Some(EPSILON),
+            },
+            Self::Magnet1 | Self::Magnet2 => Bailout {
+                radius: Some(MAGNET_RADIUS),
+                epsilon: Some(EPSILON),
+            },
+            _ => Bailout::ESCAPE,
+        }
+    }
+
+    /// Iterations before the orbit of `c` stops on `bailout`, `max_iter` if
+    /// it does not.
+    pub fn sample(&self, c: Complex<f64>, max_iter: u32, bailout: Bailout) -> Sample {
+        match *self {
+            Self::Mandelbrot if bailout == Bailout::ESCAPE => mandelbrot_fast(c, max_iter).into(),
+            Self::Mandelbrot => escape(c, max_iter, bailout, |z| z * z),
+            Self::Multibrot { power } if power.fract() == 0.0 && power <= i32::MAX as f64 => {
+                let power = power as i32;
+                escape(c, max_iter, bailout, |z| z.powi(power))
+            }
+            Self::Multibrot { power } => escape(c, max_iter, bailout, |z| z.powf(power)),
+            Self::BurningShip => escape(c, max_iter, bailout, |z| {
+                let z = Complex::new(z.re.abs(), z.im.abs());
+                z * z
+            }),
+            Self::Tricorn => escape(c, max_iter, bailout, |z| z.conj() * z.conj()),
+            Self::Celtic => escape(c, max_iter, bailout, |z| {
+                let z = z * z;
+                Complex::new(z.re.abs(), z.im)
+            }),
+            Self::Buffalo => escape(c, max_iter, bailout, |z| {
+                let z = z * z;
+                Complex::new(z.re.abs(), z.im.abs())
+            }),
+            Self::Perpendicular => escape(c, max_iter, bailout, |z| {
+                Complex::new(z.re * z.re - z.im * z.im, -2.0 * z.re.abs() * z.im)
+            }),
+            Self::Cos => mandelbrot_cos(c, max_iter, bailout),
+            Self::Phoenix { p } => {
+                let mut previous = Complex::new(0.0, 0.0);
+                escape(c, max_iter, bailout, |z| {
+                    let next = z * z + p * previous;
+                    previous = z;
+                    next
+                })
+            }
+            Self::Magnet1 => {
+                let one = Complex::new(1.0, 0.0);
+                let (c1, c2) = (c - one, c - one * 2.0);
+                iterate(Complex::new(0.0, 0.0), max_iter, bailout, |z| {
+                    let q = (z * z + c1) / (z * 2.0 + c2);
+                    q * q
+                })
+            }
+            Self::Magnet2 => {
+                let one = Complex::new(1.0, 0.0);
+                let (c1, c2) = (c - one, c - one * 2.0);
+                let c12 = c1 * c2;
+                iterate(Complex::new(0.0, 0.0), max_iter, bailout, |z| {
+                    let q =
+                        (z * z * z + c1 * z * 3.0 + c12) / (z * z * 3.0 + c2 * z * 3.0 + c12 + one);
+                    q * q
+                })
+            }
+        }
+    }
+}
+
+/// Iterate `z = step(z) + c` from `z = 0`.
+fn escape(
+    c: Complex<f64>,
+    max_iter: u32,
+    bailout: Bailout,
+    mut step: impl FnMut(Complex<f64>) -> Complex<f64>,
+) -> Sample {
+    iterate(Complex::new(0.0, 0.0), max_iter, bailout, |z| step(z) + c)
+}
+
+/// Iterate `z = step(z)` from `z` until `bailout` or `max_iter`. An orbit
+/// leaving the floating point range escaped. `step` may keep state between
+/// iterations, such as the previous `z`.
+pub fn iterate(
+    mut z: Complex<f64>,
+    max_iter: u32,
+    bailout: Bailout,
+    mut step: impl FnMut(Complex<f64>) -> Complex<f64>,
+) -> Sample {
+    let radius = bailout
+        .radius
+        .map_or(f64::INFINITY, |radius| radius * radius);
+    let epsilon = bailout.epsilon.map(|epsilon| epsilon * epsilon);
+    let mut previous = f64::INFINITY;
+    for n in 1..=max_iter {
+        let next = step(z);
+        let norm = next.norm();
+        if norm > radius || !norm.is_finite() {
+            return n.into();
+        }
+        if let Some(epsilon) = epsilon {
+            let distance = (next - z).norm();
+            if distance < epsilon {
+                return Sample::converged(
+                    n,
+                    convergence_fraction(previous, distance, epsilon),
+                    None,
+                );
+            }
+            previous = distance;
+        }
+        z = next;
+    }
+    max_iter.into()
+}
+
+/// Where in its last step an orbit crossed the convergence threshold, from 0
+/// at the start to 1 at the end, for smooth coloring. The log of the step
+/// length is interpolated between the last two steps; all three are squared.
+pub fn convergence_fraction(previous: f64, distance: f64, epsilon: f64) -> f32 {
+    if !previous.is_finite() {
+        return 1.0;
+    }
+    let fraction = (epsilon.ln() - previous.ln()) / (distance.ln() - previous.ln());
+    if fraction.is_nan() {
+        1.0
+    } else {
+        fraction.clamp(0.0, 1.0) as f32
+    }
+}
+
+pub fn mandelbrot(c: Complex<f64>, max_iter: u32) -> u32 {
+    let mut z = Complex::new(0.0, 0.0);
+    let mut n = 0;
+    while z.norm() <= 4.0 && n < max_iter {
+        z = z * z + c;
+        n += 1;
+    }
+    n
+}
+
+/// Whether `c` is in the main cardioid or the period 2 bulb of the
+/// Mandelbrot set, where orbits never escape.
+pub fn in_cardioid_or_bulb(c: Complex<f64>) -> bool {
+    // Center check
+    if (c.re + 1.0).powi(2) + c.im.powi(2) < 0.0625 {
+        return true;
+    }
+
+    // Cardoid check
+    let p = ((c.re - 0.25).powi(2) + c.im.powi(2)).sqrt();
+    c.re < (p - 2.0 * p.powi(2) + 0.25)
+}
+
+pub fn mandelbrot_fast(c: Complex<f64>, max_iter: u32) -> u32 {
+    if in_cardioid_or_bulb(c) {
+        return max_iter;
+    }
+
+    mandelbrot(c, max_iter)
+}
+
+pub fn mandelbrot_cos(c: Complex<f64>, max_iter: u32, bailout: Bailout) -> Sample {
+    let inverse = Complex::new(1.0, 0.0) / c;
+    iterate(Complex::new(0.0, 0.0), max_iter, bailout, |z| {
+        z.cos() + inverse
+    })
+}
@@ -0,0 +1,359 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::quantize::{self, Histogram};
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE: u16 = 4096;
+
+/// Where the colors of an animated GIF come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GifPalette {
+    /// Each frame has its own palette, best colors but the file is streamed
+    /// frame by frame.
+    #[default]
+    PerFrame,
+    /// One palette for the whole animation, no color flicker between frames
+    /// but every frame is kept in memory until the end.
+    Global,
+}
+
+impl FromStr for GifPalette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-frame" => Ok(Self::PerFrame),
+            "global" => Ok(Self::Global),
+            _ => Err(format!(
+                "unknown GIF palette '{}' (expected per-frame or global)",
+                s
+            )),
+        }
+    }
+}
+
+/// Animated GIF encoder, colors are reduced to 256 by median cut.
+pub struct GifWriter<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+    loops: u32,
+    palette: GifPalette,
+    header_written: bool,
+    /// Frames and delays waiting for the global palette.
+    pending: Vec<(Vec<u8>, u16)>,
+    histogram: Histogram,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// `loops` is the number of times the animation plays, 0 for forever.
+    pub fn new(
+        writer: W,
+        width: u32,
+        height: u32,
+        loops: u32,
+        palette: GifPalette,
+    ) -> io::Result<Self> {
+        let size = |n: u32| {
+            u16::try_from(n).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "GIF images are at most 65535 pixels wide and high",
+                )
+            })
+        };
+        Ok(Self {
+            writer,
+            width: size(width)?,
+            height: size(height)?,
+            loops,
+            palette,
+            header_written: false,
+            pending: Vec::new(),
+            histogram: Histogram::new(),
+        })
+    }
+
+    /// Append a frame of 8 bit RGBA pixels (alpha is ignored), shown for
+    /// `delay` hundredths of a second.
+    pub fn write_frame(&mut self, rgba: &[u8], delay: u16) -> io::Result<()> {
+        match self.palette {
+            GifPalette::PerFrame => {
+                let mut histogram = Histogram::new();
+                quantize::add_to_histogram(&mut histogram, rgba);
+                let palette = quantize::median_cut(&histogram, MAX_COLORS);
+                self.write_header(None)?;
+                self.write_image(rgba, &palette, true, delay)
+            }
+            GifPalette::Global => {
+                quantize::add_to_histogram(&mut self.histogram, rgba);
+                self.pending.push((rgba.to_vec(), delay));
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the frames waiting for the global palette and the trailer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.palette == GifPalette::Global {
+            let palette = quantize::median_cut(&self.histogram, MAX_COLORS);
+            self.write_header(Some(&palette))?;
+            for (rgba, delay) in std::mem::take(&mut self.pending) {
+                self.write_image(&rgba, &palette, false, delay)?;
+            }
+        }
+        self.write_header(None)?;
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self, global: Option<&[[u8; 3]]>) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+
+        self.writer.write_all(b"GIF89a")?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        match global {
+            Some(palette) => {
+                let bits = table_bits(palette.len());
+                self.writer.write_all(&[0xf0 | (bits - 1), 0, 0])?;
+                write_color_table(&mut self.writer, palette, bits)?;
+            }
+            None => self.writer.write_all(&[0x70, 0, 0])?,
+        }
+
+        // Netscape extension: how many times the animation repeats
+        if self.loops != 1 {
+            let repeats = self.loops.saturating_sub(1).min(u16::MAX as u32) as u16;
+            self.writer.write_all(&[0x21, 0xff, 11])?;
+            self.writer.write_all(b"NETSCAPE2.0")?;
+            self.writer.write_all(&[3, 1])?;
+            self.writer.write_all(&repeats.to_le_bytes())?;
+            self.writer.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    fn write_image(
+        &mut self,
+        rgba: &[u8],
+        palette: &[[u8; 3]],
+        local: bool,
+        delay: u16,
+    ) -> io::Result<()> {
+        // Graphic control: delay, frames replace each other
+        self.writer.write_all(&[0x21, 0xf9, 4, 0x04])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        let bits = table_bits(palette.len());
+        if local {
+            self.writer.write_all(&[0x80 | (bits - 1)])?;
+            write_color_table(&mut self.writer, palette, bits)?;
+        } else {
+            self.writer.write_all(&[0])?;
+        }
+
+        let indices = quantize::map_to_palette(rgba, palette);
+        let min_code_size = bits.max(2);
+        self.writer.write_all(&[min_code_size])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+}
+
+/// Bits needed to index a color table of `len` entries, at least 1.
+fn table_bits(len: usize) -> u8 {
+    (usize::BITS - (len.max(2) - 1).leading_zeros()) as u8
+}
+
+/// Color tables hold a power of two entries, padded with black.
+fn write_color_table(writer: &mut impl Write, palette: &[[u8; 3]], bits: u8) -> io::Result<()> {
+    for i in 0..1 << bits {
+        writer.write_all(palette.get(i).unwrap_or(&[0, 0, 0]))?;
+    }
+    Ok(())
+}
+
+/// GIF flavored LZW: variable code size up to 12 bits, packed least
+/// significant bit first, the table is cleared when it is full.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut out = Vec::new();
+    let (mut acc, mut count) = (0u32, 0u32);
+    let mut emit = |code: u16, width: u32| {
+        acc |= (code as u32) << count;
+        count += width;
+        while count >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            count -= 8;
+        }
+    };
+
+    // Code of each (prefix, index) string, 0 when not in the table
+    let mut table = vec![0u16; MAX_CODE as usize * 256];
+    let mut next = end + 1;
+    let mut width = min_code_size as u32 + 1;
+    emit(clear, width);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        emit(end, width);
+        return finish_bits(out, acc, count);
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        let slot = prefix as usize * 256 + index as usize;
+        if table[slot] != 0 {
+            prefix = table[slot];
+            continue;
+        }
+
+        emit(prefix, width);
+        if next < MAX_CODE {
+            table[slot] = next;
+            next += 1;
+            if next > 1 << width && width < 12 {
+                width += 1;
+            }
+        } else {
+            emit(clear, width);
+            table.fill(0);
+            next = end + 1;
+            width = min_code_size as u32 + 1;
+        }
+        prefix = index as u16;
+    }
+    emit(prefix, width);
+    // Decoders add a string for the last code too, which can widen the end
+    if next < MAX_CODE && next + 1 > 1 << width && width < 12 {
+        width += 1;
+    }
+    emit(end, width);
+    finish_bits(out, acc, count)
+}
+
+fn finish_bits(mut out: Vec<u8>, acc: u32, count: u32) -> Vec<u8> {
+    if count > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode a GIF LZW stream, the way viewers read it.
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> {
+            let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+            table.extend([Vec::new(), Vec::new()]);
+            table
+        };
+
+        let mut out = Vec::new();
+        let mut table = reset();
+        let mut width = min_code_size as u32 + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let (mut acc, mut count, mut bytes) = (0u32, 0u32, data.iter());
+        loop {
+            while count < width {
+                acc |= (*bytes.next().expect("end code") as u32) << count;
+                count += 8;
+            }
+            let code = (acc & ((1 << width) - 1)) as usize;
+            acc >>= width;
+            count -= width;
+
+            if code == clear {
+                table = reset();
+                width = min_code_size as u32 + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (&previous, table.get(code)) {
+                (_, Some(entry)) if code != clear && code != end => entry.clone(),
+                (Some(previous), None) if code == table.len() => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                _ => panic!("invalid code {}", code),
+            };
+            out.extend(&entry);
+            if let Some(mut previous) = previous.take() {
+                if table.len() < MAX_CODE as usize {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                }
+            }
+            if table.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for (indices, min_code_size) in [
+            (vec![], 2),
+            (vec![0], 2),
+            (vec![1, 1, 1, 1, 1, 1, 1, 1], 2),
+            (vec![0, 1, 2, 3, 0, 1, 2, 3, 3, 3, 3, 2, 1, 0], 2),
+            ((0..=255).collect(), 8),
+        ] {
+            assert_eq!(
+                lzw_decode(&lzw_encode(&indices, min_code_size), min_code_size),
+                indices
+            );
+        }
+    }
+
+    #[test]
+    fn lzw_clears_a_full_table() {
+        // Noise fills the 4096 codes many times over
+        let mut state = 1u32;
+        let indices: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let encoded = lzw_encode(&indices, 8);
+        assert_eq!(lzw_decode(&encoded, 8), indices);
+
+        // Long runs grow strings instead
+        let runs: Vec<u8> = (0..100_000).map(|i| (i / 1000 % 4) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&runs, 2), 2), runs);
+    }
+
+    #[test]
+    fn table_sizes() {
+        assert_eq!(table_bits(1), 1);
+        assert_eq!(table_bits(2), 1);
+        assert_eq!(table_bits(3), 2);
+        assert_eq!(table_bits(16), 4);
+        assert_eq!(table_bits(17), 5);
+        assert_eq!(table_bits(256), 8);
+    }
+}
@@ -0,0 +1,666 @@
+use std::str::FromStr;
+
+use crate::color::{linear_to_srgb, srgb_to_linear, LinearColor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    Smoothstep,
+    CubicSpline,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "smoothstep" => Ok(Self::Smoothstep),
+            "cubic" => Ok(Self::CubicSpline),
+            _ => Err(format!(
+                "unknown interpolation '{}' (expected linear, smoothstep or cubic)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    LinearRgb,
+    Oklab,
+    Oklch,
+    Hsv,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Self::Srgb),
+            "linear" => Ok(Self::LinearRgb),
+            "oklab" => Ok(Self::Oklab),
+            "oklch" => Ok(Self::Oklch),
+            "hsv" => Ok(Self::Hsv),
+            _ => Err(format!(
+                "unknown color space '{}' (expected srgb, linear, oklab, oklch or hsv)",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorSpace {
+    /// Index of the hue channel, which is stored in turns (`[0, 1)`).
+    fn hue_channel(self) -> Option<usize> {
+        match self {
+            ColorSpace::Oklch => Some(2),
+            ColorSpace::Hsv => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Index of the channel telling whether the hue is meaningful.
+    fn chroma_channel(self) -> Option<usize> {
+        match self {
+            ColorSpace::Oklch => Some(1),
+            ColorSpace::Hsv => Some(1),
+            _ => None,
+        }
+    }
+
+    fn encode(self, color: LinearColor) -> [f32; 4] {
+        let LinearColor { r, g, b, a } = color;
+        let [x, y, z] = match self {
+            ColorSpace::Srgb => [r, g, b].map(linear_to_srgb),
+            ColorSpace::LinearRgb => [r, g, b],
+            ColorSpace::Oklab => linear_to_oklab([r, g, b]),
+            ColorSpace::Oklch => oklab_to_oklch(linear_to_oklab([r, g, b])),
+            ColorSpace::Hsv => {
+                let [r, g, b] = [r, g, b].map(linear_to_srgb);
+                rgb_to_hsv(r, g, b).into()
+            }
+        };
+        [x, y, z, a]
+    }
+
+    fn decode(self, [x, y, z, a]: [f32; 4]) -> LinearColor {
+        let [r, g, b] = match self {
+            ColorSpace::Srgb => [x, y, z].map(srgb_to_linear),
+            ColorSpace::LinearRgb => [x, y, z],
+            ColorSpace::Oklab => oklab_to_linear([x, y, z]),
+            ColorSpace::Oklch => oklab_to_linear(oklch_to_oklab([x, y, z])),
+            ColorSpace::Hsv => {
+                let (r, g, b) = hsv_to_rgb(x, y, z);
+                [r, g, b].map(srgb_to_linear)
+            }
+        };
+        LinearColor::new(r.max(0.0), g.max(0.0), b.max(0.0), a.clamp(0.0, 1.0))
+    }
+}
+
+/// A color gradient made of stops at arbitrary positions in `[0, 1]`.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<(f32, LinearColor)>,
+    interpolation: Interpolation,
+    color_space: ColorSpace,
+
+    // Stops converted to `color_space`, with hues unwrapped along the
+    // shortest path between consecutive stops
+    points: Vec<[f32; 4]>,
+}
+
+impl Gradient {
+    /// Build a gradient from `(position, color)` stops.
+    ///
+    /// Stops are sorted by position; at least two are required.
+    pub fn new<C: Into<LinearColor>>(stops: Vec<(f32, C)>) -> Result<Self, String> {
+        let mut stops: Vec<(f32, LinearColor)> =
+            stops.into_iter().map(|(p, c)| (p, c.into())).collect();
+        if stops.len() < 2 {
+            return Err("a gradient needs at least two stops".into());
+        }
+        if stops.iter().any(|&(p, _)| !(0.0..=1.0).contains(&p)) {
+            return Err("gradient stop positions must be in [0, 1]".into());
+        }
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut gradient = Self {
+            stops,
+            interpolation: Interpolation::default(),
+            color_space: ColorSpace::default(),
+            points: Vec::new(),
+        };
+        gradient.update_points();
+        Ok(gradient)
+    }
+
+    /// Spread `colors` evenly over `[0, 1]`.
+    pub fn evenly_spaced<C: Into<LinearColor> + Copy>(colors: &[C]) -> Self {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        let stops: Vec<(f32, LinearColor)> = match colors {
+            [] => vec![(0.0, LinearColor::BLACK), (1.0, LinearColor::BLACK)],
+            [color] => vec![(0.0, (*color).into()), (1.0, (*color).into())],
+            _ => colors
+                .iter()
+                .enumerate()
+                .map(|(i, &color)| (i as f32 / last, color.into()))
+                .collect(),
+        };
+        Self::new(stops).expect("evenly spaced stops are valid")
+    }
+
+    #[cfg(test)]
+    pub fn stops(&self) -> &[(f32, LinearColor)] {
+        &self.stops
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self.update_points();
+        self
+    }
+
+    fn update_points(&mut self) {
+        let space = self.color_space;
+        self.points = self
+            .stops
+            .iter()
+            .map(|&(_, color)| space.encode(color))
+            .collect();
+
+        let (Some(hue), Some(chroma)) = (space.hue_channel(), space.chroma_channel()) else {
+            return;
+        };
+
+        // Grey stops have no hue, borrow the one of the closest colored stop
+        // so they don't drag the gradient through unrelated hues.
+        let colored: Vec<usize> = (0..self.points.len())
+            .filter(|&i| self.points[i][chroma] > 1e-4)
+            .collect();
+        if colored.is_empty() {
+            return;
+        }
+        for i in 0..self.points.len() {
+            if self.points[i][chroma] <= 1e-4 {
+                let nearest = colored
+                    .iter()
+                    .min_by_key(|&&j| j.abs_diff(i))
+                    .copied()
+                    .unwrap_or(i);
+                self.points[i][hue] = self.points[nearest][hue];
+            }
+        }
+
+        // Unwrap so consecutive hues never differ by more than half a turn
+        for i in 1..self.points.len() {
+            let prev = self.points[i - 1][hue];
+            let delta = (self.points[i][hue] - prev + 0.5).rem_euclid(1.0) - 0.5;
+            self.points[i][hue] = prev + delta;
+        }
+    }
+
+    /// Color at position `t` in `[0, 1]`.
+    pub fn sample(&self, t: f32) -> LinearColor {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let positions = &self.stops;
+        let last = positions.len() - 1;
+
+        // Index of the segment `[k, k + 1]` containing `t`
+        let k = positions[1..]
+            .iter()
+            .position(|&(p, _)| t <= p)
+            .unwrap_or(last - 1);
+
+        let (t0, t1) = (positions[k].0, positions[k + 1].0);
+        let u = if t1 - t0 > f32::EPSILON {
+            ((t - t0) / (t1 - t0)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let p0 = self.points[k];
+        let p1 = self.points[k + 1];
+
+        let mut value = [0f32; 4];
+        match self.interpolation {
+            Interpolation::Linear => {
+                for c in 0..4 {
+                    value[c] = p0[c] + (p1[c] - p0[c]) * u;
+                }
+            }
+            Interpolation::Smoothstep => {
+                let u = u * u * (3.0 - 2.0 * u);
+                for c in 0..4 {
+                    value[c] = p0[c] + (p1[c] - p0[c]) * u;
+                }
+            }
+            Interpolation::CubicSpline => {
+                // Catmull-Rom tangents adapted to non-uniform stop positions
+                let dt = t1 - t0;
+                let tangent = |i: usize, c: usize| -> f32 {
+                    let (a, b) = (i.saturating_sub(1), (i + 1).min(last));
+                    let span = positions[b].0 - positions[a].0;
+                    if span > f32::EPSILON {
+                        (self.points[b][c] - self.points[a][c]) / span
+                    } else {
+                        0.0
+                    }
+                };
+
+                let (u2, u3) = (u * u, u * u * u);
+                let h00 = 2.0 * u3 - 3.0 * u2 + 1.0;
+                let h10 = u3 - 2.0 * u2 + u;
+                let h01 = -2.0 * u3 + 3.0 * u2;
+                let h11 = u3 - u2;
+                for c in 0..4 {
+                    value[c] = h00 * p0[c]
+                        + h10 * dt * tangent(k, c)
+                        + h01 * p1[c]
+                        + h11 * dt * tangent(k + 1, c);
+                }
+            }
+        }
+
+        if let Some(hue) = self.color_space.hue_channel() {
+            value[hue] = value[hue].rem_euclid(1.0);
+        }
+        self.color_space.decode(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    #[default]
+    Clamp,
+}
+
+impl FromStr for WrapMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "repeat" => Ok(Self::Repeat),
+            "mirror" => Ok(Self::Mirror),
+            "clamp" => Ok(Self::Clamp),
+            _ => Err(format!(
+                "unknown wrap mode '{}' (expected repeat, mirror or clamp)",
+                s
+            )),
+        }
+    }
+}
+
+/// How iteration counts are mapped onto a gradient position.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mapping {
+    /// Shift of the gradient, in gradient lengths.
+    pub offset: f32,
+    /// Number of iterations for one full gradient cycle, `max_iter` if unset.
+    pub period: Option<f32>,
+    pub wrap: WrapMode,
+}
+
+impl Mapping {
+    /// Gradient position in `[0, 1]` of the `n`th iteration.
+    pub fn position(&self, n: f32, max_iter: u32) -> f32 {
+        let period = self.period.unwrap_or(max_iter as f32).max(f32::EPSILON);
+        let t = n / period + self.offset;
+
+        match self.wrap {
+            WrapMode::Repeat => t.rem_euclid(1.0),
+            WrapMode::Mirror => 1.0 - (t.rem_euclid(2.0) - 1.0).abs(),
+            WrapMode::Clamp => t.clamp(0.0, 1.0),
+        }
+    }
+}
+
+// Reference matrices from https://bottosson.github.io/posts/oklab/
+#[allow(clippy::excessive_precision)]
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+#[allow(clippy::excessive_precision)]
+fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+}
+
+fn oklab_to_oklch([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let h = b.atan2(a) / std::f32::consts::TAU;
+    [l, (a * a + b * b).sqrt(), h.rem_euclid(1.0)]
+}
+
+fn oklch_to_oklab([l, c, h]: [f32; 3]) -> [f32; 3] {
+    let h = h * std::f32::consts::TAU;
+    [l, c * h.cos(), c * h.sin()]
+}
+
+/// sRGB to HSV, every component in `[0, 1]`.
+pub fn rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta < f32::EPSILON {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let s = if max < f32::EPSILON { 0.0 } else { delta / max };
+
+    (h, s, max)
+}
+
+/// HSV to sRGB, every component in `[0, 1]`.
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    (r + m, g + m, b + m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: [ColorSpace; 5] = [
+        ColorSpace::Srgb,
+        ColorSpace::LinearRgb,
+        ColorSpace::Oklab,
+        ColorSpace::Oklch,
+        ColorSpace::Hsv,
+    ];
+
+    const INTERPOLATIONS: [Interpolation; 3] = [
+        Interpolation::Linear,
+        Interpolation::Smoothstep,
+        Interpolation::CubicSpline,
+    ];
+
+    fn srgb(r: f32, g: f32, b: f32) -> LinearColor {
+        LinearColor::from_srgb([r, g, b, 1.0])
+    }
+
+    fn hsv(h: f32, s: f32, v: f32) -> LinearColor {
+        let (r, g, b) = hsv_to_rgb(h, s, v);
+        srgb(r, g, b)
+    }
+
+    fn assert_srgb(color: LinearColor, expected: [f32; 3]) {
+        let [r, g, b, _] = color.to_srgb();
+        for (value, expected) in [r, g, b].into_iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 0.01,
+                "{:?} != {:?}",
+                [r, g, b],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn stops_are_sorted_and_validated() {
+        let gradient =
+            Gradient::new(vec![(1.0, srgb(0.0, 0.0, 1.0)), (0.0, srgb(1.0, 0.0, 0.0))]).unwrap();
+        assert_eq!(gradient.stops()[0].0, 0.0);
+        assert_eq!(gradient.stops()[1].0, 1.0);
+
+        assert!(Gradient::new(vec![(0.0, LinearColor::BLACK)]).is_err());
+        assert!(Gradient::new(vec![(0.0, LinearColor::BLACK), (1.5, LinearColor::BLACK)]).is_err());
+        assert!(Gradient::new(vec![
+            (f32::NAN, LinearColor::BLACK),
+            (1.0, LinearColor::BLACK)
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn stops_are_reached_exactly() {
+        let colors = [
+            srgb(0.9, 0.1, 0.2),
+            srgb(0.2, 0.8, 0.3),
+            srgb(0.1, 0.2, 0.7),
+            srgb(0.5, 0.5, 0.5),
+        ];
+        let expected = colors.map(|c| {
+            let [r, g, b, _] = c.to_srgb();
+            [r, g, b]
+        });
+        for space in SPACES {
+            for interpolation in INTERPOLATIONS {
+                let gradient = Gradient::evenly_spaced(&colors)
+                    .with_color_space(space)
+                    .with_interpolation(interpolation);
+                for (i, &expected) in expected.iter().enumerate() {
+                    assert_srgb(gradient.sample(i as f32 / 3.0), expected);
+                }
+                // Out of range positions stick to the ends
+                assert_srgb(gradient.sample(-1.0), expected[0]);
+                assert_srgb(gradient.sample(f32::NAN), expected[0]);
+                assert_srgb(gradient.sample(2.0), expected[3]);
+            }
+        }
+    }
+
+    #[test]
+    fn color_spaces_round_trip() {
+        let colors = [
+            srgb(0.0, 0.0, 0.0),
+            srgb(1.0, 1.0, 1.0),
+            srgb(0.9, 0.1, 0.2),
+            srgb(0.2, 0.8, 0.3),
+            srgb(0.1, 0.2, 0.7),
+        ];
+        for space in SPACES {
+            for color in colors {
+                let back = space.decode(space.encode(color));
+                let [r, g, b, _] = color.to_srgb();
+                assert_srgb(back, [r, g, b]);
+            }
+        }
+
+        assert_eq!(rgb_to_hsv(1.0, 0.0, 0.0), (0.0, 1.0, 1.0));
+        assert_eq!(rgb_to_hsv(0.0, 0.5, 0.5), (0.5, 1.0, 0.5));
+        assert_eq!(hsv_to_rgb(2.0 / 3.0, 1.0, 1.0), (0.0, 0.0, 1.0));
+        assert_eq!(hsv_to_rgb(0.25, 0.0, 0.5), (0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn color_spaces_change_the_midpoint() {
+        let red_to_blue =
+            Gradient::new(vec![(0.0, srgb(1.0, 0.0, 0.0)), (1.0, srgb(0.0, 0.0, 1.0))]).unwrap();
+
+        // Halfway in sRGB, and halfway in linear light which is brighter
+        assert_srgb(red_to_blue.sample(0.5), [0.5, 0.0, 0.5]);
+        let mid = red_to_blue
+            .clone()
+            .with_color_space(ColorSpace::LinearRgb)
+            .sample(0.5);
+        assert!((mid.r - 0.5).abs() < 1e-6 && (mid.b - 0.5).abs() < 1e-6);
+
+        // Hue goes the short way from red to blue, through magenta
+        let mid = red_to_blue.with_color_space(ColorSpace::Hsv).sample(0.5);
+        assert_srgb(mid, [1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn interpolation_modes() {
+        let black_to_white =
+            Gradient::new(vec![(0.0, srgb(0.0, 0.0, 0.0)), (1.0, srgb(1.0, 1.0, 1.0))])
+                .unwrap()
+                .with_color_space(ColorSpace::LinearRgb);
+        assert!((black_to_white.sample(0.25).r - 0.25).abs() < 1e-6);
+
+        let smooth = black_to_white.with_interpolation(Interpolation::Smoothstep);
+        assert!((smooth.sample(0.25).r - 0.15625).abs() < 1e-6);
+        assert!((smooth.sample(0.5).r - 0.5).abs() < 1e-6);
+
+        // Catmull-Rom reproduces evenly rising stops, and overshoots none of
+        // them on a monotonic ramp with uneven spacing
+        let ramp = Gradient::new(vec![
+            (0.0, LinearColor::new(0.0, 0.0, 0.0, 1.0)),
+            (0.5, LinearColor::new(0.5, 0.5, 0.5, 1.0)),
+            (1.0, LinearColor::new(1.0, 1.0, 1.0, 1.0)),
+        ])
+        .unwrap()
+        .with_color_space(ColorSpace::LinearRgb)
+        .with_interpolation(Interpolation::CubicSpline);
+        for t in [0.1, 0.3, 0.6, 0.9] {
+            assert!((ramp.sample(t).r - t).abs() < 1e-5, "{}", t);
+        }
+
+        let uneven = Gradient::new(vec![
+            (0.0, LinearColor::new(0.0, 0.0, 0.0, 1.0)),
+            (0.2, LinearColor::new(0.2, 0.2, 0.2, 1.0)),
+            (1.0, LinearColor::new(1.0, 1.0, 1.0, 1.0)),
+        ])
+        .unwrap()
+        .with_color_space(ColorSpace::LinearRgb)
+        .with_interpolation(Interpolation::CubicSpline);
+        let mut previous = 0.0;
+        for i in 1..=100 {
+            let value = uneven.sample(i as f32 / 100.0).r;
+            assert!(value >= previous - 1e-6 && value <= 1.0 + 1e-6);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn hue_wraps_the_short_way() {
+        // From hue 0.9 to hue 0.1 the short way goes across red, not through
+        // green and cyan
+        for interpolation in INTERPOLATIONS {
+            let gradient =
+                Gradient::new(vec![(0.0, hsv(0.9, 1.0, 1.0)), (1.0, hsv(0.1, 1.0, 1.0))])
+                    .unwrap()
+                    .with_color_space(ColorSpace::Hsv)
+                    .with_interpolation(interpolation);
+            assert_srgb(gradient.sample(0.5), [1.0, 0.0, 0.0]);
+            for i in 0..=20 {
+                let [r, g, b, _] = gradient.sample(i as f32 / 20.0).to_srgb();
+                let (h, _, _) = rgb_to_hsv(r, g, b);
+                assert!(!(0.11..0.89).contains(&h), "hue {} at {}", h, i);
+            }
+        }
+
+        let oklch = Gradient::new(vec![(0.0, hsv(0.9, 1.0, 1.0)), (1.0, hsv(0.05, 1.0, 1.0))])
+            .unwrap()
+            .with_color_space(ColorSpace::Oklch);
+        for i in 0..=20 {
+            let [r, g, b, _] = oklch.sample(i as f32 / 20.0).to_srgb();
+            assert!(g < r.max(b), "{:?} at {}", [r, g, b], i);
+        }
+    }
+
+    #[test]
+    fn grey_stops_borrow_the_hue() {
+        // White has no hue, the gradient stays on the hue of green instead of
+        // sweeping from red
+        let gradient = Gradient::new(vec![(0.0, srgb(1.0, 1.0, 1.0)), (1.0, srgb(0.0, 1.0, 0.0))])
+            .unwrap()
+            .with_color_space(ColorSpace::Hsv);
+        assert_srgb(gradient.sample(0.5), [0.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn wrap_modes_outside_of_the_gradient() {
+        let position = |wrap, n| {
+            Mapping {
+                offset: 0.0,
+                period: Some(10.0),
+                wrap,
+            }
+            .position(n, 1000)
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+        for wrap in [WrapMode::Repeat, WrapMode::Mirror, WrapMode::Clamp] {
+            assert!(close(position(wrap, 0.0), 0.0));
+            assert!(close(position(wrap, 5.0), 0.5));
+        }
+
+        assert!(close(position(WrapMode::Repeat, -2.0), 0.8));
+        assert!(close(position(WrapMode::Repeat, 12.0), 0.2));
+        assert!(close(position(WrapMode::Repeat, 35.0), 0.5));
+
+        assert!(close(position(WrapMode::Mirror, -2.0), 0.2));
+        assert!(close(position(WrapMode::Mirror, 12.0), 0.8));
+        assert!(close(position(WrapMode::Mirror, 20.0), 0.0));
+
+        assert!(close(position(WrapMode::Clamp, -2.0), 0.0));
+        assert!(close(position(WrapMode::Clamp, 12.0), 1.0));
+    }
+
+    #[test]
+    fn offset_and_period() {
+        let mapping = Mapping {
+            offset: 0.25,
+            period: None,
+            wrap: WrapMode::Repeat,
+        };
+        // Without a period one cycle spans max_iter
+        assert!((mapping.position(0.0, 100) - 0.25).abs() < 1e-6);
+        assert!((mapping.position(50.0, 100) - 0.75).abs() < 1e-6);
+        assert!((mapping.position(100.0, 100) - 0.25).abs() < 1e-6);
+
+        let zero = Mapping {
+            period: Some(0.0),
+            ..Mapping::default()
+        };
+        assert_eq!(zero.position(3.0, 100), 1.0);
+        assert_eq!(zero.position(0.0, 100), 0.0);
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        assert_eq!("cubic".parse(), Ok(Interpolation::CubicSpline));
+        assert_eq!("oklch".parse(), Ok(ColorSpace::Oklch));
+        assert!("cubical".parse::<Interpolation>().is_err());
+        assert!("lab".parse::<ColorSpace>().is_err());
+        assert_eq!("mirror".parse(), Ok(WrapMode::Mirror));
+        assert!("wrap".parse::<WrapMode>().is_err());
+    }
+}
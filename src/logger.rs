@@ -27,7 +27,7 @@ impl log::Log for SimpleLogger {
         }
 
         if self.enabled(record.metadata()) {
-            println!("[{}] {}", record.level(), record.args());
+            eprintln!("[{}] {}", record.level(), record.args());
         }
     }
 
@@ -1,18 +1,59 @@
+mod animation;
+mod buddhabrot;
+mod checkpoint;
+mod cli;
+mod color;
 mod complex;
+mod deep;
+mod deflate;
+mod expmap;
+mod export;
+mod expression;
+mod formula;
+mod gif;
+mod gradient;
 mod logger;
 mod mandelbrot;
+mod newton;
+mod palette;
+mod plane;
+mod plugin;
+mod png;
+mod poster;
+mod quantize;
 mod render;
+mod sampling;
+mod script;
+mod tiff;
 
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
+use animation::{Animation, Keyframe};
+use buddhabrot::{Buddhabrot, Channel, DensityMode};
+use checkpoint::Checkpoint;
 use complex::Complex;
-use pixels::Error;
+use deep::{DoubleDouble, ReferenceOrbit};
+use expmap::ExpMap;
+use export::{FrameFormat, Y4mWriter};
+use formula::{Formula, FormulaKind};
+use gif::GifWriter;
+use gradient::{Gradient, Mapping};
 
-use mandelbrot::{MandelbrotUniverse, PixelColor};
+use mandelbrot::{MandelbrotUniverse, PixelColor, ViewPort};
+use plane::PlaneTransform;
+use plugin::Plugin;
+use poster::Poster;
+use sampling::{AdaptiveAa, Sampler};
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 600;
 const MAX_ITER: u32 = 1024;
+const RANDOM_PALETTE_COLORS: usize = 8;
 
 const COLORS: &[PixelColor] = &[
     PixelColor::MAGENTA,
@@ -37,68 +78,516 @@ const COLORS: &[PixelColor] = &[
     PixelColor::BLACK,
 ];
 
-fn mandelbrot(c: Complex<f64>, max_iter: u32) -> u32 {
-    let mut z = Complex::new(0.0, 0.0);
-    let mut n = 0;
-    while (z.re + z.im) <= 4.0 && n < max_iter {
-        z = z * z + c;
-        n += 1;
+fn main() {
+    logger::init(log::LevelFilter::Trace).expect("Failed to initialize logger");
+
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(args) if args.help => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(args) {
+        log::error!("{}", err);
+        std::process::exit(1);
     }
-    n
 }
 
-fn mandelbrot_fast(c: Complex<f64>, max_iter: u32) -> u32 {
-    // Center check
-    if (c.re + 1.0).powi(2) + c.im.powi(2) < 0.0625 {
-        return max_iter;
+fn run(args: cli::Args) -> Result<(), Box<dyn std::error::Error>> {
+    let gradient = if let Some(path) = &args.palette {
+        palette::load(path)
+            .map_err(|err| format!("Failed to load palette {}: {}", path.display(), err))?
+    } else if let Some(spec) = &args.cosine_palette {
+        palette::parse_cosine(spec).map_err(|err| format!("Invalid cosine palette: {}", err))?
+    } else if let Some(seed) = args.random_palette {
+        palette::random(seed, RANDOM_PALETTE_COLORS)
+    } else if args.density.is_some() {
+        Gradient::evenly_spaced(&[PixelColor::BLACK, PixelColor::WHITE])
+    } else {
+        Gradient::evenly_spaced(COLORS)
+    }
+    .with_interpolation(args.interpolation)
+    .with_color_space(args.color_space);
+    let mapping = Mapping {
+        offset: args.palette_offset,
+        period: args.palette_period,
+        wrap: args.palette_wrap,
+    };
+
+    let threads = thread::available_parallelism()
+        .map(|t| t.get())
+        .unwrap_or(1);
+    let workers = (2.5 * threads as f32) as usize;
+    let max_iter = args.max_iter.unwrap_or(MAX_ITER);
+
+    let (width, height) = args.size.unwrap_or((WIDTH, HEIGHT));
+
+    if let Some(path) = &args.exp_map {
+        return render_exp_map(&args, path, &gradient, mapping, workers, max_iter);
+    }
+
+    if let Some(mode) = args.density {
+        return render_density(&args, mode, &gradient, threads, max_iter);
+    }
+
+    let plugin = match &args.plugin {
+        Some(path) => {
+            let plugin = Plugin::load(path, &args.plugin_params)
+                .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?;
+            log::info!("Plugin {}: {}", plugin.name(), plugin.description());
+            Some(Formula::Plugin(Arc::new(plugin)))
+        }
+        None => None,
+    };
+    let expression = args.expression.clone().map(Formula::Expression);
+    let custom = plugin.or(expression);
+    let formula = custom
+        .clone()
+        .unwrap_or_else(|| make_formula(&args, args.formula));
+
+    // Posters are computed a tile at a time, never at their full size
+    let (universe_width, universe_height) = match args.poster {
+        Some(_) => (args.tile_size, args.tile_size),
+        None => (width, height),
+    };
+    let mut universe = MandelbrotUniverse::new(
+        universe_width,
+        universe_height,
+        workers,
+        &gradient,
+        mapping,
+        max_iter,
+        formula::mandelbrot_fast,
+    );
+    formula.use_in(&mut universe);
+    let (center, scale) = start_view(&args, &formula);
+    universe.set_view(make_view(&args, center, scale, args.rotation));
+    universe.set_dither(args.dither);
+    set_sampling(&mut universe, &args);
+
+    if let Some(path) = &args.script {
+        let settings = script::Settings {
+            gradient,
+            mapping,
+            max_iter,
+            threads: workers,
+            view: *universe.view(),
+            sampler: universe.sampler().clone(),
+            dither: args.dither,
+            formula,
+            formulas: FormulaKind::ALL
+                .iter()
+                .map(|&kind| make_formula(&args, kind))
+                .collect(),
+        };
+        return script::run(path, settings);
     }
 
-    // Cardoid check
-    let p = ((c.re - 0.25).powi(2) + c.im.powi(2)).sqrt();
-    if c.re < (p - 2.0 * p.powi(2) + 0.25) {
-        return max_iter;
+    if let Some(path) = &args.animate {
+        let initial = Keyframe {
+            time: 0.0,
+            center,
+            scale,
+            rotation: args.rotation,
+            max_iter,
+            palette_offset: args.palette_offset,
+            easing: Default::default(),
+        };
+        let animation = Animation::load(path, initial)
+            .map_err(|err| format!("Failed to load keyframes {}: {}", path.display(), err))?;
+        return render_animation(&args, &animation, universe, mapping);
     }
 
-    // Compute
-    let mut z = Complex::new(0.0, 0.0);
-    let mut n = 0;
-    while (z.re + z.im) <= 4.0 && n < max_iter {
-        z = z * z + c;
-        n += 1;
+    if let Some(path) = &args.poster {
+        let view = make_view(&args, center, scale, args.rotation);
+        let mut poster = Poster::new(width, height, args.tile_size, view);
+        if let Some(checkpoint_path) = &args.checkpoint {
+            let params = render_params(&args, &formula, width, height, center, scale, max_iter);
+            let checkpoint = if args.resume {
+                Checkpoint::resume(checkpoint_path, &params)
+            } else {
+                Checkpoint::create(checkpoint_path, &params)
+            }
+            .map_err(|err| format!("Checkpoint {}: {}", checkpoint_path.display(), err))?;
+            poster.set_checkpoint(checkpoint);
+        }
+        return poster
+            .render(&mut universe, path)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err).into());
     }
-    n
+
+    universe.compute();
+
+    println!("Running on {} threads", threads);
+    println!("Press C to start/stop palette cycling, R to reverse it, +/- to change its speed");
+    println!("Press A to toggle anti-aliasing, D to highlight adaptively refined pixels");
+    println!("Press Q/E or drag with the right mouse button to rotate the view");
+    println!("Press F to switch to the next formula");
+    println!(
+        "Press the arrow keys to change the phoenix p, the newton and nova relaxation or the \
+         first parameter of an expression"
+    );
+    println!("Press ESC to exit");
+    println!();
+
+    // The formulas the viewer cycles through, starting with this one, a
+    // plugin or an expression comes before the built-in ones
+    let start = FormulaKind::ALL
+        .iter()
+        .position(|&kind| kind == args.formula)
+        .unwrap_or(0);
+    let built_in = FormulaKind::ALL
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(FormulaKind::ALL.len())
+        .map(|&kind| make_formula(&args, kind));
+    let formulas = custom.into_iter().chain(built_in).collect();
+    render::render(universe, width, height, formulas)?;
+    Ok(())
+}
+
+fn make_formula(args: &cli::Args, kind: FormulaKind) -> Formula {
+    Formula::new(
+        kind,
+        args.power,
+        &args.polynomial,
+        args.relaxation,
+        args.phoenix,
+        args.bailout,
+    )
+}
+
+fn plane_transform(args: &cli::Args) -> PlaneTransform {
+    PlaneTransform::new(
+        args.plane,
+        args.plane_mu,
+        args.plane_shift,
+        args.plane_tilt.to_radians(),
+    )
+}
+
+/// Center and scale asked on the command line, by default framing the whole
+/// set of `formula` through the plane transform.
+fn start_view(args: &cli::Args, formula: &Formula) -> (Complex<f64>, f64) {
+    let (center, scale) = plane_transform(args)
+        .default_view()
+        .unwrap_or(formula.default_view());
+    (
+        args.center.map(Complex::to_f64).unwrap_or(center),
+        args.scale.unwrap_or(scale),
+    )
 }
 
-fn mandelbrot_cos(c: Complex<f64>, max_iter: u32) -> u32 {
-    let mut z = Complex::new(0.0, 0.0);
-    let mut n = 0;
-    while (z.re + z.im) <= 4.0 && n < max_iter {
-        z = z.cos() + Complex::new(1.0, 0.0) / c;
-        n += 1;
+/// View at `center` and `scale`, rotated by `rotation` degrees, with the
+/// transforms of the command line.
+fn make_view(args: &cli::Args, center: Complex<f64>, scale: f64, rotation: f64) -> ViewPort {
+    let mut view = ViewPort::new(center, scale);
+    view.set_rotation(rotation.to_radians());
+    if let Some(transform) = args.transform {
+        view.set_transform(transform);
     }
-    n
+    view.set_plane(plane_transform(args));
+    view
 }
 
-fn main() -> Result<(), Error> {
-    logger::init(log::LevelFilter::Trace).expect("Failed to initialize logger");
+/// Everything that changes the pixels of a tiled render, one `key=value` per
+/// line, so a checkpoint is only resumed by the same render.
+fn render_params(
+    args: &cli::Args,
+    formula: &Formula,
+    width: u32,
+    height: u32,
+    center: Complex<f64>,
+    scale: f64,
+    max_iter: u32,
+) -> String {
+    let params = [
+        ("size", format!("{}x{}", width, height)),
+        ("tile-size", args.tile_size.to_string()),
+        ("center", format!("{:?},{:?}", center.re, center.im)),
+        ("scale", format!("{:?}", scale)),
+        ("rotation", format!("{:?}", args.rotation)),
+        ("transform", format!("{:?}", args.transform)),
+        ("max-iter", max_iter.to_string()),
+        ("formula", format!("{:?}", formula)),
+        ("plane", format!("{:?}", plane_transform(args))),
+        ("palette", format!("{:?}", args.palette)),
+        ("cosine-palette", format!("{:?}", args.cosine_palette)),
+        ("random-palette", format!("{:?}", args.random_palette)),
+        ("palette-offset", format!("{:?}", args.palette_offset)),
+        ("palette-period", format!("{:?}", args.palette_period)),
+        ("palette-wrap", format!("{:?}", args.palette_wrap)),
+        ("interpolation", format!("{:?}", args.interpolation)),
+        ("color-space", format!("{:?}", args.color_space)),
+        ("dither", format!("{:?}", args.dither)),
+        ("aa", args.aa.to_string()),
+        ("aa-pattern", format!("{:?}", args.aa_pattern)),
+        ("aa-seed", args.aa_seed.to_string()),
+        ("aa-adaptive", format!("{:?}", args.aa_adaptive)),
+        ("aa-iter-threshold", format!("{:?}", args.aa_iter_threshold)),
+        ("aa-debug", args.aa_debug.to_string()),
+    ];
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
 
-    let threads = thread::available_parallelism()
-        .map(|t| t.get())
-        .unwrap_or(1);
+fn set_sampling(universe: &mut MandelbrotUniverse, args: &cli::Args) {
+    let sampler = Sampler::new(args.aa_pattern, args.aa, args.aa_seed);
+    if let Some(color_threshold) = args.aa_adaptive {
+        // Single sample first pass, the requested pattern refines the edges
+        universe.set_adaptive(Some(AdaptiveAa {
+            sampler,
+            color_threshold,
+            iter_threshold: args.aa_iter_threshold,
+        }));
+    } else {
+        universe.set_sampler(sampler);
+    }
+    universe.set_show_refined(args.aa_debug);
+}
+
+/// Render the exponential map strip of a zoom on `--center` and save it,
+/// without opening the viewer.
+fn render_exp_map(
+    args: &cli::Args,
+    path: &Path,
+    gradient: &Gradient,
+    mapping: Mapping,
+    workers: usize,
+    max_iter: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let default_view = ViewPort::default();
+    let center = args.center.unwrap_or(Complex::new(
+        DoubleDouble::new(default_view.center().re),
+        DoubleDouble::new(default_view.center().im),
+    ));
+    let strip = ExpMap::new(
+        args.exp_map_width,
+        args.scale.unwrap_or(default_view.scale()),
+        args.exp_map_zoom,
+    );
+    let (width, height) = strip.size();
 
+    let checkpoint = match &args.checkpoint {
+        Some(checkpoint_path) => {
+            let formula = make_formula(args, args.formula);
+            let scale = args.scale.unwrap_or(default_view.scale());
+            let mut params = render_params(
+                args,
+                &formula,
+                width,
+                height,
+                center.to_f64(),
+                scale,
+                max_iter,
+            );
+            // The center at full precision
+            params.push_str(&format!("exp-map-center={:?}\n", center));
+            let checkpoint = if args.resume {
+                Checkpoint::resume(checkpoint_path, &params)
+            } else {
+                Checkpoint::create(checkpoint_path, &params)
+            }
+            .map_err(|err| format!("Checkpoint {}: {}", checkpoint_path.display(), err))?;
+            Some(checkpoint)
+        }
+        None => None,
+    };
+
+    // Pixels are iterated relative to the center, at any zoom depth
+    let orbit = ReferenceOrbit::new(center, max_iter);
     let mut universe = MandelbrotUniverse::new(
-        WIDTH,
-        HEIGHT,
-        (2.5 * threads as f32) as usize,
-        COLORS,
-        MAX_ITER,
-        mandelbrot_fast,
+        width,
+        args.tile_size.min(height),
+        workers,
+        gradient,
+        mapping,
+        max_iter,
+        move |dc, max_iter| orbit.iterate(dc, max_iter),
     );
-    universe.compute();
+    universe.set_dither(args.dither);
+    set_sampling(&mut universe, args);
 
-    println!("Running on {} threads", threads);
-    println!("Press ESC to exit");
-    println!("");
+    strip
+        .render(&mut universe, args.tile_size, checkpoint, center, path)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+    println!(
+        "Exponential map ({}×{}) written to {}",
+        width,
+        height,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Trace the orbit density of `mode`: `--samples` points written to
+/// `--output`, or in the viewer until it is closed.
+fn render_density(
+    args: &cli::Args,
+    mode: DensityMode,
+    gradient: &Gradient,
+    threads: usize,
+    max_iter: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = args.size.unwrap_or((WIDTH, HEIGHT));
+    let (center, scale) = start_view(args, &make_formula(args, FormulaKind::Mandelbrot));
+    let view = make_view(args, center, scale, args.rotation);
+    let channels = if args.channels.is_empty() {
+        vec![Channel::new(max_iter)]
+    } else {
+        args.channels.clone()
+    };
+    let mut buddhabrot = Buddhabrot::new(
+        mode,
+        width,
+        height,
+        &view,
+        channels,
+        args.density_seed,
+        gradient,
+    );
+    buddhabrot.set_dither(args.dither);
+    buddhabrot.set_sampling(args.density_sampling);
 
-    render::render(universe, WIDTH, HEIGHT)
+    let Some(samples) = args.samples else {
+        println!("Running on {} threads", threads);
+        println!("Press ESC to exit");
+        println!();
+        render::render_density(Arc::new(buddhabrot), threads)?;
+        return Ok(());
+    };
+
+    let start = Instant::now();
+    buddhabrot.run(threads, samples);
+    log::info!("{} in {:.1?}", buddhabrot.statistics(), start.elapsed());
+
+    let path = args.output.clone().unwrap_or_else(|| "density.png".into());
+    let written = if args.bit_depth == 16 {
+        export::write_image16(&path, width, height, &buddhabrot.render16())
+    } else {
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        buddhabrot.render(&mut rgba);
+        export::write_image(&path, width, height, &rgba)
+    };
+    written.map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+    Ok(())
+}
+
+/// Render every frame of `animation` without opening the viewer, as an image
+/// sequence or a Y4M stream.
+fn render_animation(
+    args: &cli::Args,
+    animation: &Animation,
+    mut universe: MandelbrotUniverse,
+    mapping: Mapping,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = universe.size();
+    let frames = args
+        .frames
+        .unwrap_or((animation.duration() * args.fps as f64).round() as u32 + 1);
+    let output = args.output.clone().unwrap_or_else(|| match args.format {
+        FrameFormat::Y4m => "-".into(),
+        FrameFormat::Gif => "animation.gif".into(),
+        _ => "frames".into(),
+    });
+
+    let mut sink = match args.format {
+        FrameFormat::Y4m => FrameSink::Y4m(Y4mWriter::new(
+            output_writer(&output)?,
+            width,
+            height,
+            args.fps,
+        )?),
+        FrameFormat::Gif => FrameSink::Gif(GifWriter::new(
+            output_writer(&output)?,
+            width,
+            height,
+            args.gif_loops,
+            args.gif_palette,
+        )?),
+        FrameFormat::Png | FrameFormat::Ppm => {
+            fs::create_dir_all(&output)?;
+            FrameSink::Sequence(output)
+        }
+    };
+
+    let mut rgba = vec![0; width as usize * height as usize * 4];
+    for frame in 0..frames {
+        let time = if frames > 1 {
+            animation.duration() * frame as f64 / (frames - 1) as f64
+        } else {
+            0.0
+        };
+        let keyframe = animation.at(time);
+
+        universe.set_view(make_view(
+            args,
+            keyframe.center,
+            keyframe.scale,
+            keyframe.rotation,
+        ));
+        universe.set_max_iter(keyframe.max_iter);
+        universe.set_mapping(Mapping {
+            offset: keyframe.palette_offset,
+            ..mapping
+        });
+        universe.compute();
+        universe.render(&mut rgba);
+
+        match &mut sink {
+            FrameSink::Sequence(dir) => {
+                let extension = match args.format {
+                    FrameFormat::Ppm => "ppm",
+                    _ => "png",
+                };
+                let path = dir.join(format!("frame_{:05}.{}", frame, extension));
+                let mut writer = BufWriter::new(File::create(&path)?);
+                match args.format {
+                    FrameFormat::Ppm => export::write_ppm(&mut writer, width, height, &rgba, &[])?,
+                    _ => export::write_png(writer, width, height, &rgba)?,
+                }
+            }
+            FrameSink::Y4m(y4m) => y4m.write_frame(&rgba)?,
+            FrameSink::Gif(gif) => {
+                // Delays are in hundredths of a second, round the frame end
+                // times rather than each delay so the rounding errors do not
+                // add up. The end times overflow 16 bits after 655s, only
+                // the delay has to fit
+                let end = |frame: u32| (100.0 * frame as f64 / args.fps as f64).round() as u64;
+                gif.write_frame(&rgba, (end(frame + 1) - end(frame)) as u16)?;
+            }
+        }
+        log::info!("Frame {}/{} (t = {:.2}s)", frame + 1, frames, time);
+    }
+
+    if let FrameSink::Gif(gif) = sink {
+        gif.finish()?;
+    }
+    Ok(())
+}
+
+/// Destination of the frames of an animation.
+enum FrameSink {
+    /// Numbered images in a directory.
+    Sequence(PathBuf),
+    Y4m(Y4mWriter<Box<dyn io::Write>>),
+    Gif(GifWriter<Box<dyn io::Write>>),
+}
+
+/// Buffered writer to `path`, the standard output for `-`.
+fn output_writer(path: &Path) -> io::Result<Box<dyn io::Write>> {
+    if path.as_os_str() == "-" {
+        Ok(Box::new(BufWriter::new(io::stdout().lock())))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
 }
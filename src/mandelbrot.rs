@@ -1,6 +1,23 @@
 use std::thread;
 
+use crate::color::{Dither, LinearColor};
 use crate::complex::Complex;
+use crate::gradient::{hsv_to_rgb, Gradient, Mapping};
+use crate::plane::PlaneTransform;
+use crate::sampling::{AdaptiveAa, Sampler};
+
+/// Marks a pixel without adaptive refinement samples.
+const NOT_REFINED: u32 = u32::MAX;
+/// Distinct root colors, they repeat after this many roots.
+const ROOT_COLORS: u32 = 16;
+const ROOT_SATURATION: f32 = 0.7;
+/// Brightness kept by a root color for each iteration the orbit took to
+/// converge, down to `MIN_ROOT_SHADE`.
+const ROOT_SHADE: f32 = 0.93;
+const MIN_ROOT_SHADE: f32 = 0.05;
+/// Orbits that converge without a root are colored with the gradient shifted
+/// by this, in gradient lengths, to stand apart from the escaping ones.
+const CONVERGED_OFFSET: f32 = 0.5;
 
 #[derive(Debug, Clone, Copy)]
 pub struct PixelColor {
@@ -23,7 +40,7 @@ macro_rules! define_color {
 }
 
 impl PixelColor {
-    const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self { r, g, b, a }
     }
 
@@ -38,31 +55,100 @@ impl PixelColor {
         YELLOW => [255, 255, 0, 255];
     );
 
-    fn gradient(n: u32, max_iter: u32, colors: &[Self]) -> Self {
-        let p = n as f32 / max_iter as f32;
-        let p = p.clamp(0.0, 0.9999);
-        let n = colors.len() - 1;
-        let idx = (p * n as f32).floor() as usize;
-        let p = p * n as f32 - idx as f32;
-
-        let c0 = colors[idx];
-        let c1 = colors[idx + 1];
+    /// Channels as floats in `[0, 1]`.
+    pub fn to_f32(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a].map(|c| c as f32 / 255.0)
+    }
 
-        let r = (c0.r as f32 + (c1.r as f32 - c0.r as f32) * p) as u8;
-        let g = (c0.g as f32 + (c1.g as f32 - c0.g as f32) * p) as u8;
-        let b = (c0.b as f32 + (c1.b as f32 - c0.b as f32) * p) as u8;
-        let a = (c0.a as f32 + (c1.a as f32 - c0.a as f32) * p) as u8;
+    pub fn to_rgba(self) -> [u8; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
 
-        Self::new(r, g, b, a)
+    fn gradient(n: u32, max_iter: u32, gradient: &Gradient, mapping: &Mapping) -> LinearColor {
+        gradient.sample(mapping.position(n as f32, max_iter))
     }
 
-    fn compute_gradient_table(max_iter: u32, colors: &[Self]) -> Vec<Self> {
+    fn compute_gradient_table(
+        max_iter: u32,
+        gradient: &Gradient,
+        mapping: &Mapping,
+    ) -> Vec<LinearColor> {
         (0..=max_iter)
-            .map(|n| Self::gradient(n, max_iter, colors))
+            .map(|n| Self::gradient(n, max_iter, gradient, mapping))
+            .collect()
+    }
+
+    fn compute_converged_table(
+        max_iter: u32,
+        gradient: &Gradient,
+        mapping: &Mapping,
+    ) -> Vec<LinearColor> {
+        let mapping = Mapping {
+            offset: mapping.offset + CONVERGED_OFFSET,
+            ..*mapping
+        };
+        Self::compute_gradient_table(max_iter, gradient, &mapping)
+    }
+
+    /// Colors of the roots, hues spread by the golden ratio so neighbouring
+    /// indices stand apart whatever the palette. They turn with the palette
+    /// offset.
+    fn compute_root_table(mapping: &Mapping) -> Vec<LinearColor> {
+        const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034;
+        (0..ROOT_COLORS)
+            .map(|root| {
+                let hue = root as f32 * GOLDEN_RATIO_CONJUGATE + mapping.offset;
+                let (r, g, b) = hsv_to_rgb(hue, ROOT_SATURATION, 1.0);
+                LinearColor::from_srgb([r, g, b, 1.0])
+            })
             .collect()
     }
 }
 
+/// Result of the formula at one point.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    /// Iterations before the orbit escaped or converged, the maximum
+    /// iterations if it did neither.
+    pub iter: u32,
+    /// The orbit converged rather than escaped.
+    pub converged: bool,
+    /// Where in the last iteration a converged orbit crossed the threshold,
+    /// in `[0, 1]`, for smooth coloring.
+    pub fraction: f32,
+    /// Root the orbit converged to, for root finding formulas.
+    pub root: Option<u16>,
+}
+
+impl Sample {
+    pub fn converged(iter: u32, fraction: f32, root: Option<u16>) -> Self {
+        Self {
+            iter,
+            converged: true,
+            fraction,
+            root,
+        }
+    }
+}
+
+impl From<u32> for Sample {
+    fn from(iter: u32) -> Self {
+        Self {
+            iter,
+            ..Self::default()
+        }
+    }
+}
+
+/// Result at a point of the plane, given the maximum iterations.
+pub type FormulaFn = dyn Fn(Complex<f64>, u32) -> Sample + Send + Sync;
+/// Results at a row of points of the plane, written to the slice of the
+/// same length.
+pub type RowFormulaFn = dyn Fn(&[Complex<f64>], u32, &mut [Sample]) + Send + Sync;
+
+/// Most points handed at once to a [`RowFormulaFn`].
+const ROW_POINTS: usize = 1024;
+
 pub struct MandelbrotUniverse {
     width: u32,
     height: u32,
@@ -71,78 +157,262 @@ pub struct MandelbrotUniverse {
     // Mandelbrot universe
     view: ViewPort,
     max_iter: u32,
-    gradient_table: Vec<PixelColor>,
+
+    // Coloring
+    gradient: Gradient,
+    mapping: Mapping,
+    gradient_table: Vec<LinearColor>,
+    converged_table: Vec<LinearColor>,
+    root_table: Vec<LinearColor>,
+    dither: Dither,
 
     // Mandelbrot function
-    apply: fn(Complex<f64>, u32) -> u32,
+    apply: Box<FormulaFn>,
+    /// Batched form of `apply`, used in its place when set.
+    apply_row: Option<Box<RowFormulaFn>>,
+    sampler: Sampler,
+    adaptive: Option<AdaptiveAa>,
+    show_refined: bool,
+
+    // Mandelbrot data, result of each sample of each pixel
+    data: Vec<Sample>,
+    refinement: Option<Refinement>,
+}
+
+/// Iteration counts of a computed image, enough to color it again without
+/// computing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Iterations {
+    /// Every sample of the sampler, pixel after pixel.
+    pub samples: Vec<Sample>,
+    /// Pixels refined by adaptive anti-aliasing, in increasing order.
+    pub refined_pixels: Vec<u32>,
+    /// Adaptive samples of the refined pixels, pixel after pixel.
+    pub refined_samples: Vec<Sample>,
+}
 
-    // Mandelbrot data
-    data: Vec<PixelColor>,
+/// Samples taken by adaptive anti-aliasing for the pixels needing them.
+struct Refinement {
+    /// Position (in pixels) in `samples` of each pixel, `NOT_REFINED` if the
+    /// pixel was not refined.
+    index: Vec<u32>,
+    samples: Vec<Sample>,
+    samples_per_pixel: usize,
 }
 
+/// Region of the complex plane shown on screen.
+///
+/// The view is defined by its center and the extent of the shorter side of
+/// the window, the other side follows the window aspect ratio so the image
+/// is never stretched. The window can then be rotated, and more generally
+/// deformed by any linear `transform` (skew, stretch, mirror). Last, a
+/// non-linear `plane` transform maps the view to the formula input.
 #[derive(Debug, Clone, Copy)]
 pub struct ViewPort {
-    x_min: f64,
-    x_max: f64,
-    y_min: f64,
-    y_max: f64,
+    center: Complex<f64>,
+    scale: f64,
+    rotation: f64,
+    transform: [[f64; 2]; 2],
+    plane: PlaneTransform,
 }
 
+const IDENTITY: [[f64; 2]; 2] = [[1.0, 0.0], [0.0, 1.0]];
+
 impl ViewPort {
-    pub fn zoom(&mut self, factor: f64, center_x: f64, center_y: f64) {
-        let width = self.x_max - self.x_min;
-        let height = self.y_max - self.y_min;
+    pub fn new(center: Complex<f64>, scale: f64) -> Self {
+        Self {
+            center,
+            scale,
+            rotation: 0.0,
+            transform: IDENTITY,
+            plane: PlaneTransform::Identity,
+        }
+    }
 
-        let new_width = width / factor;
-        let new_height = height / factor;
+    /// Rotation of the view, in radians.
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+
+    pub fn rotate(&mut self, angle: f64) {
+        self.rotation = (self.rotation + angle).rem_euclid(std::f64::consts::TAU);
+    }
+
+    pub fn set_rotation(&mut self, angle: f64) {
+        self.rotation = angle.rem_euclid(std::f64::consts::TAU);
+    }
+
+    /// Linear transform applied to window offsets before the rotation, as a
+    /// row-major 2×2 matrix.
+    pub fn set_transform(&mut self, transform: [[f64; 2]; 2]) {
+        self.transform = transform;
+    }
+
+    /// Non-linear transform applied after the linear ones. The center, scale
+    /// and rotation are then expressed in the coordinates of the transform.
+    pub fn set_plane(&mut self, plane: PlaneTransform) {
+        self.plane = plane;
+    }
+
+    /// Map an offset from the window center (already in complex units) to
+    /// an offset in the complex plane, applying the transform then rotation.
+    fn map_offset(&self, dx: f64, dy: f64) -> Complex<f64> {
+        let [[a, b], [c, d]] = self.transform;
+        let (x, y) = (a * dx + b * dy, c * dx + d * dy);
+        let (sin, cos) = self.rotation.sin_cos();
+        Complex::new(x * cos - y * sin, x * sin + y * cos)
+    }
 
-        self.x_min = center_x - new_width / 2.0;
-        self.x_max = center_x + new_width / 2.0;
-        self.y_min = center_y - new_height / 2.0;
-        self.y_max = center_y + new_height / 2.0;
+    pub fn center(&self) -> Complex<f64> {
+        self.center
+    }
+
+    pub fn set_center(&mut self, center: Complex<f64>) {
+        self.center = center;
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    pub fn plane(&self) -> &PlaneTransform {
+        &self.plane
+    }
+
+    /// Size of a pixel in the complex plane.
+    pub fn pixel_size(&self, width: u32, height: u32) -> f64 {
+        self.scale / width.min(height).max(1) as f64
+    }
+
+    pub fn zoom(&mut self, factor: f64, center_x: f64, center_y: f64) {
+        self.center = Complex::new(center_x, center_y);
+        self.scale /= factor;
     }
 
     pub fn translate(&mut self, dx: f64, dy: f64) {
-        self.x_min += dx;
-        self.x_max += dx;
-        self.y_min += dy;
-        self.y_max += dy;
+        self.center += Complex::new(dx, dy);
+    }
+
+    /// Translate by a displacement given in pixels, following the rotation
+    /// and transform of the view.
+    pub fn translate_pixels(&mut self, dx: f64, dy: f64, width: u32, height: u32) {
+        let pixel_size = self.pixel_size(width, height);
+        let offset = self.map_offset(dx * pixel_size, dy * pixel_size);
+        self.translate(offset.re, offset.im);
+    }
+
+    /// View of the `tile_width`×`tile_height` pixels at `(x, y)` of a
+    /// `width`×`height` image of this view, pixel for pixel.
+    pub fn tile(
+        &self,
+        x: u32,
+        y: u32,
+        tile_width: u32,
+        tile_height: u32,
+        width: u32,
+        height: u32,
+    ) -> ViewPort {
+        let center = self.point_to_view(
+            x as f64 + tile_width as f64 / 2.0,
+            y as f64 + tile_height as f64 / 2.0,
+            width,
+            height,
+        );
+        let scale = self.pixel_size(width, height) * tile_width.min(tile_height) as f64;
+        Self {
+            center,
+            scale,
+            ..*self
+        }
+    }
+
+    /// View coordinates (before the plane transform) at the center of the
+    /// pixel `(x, y)`, what the center and zoom are expressed in.
+    pub fn idx_to_view(&self, x: u32, y: u32, width: u32, height: u32) -> Complex<f64> {
+        self.point_to_view(x as f64 + 0.5, y as f64 + 0.5, width, height)
     }
 
-    pub fn idx_to_complex(&self, x: u32, y: u32, width: u32, height: u32) -> Complex<f64> {
-        let x = x as f64;
-        let y = y as f64;
+    /// View coordinates at a continuous position of the window, the pixel
+    /// `(x, y)` covering `[x, x + 1) × [y, y + 1)`.
+    fn point_to_view(&self, x: f64, y: f64, width: u32, height: u32) -> Complex<f64> {
+        let pixel_size = self.pixel_size(width, height);
 
-        let re = self.x_min + (self.x_max - self.x_min) * x / width as f64;
-        let im = self.y_min + (self.y_max - self.y_min) * y / height as f64;
+        let dx = (x - width as f64 / 2.0) * pixel_size;
+        let dy = (y - height as f64 / 2.0) * pixel_size;
 
-        Complex::new(re, im)
+        self.center + self.map_offset(dx, dy)
+    }
+
+    /// Window position of view coordinates, the inverse of `point_to_view`.
+    /// Not finite when the transform is singular.
+    pub fn view_to_point(&self, p: Complex<f64>, width: u32, height: u32) -> (f64, f64) {
+        let offset = p - self.center;
+        let (sin, cos) = self.rotation.sin_cos();
+        let (x, y) = (
+            offset.re * cos + offset.im * sin,
+            offset.im * cos - offset.re * sin,
+        );
+        let [[a, b], [c, d]] = self.transform;
+        let det = a * d - b * c;
+        let (dx, dy) = ((d * x - b * y) / det, (a * y - c * x) / det);
+        let pixel_size = self.pixel_size(width, height);
+        (
+            dx / pixel_size + width as f64 / 2.0,
+            dy / pixel_size + height as f64 / 2.0,
+        )
+    }
+
+    /// Formula input at a continuous position of the window.
+    pub fn point_to_complex(&self, x: f64, y: f64, width: u32, height: u32) -> Complex<f64> {
+        self.plane.apply(self.point_to_view(x, y, width, height))
     }
 }
 
 impl std::default::Default for ViewPort {
     fn default() -> Self {
-        Self {
-            x_min: -2.0,
-            x_max: 1.0,
-            y_min: -1.5,
-            y_max: 1.5,
-        }
+        Self::new(Complex::new(-0.5, 0.0), 3.0)
+    }
+}
+
+/// Run the formula on `c`, unless [`off_plane`].
+fn evaluate(function: &FormulaFn, c: Complex<f64>, max_iter: u32) -> Sample {
+    off_plane(c, max_iter).unwrap_or_else(|| function(c, max_iter))
+}
+
+/// Sample of the points the formula is not run on. Points mapped to
+/// infinity by the plane, like the pole of an inversion, escape at once.
+/// Points it has no image for (NaN), outside of the sphere, never escape
+/// and are drawn as the background.
+fn off_plane(c: Complex<f64>, max_iter: u32) -> Option<Sample> {
+    if c.re.is_nan() || c.im.is_nan() {
+        Some(max_iter.into())
+    } else if c.re.is_infinite() || c.im.is_infinite() {
+        Some(1.into())
+    } else {
+        None
     }
 }
 
 impl MandelbrotUniverse {
-    pub fn new(
+    /// `function` returns iteration counts, or full [`Sample`]s.
+    pub fn new<R: Into<Sample>>(
         width: u32,
         height: u32,
         threads: usize,
-        colors: &[PixelColor],
+        gradient: &Gradient,
+        mapping: Mapping,
         max_iter: u32,
-        function: fn(Complex<f64>, u32) -> u32,
+        function: impl Fn(Complex<f64>, u32) -> R + Send + Sync + 'static,
     ) -> Self {
         // Compute gradient table
 
-        let gradient_table = PixelColor::compute_gradient_table(max_iter, colors);
+        let gradient_table = PixelColor::compute_gradient_table(max_iter, gradient, &mapping);
+        let converged_table = PixelColor::compute_converged_table(max_iter, gradient, &mapping);
+        let root_table = PixelColor::compute_root_table(&mapping);
         let threads = threads.max(1); // At least 1 thread
 
         Self {
@@ -150,119 +420,295 @@ impl MandelbrotUniverse {
             height,
             threads,
 
+            gradient: gradient.clone(),
+            mapping,
             gradient_table,
-            apply: function,
+            converged_table,
+            root_table,
+            dither: Dither::default(),
+            apply: Box::new(move |c, max_iter| function(c, max_iter).into()),
+            apply_row: None,
+            sampler: Sampler::default(),
+            adaptive: None,
+            show_refined: false,
 
             view: ViewPort::default(),
             max_iter,
 
-            data: vec![PixelColor::BLACK; (width * height) as usize],
+            data: vec![Sample::default(); width as usize * height as usize],
+            refinement: None,
         }
     }
 
+    /// Change the image size, the view keeps its center and scale.
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.set_size(width, height);
+        self.compute();
+    }
+
+    /// Change the image size, takes effect on the next compute.
+    pub fn set_size(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
-        self.data = vec![PixelColor::BLACK; (width * height) as usize];
-        self.compute();
+        self.data = vec![Sample::default(); self.pixel_count() * self.sampler.count()];
+    }
+
+    fn pixel_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn view(&self) -> &ViewPort {
+        &self.view
+    }
+
+    /// Change the view, takes effect on the next compute.
+    pub fn set_view(&mut self, view: ViewPort) {
+        self.view = view;
+    }
+
+    /// Change the formula, takes effect on the next compute.
+    pub fn set_function<R: Into<Sample>>(
+        &mut self,
+        function: impl Fn(Complex<f64>, u32) -> R + Send + Sync + 'static,
+    ) {
+        self.apply = Box::new(move |c, max_iter| function(c, max_iter).into());
+        self.apply_row = None;
+    }
+
+    /// Evaluate the formula by rows of points with `function`, the same
+    /// formula as the one of [`Self::set_function`], which it follows.
+    pub fn set_row_function(
+        &mut self,
+        function: impl Fn(&[Complex<f64>], u32, &mut [Sample]) + Send + Sync + 'static,
+    ) {
+        self.apply_row = Some(Box::new(function));
+    }
+
+    pub fn sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+
+    /// Change the supersampling pattern, takes effect on the next compute.
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.data = vec![Sample::default(); self.pixel_count() * sampler.count()];
+        self.sampler = sampler;
+    }
+
+    /// Enable adaptive anti-aliasing, takes effect on the next compute.
+    pub fn set_adaptive(&mut self, adaptive: Option<AdaptiveAa>) {
+        self.adaptive = adaptive;
+    }
+
+    /// Highlight the pixels refined by adaptive anti-aliasing.
+    pub fn set_show_refined(&mut self, show: bool) {
+        self.show_refined = show;
+    }
+
+    pub fn show_refined(&self) -> bool {
+        self.show_refined
+    }
+
+    pub fn mapping(&self) -> Mapping {
+        self.mapping
+    }
+
+    /// Change the iteration limit, takes effect on the next compute.
+    pub fn set_max_iter(&mut self, max_iter: u32) {
+        self.max_iter = max_iter;
+        self.gradient_table =
+            PixelColor::compute_gradient_table(self.max_iter, &self.gradient, &self.mapping);
+        self.converged_table =
+            PixelColor::compute_converged_table(self.max_iter, &self.gradient, &self.mapping);
+    }
+
+    /// Change the palette, without recomputing the fractal.
+    pub fn set_gradient(&mut self, gradient: &Gradient) {
+        self.gradient = gradient.clone();
+        self.set_mapping(self.mapping);
+    }
+
+    /// Change how iterations map to colors, without recomputing the fractal.
+    pub fn set_mapping(&mut self, mapping: Mapping) {
+        self.mapping = mapping;
+        self.gradient_table =
+            PixelColor::compute_gradient_table(self.max_iter, &self.gradient, &self.mapping);
+        self.converged_table =
+            PixelColor::compute_converged_table(self.max_iter, &self.gradient, &self.mapping);
+        self.root_table = PixelColor::compute_root_table(&self.mapping);
     }
 
     fn rev_convert_idx(&self, idx: usize) -> (u32, u32) {
-        let x = idx as u32 % self.width;
-        let y = idx as u32 / self.width;
+        let x = (idx % self.width as usize) as u32;
+        let y = (idx / self.width as usize) as u32;
 
         (x, y)
     }
 
-    fn idx_to_complex(&self, x: u32, y: u32) -> Complex<f64> {
-        self.view.idx_to_complex(x, y, self.width, self.height)
+    fn compute_single_thread(&mut self) {
+        let pixels: Vec<usize> = (0..self.pixel_count()).collect();
+        let mut data = std::mem::take(&mut self.data);
+        data.resize(pixels.len() * self.sampler.count(), Sample::default());
+        self.sample_chunk(&pixels, &self.sampler, &mut data);
+        self.data = data;
     }
 
-    fn compute_single_thread(&mut self) {
-        for idx in 0..self.data.len() {
+    /// Evaluate every `sampler` sample of `pixels` into `cells`, by rows of
+    /// points when the formula takes them.
+    fn sample_chunk(&self, pixels: &[usize], sampler: &Sampler, cells: &mut [Sample]) {
+        let samples = sampler.count();
+        let points = pixels.iter().flat_map(|&idx| {
             let (x, y) = self.rev_convert_idx(idx);
-            let c = self.idx_to_complex(x, y);
-            let n = (self.apply)(c, self.max_iter);
-
-            let color = if n == self.max_iter {
-                PixelColor::BLACK
-            } else {
-                self.gradient_table[n as usize]
-                // let brightness = 255 - n * 255 / self.max_iter;
-                // PixelColor::new(brightness as u8, brightness as u8, brightness as u8, 255)
-            };
+            sampler.offsets(x, y).iter().map(move |&(dx, dy)| {
+                self.view.point_to_complex(
+                    x as f64 + 0.5 + dx,
+                    y as f64 + 0.5 + dy,
+                    self.width,
+                    self.height,
+                )
+            })
+        });
+        debug_assert_eq!(cells.len(), pixels.len() * samples);
 
-            self.data[idx] = color;
+        let Some(apply_row) = &self.apply_row else {
+            for (cell, c) in cells.iter_mut().zip(points) {
+                *cell = evaluate(&*self.apply, c, self.max_iter);
+            }
+            return;
+        };
+
+        // Points off the plane are filled at once, the others gathered in
+        // rows with the cells they go to
+        let mut row = Vec::with_capacity(ROW_POINTS);
+        let mut targets = Vec::with_capacity(ROW_POINTS);
+        let mut results = vec![Sample::default(); ROW_POINTS];
+        let mut flush =
+            |row: &mut Vec<Complex<f64>>, targets: &mut Vec<usize>, cells: &mut [Sample]| {
+                let results = &mut results[..row.len()];
+                apply_row(row, self.max_iter, results);
+                for (&target, &sample) in targets.iter().zip(results.iter()) {
+                    cells[target] = sample;
+                }
+                row.clear();
+                targets.clear();
+            };
+        for (target, c) in points.enumerate() {
+            if let Some(sample) = off_plane(c, self.max_iter) {
+                cells[target] = sample;
+                continue;
+            }
+            row.push(c);
+            targets.push(target);
+            if row.len() == ROW_POINTS {
+                flush(&mut row, &mut targets, cells);
+            }
+        }
+        if !row.is_empty() {
+            flush(&mut row, &mut targets, cells);
         }
     }
 
     pub fn zoom(&mut self, factor: f64, center_x: u32, center_y: u32) {
-        let center = self.idx_to_complex(center_x, center_y);
+        let center = self
+            .view
+            .idx_to_view(center_x, center_y, self.width, self.height);
         self.view.zoom(factor, center.re, center.im);
         self.compute();
     }
 
+    /// Rotate the view around its center by `angle` radians.
+    pub fn rotate(&mut self, angle: f64) {
+        self.view.rotate(angle);
+        log::debug!("View rotation: {:.1}°", self.view.rotation().to_degrees());
+        self.compute();
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
     pub fn translate(&mut self, dx: f64, dy: f64) {
-        let dx = dx * (self.view.x_max - self.view.x_min) / self.width as f64;
-        let dy = dy * (self.view.y_max - self.view.y_min) / self.height as f64;
-        self.view.translate(dx, dy);
+        self.view.translate_pixels(dx, dy, self.width, self.height);
         self.compute();
     }
 
     fn compute_multi_thread(&mut self) {
-        let concurrent_threads = self.threads;
-        let pixels_per_thread = self.data.len() / concurrent_threads;
-
-        let mut new_data = vec![PixelColor::BLACK; self.data.len()];
-
-        let mut pixels = {
-            let mut rep = Vec::new();
-            let mut pixels = new_data.as_mut_slice();
-            for _ in 0..concurrent_threads {
-                let (start, end) = pixels.split_at_mut(pixels_per_thread as usize);
-                rep.push(start);
-                pixels = end;
-            }
-            rep
-        };
+        let pixels: Vec<usize> = (0..self.pixel_count()).collect();
+        self.data = self.sample_pixels(&pixels, &self.sampler);
+    }
 
-        let width = self.width;
-        let height = self.height;
-        let max_iter = self.max_iter;
+    /// Evaluate every `sampler` sample of the given pixels, split over the
+    /// worker threads.
+    fn sample_pixels(&self, pixels: &[usize], sampler: &Sampler) -> Vec<Sample> {
+        let samples = sampler.count();
+        let pixels_per_thread = pixels.len().div_ceil(self.threads).max(1);
 
-        let gradient_table = self.gradient_table.clone();
-        let viewport = self.view.clone();
+        let mut data = vec![Sample::default(); pixels.len() * samples];
 
-        // Create a scope for the threads to run in
         thread::scope(|s| {
-            for (i, cells) in pixels.iter_mut().enumerate() {
-                let base_index = i * pixels_per_thread as usize;
-                let gradient_table = gradient_table.clone();
-                let viewport = viewport.clone();
-
-                let mandelbrot = self.apply;
-
-                s.spawn(move || {
-                    for (i, pixel) in cells.iter_mut().enumerate() {
-                        let (x, y) = (
-                            (base_index + i) as u32 % width,
-                            (base_index + i) as u32 / width,
-                        );
-                        let c = viewport.idx_to_complex(x, y, width, height);
-                        let n = mandelbrot(c, max_iter);
-                        *pixel = gradient_table[n as usize];
-                    }
-                });
+            for (cells, pixels) in data
+                .chunks_mut(pixels_per_thread * samples)
+                .zip(pixels.chunks(pixels_per_thread))
+            {
+                s.spawn(move || self.sample_chunk(pixels, sampler, cells));
             }
         });
 
-        // let max_iter = new_data.iter().max().unwrap();
-        // let gradient = PixelColor::compute_gradient_table(
-        //     *max_iter,
-        //     vec![PixelColor::BLUE, PixelColor::YELLOW],
-        // );
-        // self.data = new_data.iter().map(|&n| gradient[n as usize]).collect();
-        std::mem::swap(&mut self.data, &mut new_data);
+        data
+    }
+
+    /// Whether the pixel at `idx` differs too much from one of its neighbours.
+    fn needs_refinement(&self, idx: usize, colors: &[LinearColor], adaptive: &AdaptiveAa) -> bool {
+        let (x, y) = self.rev_convert_idx(idx);
+        let stride = self.sampler.count();
+        let n = self.data[idx * stride].iter;
+        let color = colors[idx].to_srgb();
+
+        let neighbours = [
+            (x > 0).then(|| idx - 1),
+            (x + 1 < self.width).then(|| idx + 1),
+            (y > 0).then(|| idx - self.width as usize),
+            (y + 1 < self.height).then(|| idx + self.width as usize),
+        ];
+
+        neighbours.into_iter().flatten().any(|other| {
+            let iter_diff = n.abs_diff(self.data[other * stride].iter);
+            if adaptive.iter_threshold.is_some_and(|t| iter_diff > t) {
+                return true;
+            }
+            let other = colors[other].to_srgb();
+            (0..3).any(|c| (color[c] - other[c]).abs() > adaptive.color_threshold)
+        })
+    }
+
+    fn refine(&mut self) {
+        let Some(adaptive) = self.adaptive.clone() else {
+            self.refinement = None;
+            return;
+        };
+
+        self.refinement = None;
+        let pixels = self.pixel_count();
+        let colors: Vec<LinearColor> = (0..pixels).map(|i| self.pixel_color(i)).collect();
+        let marked: Vec<usize> = (0..pixels)
+            .filter(|&i| self.needs_refinement(i, &colors, &adaptive))
+            .collect();
+
+        let samples = self.sample_pixels(&marked, &adaptive.sampler);
+        let mut index = vec![NOT_REFINED; pixels];
+        for (k, &idx) in marked.iter().enumerate() {
+            index[idx] = k as u32;
+        }
+
+        log::debug!(
+            "Refined {} pixels ({:.1}%)",
+            marked.len(),
+            100.0 * marked.len() as f64 / pixels.max(1) as f64
+        );
+        self.refinement = Some(Refinement {
+            index,
+            samples,
+            samples_per_pixel: adaptive.sampler.count(),
+        });
     }
 
     pub fn compute(&mut self) {
@@ -272,15 +718,226 @@ impl MandelbrotUniverse {
         } else {
             self.compute_multi_thread();
         }
+        self.refine();
         let t2 = std::time::Instant::now();
-        println!("Compute time: {:?}", t2 - t1);
+        log::debug!("Compute time: {:?}", t2 - t1);
+    }
+
+    /// Iteration counts of the last compute.
+    pub fn iterations(&self) -> Iterations {
+        let (refined_pixels, refined_samples) = match &self.refinement {
+            Some(refinement) => (
+                (0..refinement.index.len() as u32)
+                    .filter(|&idx| refinement.index[idx as usize] != NOT_REFINED)
+                    .collect(),
+                refinement.samples.clone(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        Iterations {
+            samples: self.data.clone(),
+            refined_pixels,
+            refined_samples,
+        }
+    }
+
+    /// Replace the computed data with `iterations`, saved by [`Self::iterations`]
+    /// with the same size and sampling.
+    pub fn set_iterations(&mut self, iterations: Iterations) -> Result<(), String> {
+        let pixels = self.pixel_count();
+        if iterations.samples.len() != self.data.len() {
+            return Err(format!(
+                "{} samples for {} pixels of {} samples",
+                iterations.samples.len(),
+                pixels,
+                self.sampler.count()
+            ));
+        }
+
+        self.refinement = match &self.adaptive {
+            Some(adaptive) => {
+                let samples_per_pixel = adaptive.sampler.count();
+                if iterations.refined_samples.len()
+                    != iterations.refined_pixels.len() * samples_per_pixel
+                {
+                    return Err(format!(
+                        "{} adaptive samples for {} pixels of {} samples",
+                        iterations.refined_samples.len(),
+                        iterations.refined_pixels.len(),
+                        samples_per_pixel
+                    ));
+                }
+                let mut index = vec![NOT_REFINED; pixels];
+                for (k, &idx) in iterations.refined_pixels.iter().enumerate() {
+                    *index
+                        .get_mut(idx as usize)
+                        .ok_or_else(|| format!("refined pixel {} out of the image", idx))? =
+                        k as u32;
+                }
+                Some(Refinement {
+                    index,
+                    samples: iterations.refined_samples,
+                    samples_per_pixel,
+                })
+            }
+            None if iterations.refined_pixels.is_empty() => None,
+            None => return Err("adaptive samples without adaptive anti-aliasing".into()),
+        };
+        self.data = iterations.samples;
+        Ok(())
+    }
+
+    /// First sample of the pixel at `(x, y)`.
+    pub fn sample_at(&self, x: u32, y: u32) -> Sample {
+        let idx = y as usize * self.width as usize + x as usize;
+        self.data[idx * self.sampler.count()]
+    }
+
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
     }
 
+    /// Color of a sample: escaped, the gradient. Converged to a root, the
+    /// color of the root darker as convergence is slower, otherwise the
+    /// shifted gradient. Converged samples are colored by their fractional
+    /// iteration count, without bands.
+    fn color(&self, sample: Sample) -> LinearColor {
+        if sample.iter >= self.max_iter {
+            return LinearColor::BLACK;
+        } else if !sample.converged {
+            return self.gradient_table[sample.iter as usize];
+        }
+
+        let iter = sample.iter.max(1);
+        let smooth = (iter - 1) as f32 + sample.fraction;
+        match sample.root {
+            Some(root) => {
+                let shade = ROOT_SHADE.powf(smooth).max(MIN_ROOT_SHADE);
+                self.root_table[(root as u32 % ROOT_COLORS) as usize] * shade
+            }
+            None => {
+                let before = self.converged_table[iter as usize - 1];
+                let after = self.converged_table[iter as usize];
+                before * (1.0 - sample.fraction) + after * sample.fraction
+            }
+        }
+    }
+
+    /// Linear color of the pixel at `idx`, the average of its samples.
+    fn pixel_color(&self, idx: usize) -> LinearColor {
+        let samples = match &self.refinement {
+            Some(refinement) if refinement.index[idx] != NOT_REFINED => {
+                let start = refinement.index[idx] as usize * refinement.samples_per_pixel;
+                &refinement.samples[start..start + refinement.samples_per_pixel]
+            }
+            _ => {
+                let count = self.sampler.count();
+                &self.data[idx * count..(idx + 1) * count]
+            }
+        };
+        let sum = samples
+            .iter()
+            .fold(LinearColor::default(), |sum, &n| sum + self.color(n));
+        sum / samples.len() as f32
+    }
+
+    fn is_refined(&self, idx: usize) -> bool {
+        self.refinement
+            .as_ref()
+            .is_some_and(|refinement| refinement.index[idx] != NOT_REFINED)
+    }
+
+    /// Encode the pixels to 8 bit sRGBA, dithering is applied here and only here.
     pub fn render(&self, frame: &mut [u8]) {
-        debug_assert!(self.data.len() * 4 <= frame.len());
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let color = self.data[i];
+        debug_assert!(self.data.len() / self.sampler.count() * 4 <= frame.len());
+        let pixels = self.data.len() / self.sampler.count();
+        for (i, pixel) in frame.chunks_exact_mut(4).take(pixels).enumerate() {
+            let (x, y) = self.rev_convert_idx(i);
+            let mut color = self.pixel_color(i);
+            if self.show_refined && self.is_refined(i) {
+                color = color * 0.5 + LinearColor::from(PixelColor::RED) * 0.5;
+            }
+            let color = color.to_pixel(self.dither.threshold(x, y));
             pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formula;
+    use crate::plane::PlaneKind;
+
+    fn universe(plane: PlaneTransform) -> MandelbrotUniverse {
+        let gradient = Gradient::evenly_spaced(&[PixelColor::WHITE, PixelColor::RED]);
+        let mut universe = MandelbrotUniverse::new(
+            8,
+            8,
+            1,
+            &gradient,
+            Mapping::default(),
+            50,
+            formula::mandelbrot_fast,
+        );
+        let (center, scale) = plane.default_view().expect("view");
+        let mut view = ViewPort::new(center, scale);
+        view.set_plane(plane);
+        universe.set_view(view);
+        universe.compute();
+        universe
+    }
+
+    #[test]
+    fn outside_of_the_sphere_is_background() {
+        let sphere = PlaneTransform::new(
+            PlaneKind::Sphere,
+            Complex::new(0.0, 0.0),
+            Complex::new(0.0, 0.0),
+            0.0,
+        );
+        let universe = universe(sphere);
+        let view = universe.view();
+        // Corner pixel: outside of the unit disc
+        assert!(view.point_to_view(0.5, 0.5, 8, 8).norm() > 1.0);
+        assert!(view.point_to_complex(0.5, 0.5, 8, 8).re.is_nan());
+        assert_eq!(universe.sample_at(0, 0), 50.into());
+        assert_eq!(universe.color(universe.sample_at(0, 0)), LinearColor::BLACK);
+        // Center pixel: the origin, in the set
+        assert_eq!(universe.sample_at(4, 4), 50.into());
+        // Rim of the sphere, on the escape circle, right of the center
+        assert!(universe.sample_at(7, 4).iter < 50);
+    }
+
+    #[test]
+    fn points_sent_to_infinity_escape_at_once() {
+        let function: Box<FormulaFn> = Box::new(|_, _| panic!("not evaluated"));
+        let infinity = Complex::new(f64::INFINITY, 0.0);
+        assert_eq!(evaluate(&*function, infinity, 50), 1.into());
+
+        let inversion = PlaneTransform::new(
+            PlaneKind::Inversion,
+            Complex::new(0.5, 0.0),
+            Complex::new(0.0, 0.0),
+            0.0,
+        );
+        assert_eq!(
+            evaluate(&*function, inversion.apply(Complex::new(0.5, 0.0)), 50),
+            1.into()
+        );
+
+        // Turned half a turn, the sphere shows its north pole at the center
+        let sphere = PlaneTransform::new(
+            PlaneKind::Sphere,
+            Complex::new(0.0, 0.0),
+            Complex::new(0.0, 0.0),
+            std::f64::consts::PI,
+        );
+        assert_eq!(
+            evaluate(&*function, sphere.apply(Complex::new(0.0, 0.0)), 50),
+            1.into()
+        );
+        let nan = Complex::new(f64::NAN, f64::NAN);
+        assert_eq!(evaluate(&*function, nan, 50), 50.into());
+    }
+}
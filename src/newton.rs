@@ -0,0 +1,434 @@
+use std::str::FromStr;
+
+use crate::complex::Complex;
+use crate::formula::convergence_fraction;
+use crate::mandelbrot::Sample;
+
+/// Orbits stop once a step is shorter than this.
+const CONVERGENCE: f64 = 1e-6;
+/// A converged orbit closer than this to a root is attributed to it.
+const ROOT_DISTANCE: f64 = 1e-3;
+/// Durand–Kerner root search.
+const ROOT_SEARCH_ITERATIONS: u32 = 1000;
+const ROOT_SEARCH_TOLERANCE: f64 = 1e-14;
+
+/// Polynomial with complex coefficients.
+#[derive(Debug, Clone)]
+pub struct Polynomial {
+    /// Lowest degree first, the last one is not zero.
+    coefficients: Vec<Complex<f64>>,
+}
+
+impl Default for Polynomial {
+    /// `z³ - 1`
+    fn default() -> Self {
+        let mut coefficients = vec![Complex::new(0.0, 0.0); 4];
+        coefficients[0] = Complex::new(-1.0, 0.0);
+        coefficients[3] = Complex::new(1.0, 0.0);
+        Self { coefficients }
+    }
+}
+
+impl Polynomial {
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    /// Value at `z`, by Horner's method.
+    pub fn eval(&self, z: Complex<f64>) -> Complex<f64> {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(Complex::new(0.0, 0.0), |sum, &coefficient| {
+                sum * z + coefficient
+            })
+    }
+
+    pub fn derivative(&self) -> Polynomial {
+        Polynomial {
+            coefficients: self
+                .coefficients
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(power, &coefficient)| coefficient * power as f64)
+                .collect(),
+        }
+    }
+
+    /// Every root, repeated ones included, by the Durand–Kerner method.
+    pub fn roots(&self) -> Vec<Complex<f64>> {
+        let degree = self.degree();
+        let leading = self.coefficients[degree];
+        let monic = |z: Complex<f64>| self.eval(z) / leading;
+
+        // Starting points spread on a spiral, none of them real
+        let seed = Complex::new(0.4, 0.9);
+        let mut roots: Vec<Complex<f64>> = (0..degree as i32).map(|k| seed.powi(k)).collect();
+        for _ in 0..ROOT_SEARCH_ITERATIONS {
+            let mut largest_step: f64 = 0.0;
+            for i in 0..degree {
+                let denominator = (0..degree)
+                    .filter(|&j| j != i)
+                    .fold(Complex::new(1.0, 0.0), |product, j| {
+                        product * (roots[i] - roots[j])
+                    });
+                let step = monic(roots[i]) / denominator;
+                if step.re.is_finite() && step.im.is_finite() {
+                    roots[i] -= step;
+                    largest_step = largest_step.max(step.norm());
+                }
+            }
+            if largest_step < ROOT_SEARCH_TOLERANCE * ROOT_SEARCH_TOLERANCE {
+                break;
+            }
+        }
+        roots
+    }
+}
+
+impl FromStr for Polynomial {
+    type Err = String;
+
+    /// Sum of terms in `z` such as `z^3 - 1`, `2.5z^2 + (1-2i)z + i` or
+    /// `3*z^4 - 0.5i`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let error = |position: usize, message: &str| {
+            Err(format!(
+                "invalid polynomial '{}': {} at character {}",
+                s,
+                message,
+                position + 1
+            ))
+        };
+
+        let mut coefficients = Vec::new();
+        let mut pos = 0;
+        while pos < text.len() {
+            let sign = match text[pos] {
+                '+' => {
+                    pos += 1;
+                    1.0
+                }
+                '-' => {
+                    pos += 1;
+                    -1.0
+                }
+                _ if pos == 0 => 1.0,
+                _ => return error(pos, "expected + or -"),
+            };
+
+            // Coefficient, 1 when only z is written
+            let coefficient = if text.get(pos) == Some(&'(') {
+                let end = match text[pos..].iter().position(|&c| c == ')') {
+                    Some(end) => pos + end,
+                    None => return error(pos, "unclosed parenthesis"),
+                };
+                let inner: String = text[pos + 1..end].iter().collect();
+                let Some(value) = parse_complex_number(&inner) else {
+                    return error(pos + 1, "expected a complex number");
+                };
+                pos = end + 1;
+                Some(value)
+            } else {
+                let start = pos;
+                while pos < text.len() && is_number_char(&text, pos) {
+                    pos += 1;
+                }
+                if text.get(pos) == Some(&'i') {
+                    pos += 1;
+                }
+                match &text[start..pos] {
+                    [] => None,
+                    number => {
+                        let number: String = number.iter().collect();
+                        match parse_complex_number(&number) {
+                            Some(value) => Some(value),
+                            None => return error(start, "expected a number"),
+                        }
+                    }
+                }
+            };
+
+            if coefficient.is_some() && text.get(pos) == Some(&'*') {
+                pos += 1;
+                if text.get(pos) != Some(&'z') {
+                    return error(pos, "expected z after *");
+                }
+            }
+
+            let power = if text.get(pos) == Some(&'z') {
+                pos += 1;
+                if text.get(pos) == Some(&'^') {
+                    pos += 1;
+                    let start = pos;
+                    while pos < text.len() && text[pos].is_ascii_digit() {
+                        pos += 1;
+                    }
+                    let digits: String = text[start..pos].iter().collect();
+                    match digits.parse::<usize>() {
+                        Ok(power) if power <= 64 => power,
+                        _ => return error(start, "expected a power from 0 to 64"),
+                    }
+                } else {
+                    1
+                }
+            } else if coefficient.is_none() {
+                return error(pos, "expected a number or z");
+            } else {
+                0
+            };
+
+            if coefficients.len() <= power {
+                coefficients.resize(power + 1, Complex::new(0.0, 0.0));
+            }
+            coefficients[power] += coefficient.unwrap_or(Complex::new(1.0, 0.0)) * sign;
+        }
+
+        while coefficients
+            .last()
+            .is_some_and(|c: &Complex<f64>| c.norm() == 0.0)
+        {
+            coefficients.pop();
+        }
+        if coefficients.len() < 2 {
+            return Err(format!("invalid polynomial '{}': z is missing", s));
+        }
+        Ok(Self { coefficients })
+    }
+}
+
+/// Whether `text[pos]` continues a decimal number, exponent included.
+fn is_number_char(text: &[char], pos: usize) -> bool {
+    match text[pos] {
+        '0'..='9' | '.' => true,
+        'e' | 'E' => {
+            pos + 1 < text.len() && (text[pos + 1].is_ascii_digit() || "+-".contains(text[pos + 1]))
+        }
+        '+' | '-' => pos > 0 && "eE".contains(text[pos - 1]),
+        _ => false,
+    }
+}
+
+/// `a`, `bi`, `i`, `a+bi` or `a-bi`.
+fn parse_complex_number(s: &str) -> Option<Complex<f64>> {
+    let imaginary = |s: &str| match s.strip_suffix('i')? {
+        "" | "+" => Some(1.0),
+        "-" => Some(-1.0),
+        number => number.parse().ok(),
+    };
+    if !s.ends_with('i') {
+        return Some(Complex::new(s.parse().ok()?, 0.0));
+    }
+    // Split before the sign of the imaginary part, not an exponent sign
+    let split = s
+        .char_indices()
+        .skip(1)
+        .filter(|&(i, c)| "+-".contains(c) && !s[..i].ends_with(['e', 'E']))
+        .map(|(i, _)| i)
+        .last();
+    match split {
+        Some(i) => Some(Complex::new(s[..i].parse().ok()?, imaginary(&s[i..])?)),
+        None => Some(Complex::new(0.0, imaginary(s)?)),
+    }
+}
+
+/// Newton's method on a polynomial `p`, `z = z - a p(z) / p'(z)`.
+///
+/// In the plain fractal the pixel is the starting point and the orbit
+/// converges to one of the roots of `p`, which gives its color. The Nova
+/// fractal adds the pixel at each step, `z = z - a p(z) / p'(z) + c`, and
+/// starts from `z = 1`; its orbits converge to points that are not roots.
+#[derive(Debug, Clone)]
+pub struct Newton {
+    polynomial: Polynomial,
+    derivative: Polynomial,
+    roots: Vec<Complex<f64>>,
+    /// The `a` factor of the step, 1 for the usual method.
+    relaxation: Complex<f64>,
+    nova: bool,
+}
+
+impl Newton {
+    pub fn new(polynomial: Polynomial, relaxation: Complex<f64>, nova: bool) -> Self {
+        Self {
+            derivative: polynomial.derivative(),
+            roots: polynomial.roots(),
+            polynomial,
+            relaxation,
+            nova,
+        }
+    }
+
+    pub fn relaxation(&self) -> Complex<f64> {
+        self.relaxation
+    }
+
+    pub fn set_relaxation(&mut self, relaxation: Complex<f64>) {
+        self.relaxation = relaxation;
+    }
+
+    /// Center and scale of a view framing the roots, or the Nova set.
+    pub fn default_view(&self) -> (Complex<f64>, f64) {
+        if self.nova {
+            return (Complex::new(0.0, 0.0), 3.0);
+        }
+        let count = self.roots.len().max(1) as f64;
+        let center = self
+            .roots
+            .iter()
+            .fold(Complex::new(0.0, 0.0), |sum, &root| sum + root)
+            / count;
+        let radius = self
+            .roots
+            .iter()
+            .map(|&root| (root - center).norm().sqrt())
+            .fold(0.0, f64::max);
+        (center, (3.0 * radius).max(1.0))
+    }
+
+    /// Iterations to converge and the root reached.
+    pub fn sample(&self, c: Complex<f64>, max_iter: u32) -> Sample {
+        let (mut z, offset) = if self.nova {
+            (Complex::new(1.0, 0.0), c)
+        } else {
+            (c, Complex::new(0.0, 0.0))
+        };
+
+        let epsilon = CONVERGENCE * CONVERGENCE;
+        let mut previous = f64::INFINITY;
+        for n in 1..=max_iter {
+            let step = self.relaxation * self.polynomial.eval(z) / self.derivative.eval(z) - offset;
+            if !(step.re.is_finite() && step.im.is_finite()) {
+                // Critical point or escape, it will not converge
+                break;
+            }
+            z -= step;
+            let distance = step.norm();
+            if distance < epsilon {
+                let root = (!self.nova).then(|| self.nearest_root(z)).flatten();
+                let fraction = convergence_fraction(previous, distance, epsilon);
+                return Sample::converged(n, fraction, root);
+            }
+            previous = distance;
+        }
+        max_iter.into()
+    }
+
+    /// Index of the root `z` is on.
+    fn nearest_root(&self, z: Complex<f64>) -> Option<u16> {
+        self.roots
+            .iter()
+            .position(|&root| (z - root).norm() < ROOT_DISTANCE * ROOT_DISTANCE)
+            .map(|index| index as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex<f64> {
+        Complex::new(re, im)
+    }
+
+    fn coefficients(s: &str) -> Vec<Complex<f64>> {
+        s.parse::<Polynomial>().unwrap().coefficients
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<Polynomial>().unwrap_err()
+    }
+
+    /// Whether `roots` are `expected`, in any order, within `tolerance`.
+    fn assert_roots(roots: &[Complex<f64>], expected: &[Complex<f64>], tolerance: f64) {
+        assert_eq!(roots.len(), expected.len());
+        let mut left: Vec<Complex<f64>> = roots.to_vec();
+        for &root in expected {
+            let closest = (0..left.len())
+                .min_by(|&a, &b| (left[a] - root).norm().total_cmp(&(left[b] - root).norm()))
+                .unwrap();
+            let distance = (left[closest] - root).norm().sqrt();
+            assert!(distance < tolerance, "{:?} not in {:?}", root, roots);
+            left.remove(closest);
+        }
+    }
+
+    #[test]
+    fn parse_terms() {
+        let zero = c(0.0, 0.0);
+        assert_eq!(
+            coefficients("z^5 + (1-2i)z - 3"),
+            [c(-3.0, 0.0), c(1.0, -2.0), zero, zero, zero, c(1.0, 0.0)]
+        );
+        assert_eq!(
+            coefficients("3*z^4 - 0.5i"),
+            [c(0.0, -0.5), zero, zero, zero, c(3.0, 0.0)]
+        );
+        assert_eq!(coefficients("1e-3z"), [zero, c(1e-3, 0.0)]);
+        assert_eq!(coefficients("-z + 2.5e+1"), [c(25.0, 0.0), c(-1.0, 0.0)]);
+        assert_eq!(coefficients("iz - i"), [c(0.0, -1.0), c(0.0, 1.0)]);
+        // Repeated powers add up, leading zeros are dropped
+        assert_eq!(
+            coefficients("z^2 + z + z^3 - z^3 + z^2"),
+            [zero, c(1.0, 0.0), c(2.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(error("z^5 + (1-2i z").contains("unclosed parenthesis at character 5"));
+        assert!(error("(1+x)z").contains("expected a complex number at character 2"));
+        assert!(error("z^").contains("expected a power from 0 to 64 at character 3"));
+        assert!(error("z^65").contains("expected a power from 0 to 64 at character 3"));
+        assert!(error("3*2").contains("expected z after * at character 3"));
+        assert!(error("z*2").contains("expected + or - at character 2"));
+        assert!(error("z + * 2").contains("expected a number or z at character 3"));
+        assert!(error("1.2.3z").contains("expected a number at character 1"));
+        assert!(error("3").contains("z is missing"));
+        assert!(error("z - z").contains("z is missing"));
+        assert!(error("").contains("z is missing"));
+    }
+
+    #[test]
+    fn eval_and_derivative() {
+        let p: Polynomial = "z^3 - 2z + (1+i)".parse().unwrap();
+        assert_eq!(p.degree(), 3);
+        assert_eq!(p.eval(c(2.0, 0.0)), c(5.0, 1.0));
+        assert_eq!(p.derivative().eval(c(2.0, 0.0)), c(10.0, 0.0));
+    }
+
+    #[test]
+    fn roots_of_unity() {
+        let roots = Polynomial::default().roots();
+        let h = 3f64.sqrt() / 2.0;
+        assert_roots(&roots, &[c(1.0, 0.0), c(-0.5, h), c(-0.5, -h)], 1e-12);
+    }
+
+    #[test]
+    fn repeated_roots() {
+        // (z - 1)² (z + 2), the double root is found less precisely
+        let roots = "z^3 - 3z + 2".parse::<Polynomial>().unwrap().roots();
+        assert_roots(&roots, &[c(1.0, 0.0), c(1.0, 0.0), c(-2.0, 0.0)], 1e-6);
+    }
+
+    #[test]
+    fn complex_coefficients() {
+        // (z - i)(z + 1 - i) = z² + (1 - 2i)z - 1 - i
+        let roots = "z^2 + (1-2i)z - 1 - i"
+            .parse::<Polynomial>()
+            .unwrap()
+            .roots();
+        assert_roots(&roots, &[c(0.0, 1.0), c(-1.0, 1.0)], 1e-12);
+    }
+
+    #[test]
+    fn newton_reaches_the_nearest_root() {
+        let newton = Newton::new(Polynomial::default(), c(1.0, 0.0), false);
+        let sample = newton.sample(c(2.0, 0.1), 100);
+        assert!(sample.converged);
+        let root = newton.roots[sample.root.unwrap() as usize];
+        assert!((root - c(1.0, 0.0)).norm() < 1e-12);
+    }
+}
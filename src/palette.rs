@@ -0,0 +1,690 @@
+use std::fmt;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::color::LinearColor;
+use crate::gradient::{hsv_to_rgb, rgb_to_hsv, Gradient};
+use crate::mandelbrot::PixelColor;
+
+/// Number of stops a GIMP gradient is resampled to.
+const SAMPLES: usize = 256;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    Format(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "{}", err),
+            PaletteError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PaletteError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(err: std::io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+fn parse_error<T>(line: usize, message: impl Into<String>) -> Result<T, PaletteError> {
+    Err(PaletteError::Parse {
+        line,
+        message: message.into(),
+    })
+}
+
+/// Load a palette file, picking the parser from the file extension.
+///
+/// Supported formats are Fractint `.map`, GIMP `.ggr`, UltraFractal `.ugr`
+/// and our own `.grad` stop list (see [`parse_grad`]).
+pub fn load(path: &Path) -> Result<Gradient, PaletteError> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    let parse = match extension.as_deref() {
+        Some("map") => parse_map,
+        Some("ggr") => parse_ggr,
+        Some("ugr") => parse_ugr,
+        Some("grad") => parse_grad,
+        _ => {
+            return Err(PaletteError::Format(format!(
+                "unknown palette format for {} (expected .map, .ggr, .ugr or .grad)",
+                path.display()
+            )))
+        }
+    };
+
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Fractint `.map`: one `r g b` triplet per line, anything after is a comment.
+pub fn parse_map(content: &str) -> Result<Gradient, PaletteError> {
+    let mut colors = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        if fields.len() < 3 {
+            return parse_error(line_no, "expected three color values");
+        }
+
+        let mut rgb = [0u8; 3];
+        for (channel, field) in rgb.iter_mut().zip(&fields) {
+            *channel = match field.parse::<u8>() {
+                Ok(value) => value,
+                Err(_) => return parse_error(line_no, format!("invalid color value '{}'", field)),
+            };
+        }
+
+        colors.push(PixelColor::new(rgb[0], rgb[1], rgb[2], 255));
+    }
+
+    if colors.len() > 256 {
+        return Err(PaletteError::Format(format!(
+            "a .map palette holds at most 256 colors, found {}",
+            colors.len()
+        )));
+    }
+    if colors.len() < 2 {
+        return Err(PaletteError::Format(
+            "a palette needs at least two colors".into(),
+        ));
+    }
+    Ok(Gradient::evenly_spaced(&colors))
+}
+
+/// UltraFractal `.ugr`: the first gradient of the file, made of
+/// `index=<0..399> color=<BGR integer>` pairs.
+pub fn parse_ugr(content: &str) -> Result<Gradient, PaletteError> {
+    let mut stops = Vec::new();
+    let mut index = None;
+    let mut in_gradient = false;
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+
+        if line.starts_with('}') {
+            if in_gradient {
+                break;
+            }
+            continue;
+        }
+        if line.starts_with("gradient:") {
+            in_gradient = true;
+            continue;
+        }
+        if !in_gradient {
+            continue;
+        }
+
+        for token in line.split_whitespace() {
+            if let Some(value) = token.strip_prefix("index=") {
+                let value = match value.parse::<i32>() {
+                    Ok(value) if (0..400).contains(&value) => value,
+                    _ => return parse_error(line_no, format!("invalid index '{}'", value)),
+                };
+                index = Some(value);
+            } else if let Some(value) = token.strip_prefix("color=") {
+                let Some(idx) = index.take() else {
+                    return parse_error(line_no, "color without a preceding index");
+                };
+                let Ok(bgr) = value.parse::<u32>() else {
+                    return parse_error(line_no, format!("invalid color '{}'", value));
+                };
+                let color = PixelColor::new(
+                    (bgr & 0xff) as u8,
+                    ((bgr >> 8) & 0xff) as u8,
+                    ((bgr >> 16) & 0xff) as u8,
+                    255,
+                );
+                stops.push((idx as f32 / 400.0, color));
+            }
+        }
+    }
+
+    if !in_gradient {
+        return Err(PaletteError::Format("no 'gradient:' section found".into()));
+    }
+    // UltraFractal gradients wrap around, close the loop on the first stop.
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some(&(_, first)) = stops.first() {
+        stops.push((1.0, first));
+    }
+    gradient_from_stops(stops)
+}
+
+/// Our own stop list: one `<position> <color>` per line where the position
+/// is in `[0, 1]` and the color is either `#rrggbb[aa]` or `r g b [a]`.
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// # black to magenta to yellow
+/// 0.0  #000000
+/// 0.3  255 0 255
+/// 1.0  #ffff00
+/// ```
+pub fn parse_grad(content: &str) -> Result<Gradient, PaletteError> {
+    let mut stops: Vec<(f32, PixelColor)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let position = fields.next().unwrap_or_default();
+        let position = match position.parse::<f32>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => p,
+            _ => return parse_error(line_no, format!("invalid stop position '{}'", position)),
+        };
+        if let Some(&(last, _)) = stops.last() {
+            if position < last {
+                return parse_error(line_no, "stop positions must be increasing");
+            }
+        }
+
+        let fields: Vec<&str> = fields.collect();
+        let color = match fields.as_slice() {
+            [hex] if hex.starts_with('#') => match parse_hex(&hex[1..]) {
+                Some(color) => color,
+                None => return parse_error(line_no, format!("invalid hex color '{}'", hex)),
+            },
+            [r, g, b] | [r, g, b, _] => {
+                let a = fields.get(3).copied().unwrap_or("255");
+                match (r.parse(), g.parse(), b.parse(), a.parse()) {
+                    (Ok(r), Ok(g), Ok(b), Ok(a)) => PixelColor::new(r, g, b, a),
+                    _ => return parse_error(line_no, "color values must be in 0..=255"),
+                }
+            }
+            _ => return parse_error(line_no, "expected '#rrggbb[aa]' or 'r g b [a]'"),
+        };
+
+        stops.push((position, color));
+    }
+
+    gradient_from_stops(stops)
+}
+
+/// Color written `rrggbb[aa]`, in hexadecimal.
+pub fn parse_hex(hex: &str) -> Option<PixelColor> {
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    let a = if hex.len() == 8 { channel(6)? } else { 255 };
+    Some(PixelColor::new(channel(0)?, channel(2)?, channel(4)?, a))
+}
+
+/// A single GIMP gradient segment.
+struct GgrSegment {
+    left: f32,
+    middle: f32,
+    right: f32,
+    left_color: [f32; 4],
+    right_color: [f32; 4],
+    blend: u8,
+    coloring: u8,
+}
+
+impl GgrSegment {
+    fn blend_factor(&self, pos: f32) -> f32 {
+        let len = self.right - self.left;
+        let (pos, middle) = if len < f32::EPSILON {
+            (0.5, 0.5)
+        } else {
+            ((pos - self.left) / len, (self.middle - self.left) / len)
+        };
+
+        // GIMP's curved "midpoint" mapping, then the blend curve on top
+        let factor = if pos <= middle {
+            if middle < f32::EPSILON {
+                0.0
+            } else {
+                0.5 * pos / middle
+            }
+        } else if middle > 1.0 - f32::EPSILON {
+            1.0
+        } else {
+            0.5 + 0.5 * (pos - middle) / (1.0 - middle)
+        };
+
+        match self.blend {
+            1 => {
+                let middle = middle.max(f32::EPSILON);
+                pos.powf(0.5f32.ln() / middle.ln())
+            }
+            2 => ((-std::f32::consts::FRAC_PI_2 + std::f32::consts::PI * factor).sin() + 1.0) / 2.0,
+            3 => {
+                let factor = factor - 1.0;
+                (1.0 - factor * factor).sqrt()
+            }
+            4 => 1.0 - (1.0 - factor * factor).sqrt(),
+            5 => {
+                if pos >= middle {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => factor,
+        }
+    }
+
+    fn color_at(&self, pos: f32) -> LinearColor {
+        let t = self.blend_factor(pos);
+        let [r0, g0, b0, a0] = self.left_color;
+        let [r1, g1, b1, a1] = self.right_color;
+
+        let (r, g, b) = match self.coloring {
+            1 | 2 => {
+                let (h0, s0, v0) = rgb_to_hsv(r0, g0, b0);
+                let (h1, s1, v1) = rgb_to_hsv(r1, g1, b1);
+                // 1 is counter-clockwise (increasing hue), 2 is clockwise
+                let mut dh = h1 - h0;
+                if self.coloring == 1 && dh < 0.0 {
+                    dh += 1.0;
+                } else if self.coloring == 2 && dh > 0.0 {
+                    dh -= 1.0;
+                }
+                let h = (h0 + dh * t).rem_euclid(1.0);
+                hsv_to_rgb(h, s0 + (s1 - s0) * t, v0 + (v1 - v0) * t)
+            }
+            _ => (r0 + (r1 - r0) * t, g0 + (g1 - g0) * t, b0 + (b1 - b0) * t),
+        };
+        let a = a0 + (a1 - a0) * t;
+
+        LinearColor::from_srgb([r, g, b, a].map(|c| c.clamp(0.0, 1.0)))
+    }
+}
+
+/// GIMP `.ggr` gradient, made of blended segments.
+pub fn parse_ggr(content: &str) -> Result<Gradient, PaletteError> {
+    let mut lines = content.lines().enumerate();
+
+    match lines.next() {
+        Some((_, line)) if line.trim() == "GIMP Gradient" => {}
+        _ => return parse_error(1, "missing 'GIMP Gradient' header"),
+    }
+
+    let (line_no, count_line) = match lines.next() {
+        Some((i, line)) if line.starts_with("Name:") => match lines.next() {
+            Some((i, line)) => (i + 1, line),
+            None => return parse_error(i + 2, "missing segment count"),
+        },
+        Some((i, line)) => (i + 1, line),
+        None => return parse_error(2, "missing segment count"),
+    };
+    let count = match count_line.trim().parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => return parse_error(line_no, format!("invalid segment count '{}'", count_line)),
+    };
+
+    let mut segments = Vec::with_capacity(count);
+    for (i, line) in lines.by_ref().take(count) {
+        let line_no = i + 1;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 13 {
+            return parse_error(line_no, "a segment needs at least 13 fields");
+        }
+
+        let mut values = [0f32; 11];
+        for (value, field) in values.iter_mut().zip(&fields) {
+            *value = match field.parse() {
+                Ok(value) => value,
+                Err(_) => return parse_error(line_no, format!("invalid number '{}'", field)),
+            };
+        }
+        let (Ok(blend), Ok(coloring)) = (fields[11].parse::<u8>(), fields[12].parse::<u8>()) else {
+            return parse_error(line_no, "invalid blend or coloring type");
+        };
+        if blend > 5 || coloring > 2 {
+            return parse_error(line_no, "unknown blend or coloring type");
+        }
+
+        let segment = GgrSegment {
+            left: values[0],
+            middle: values[1],
+            right: values[2],
+            left_color: [values[3], values[4], values[5], values[6]],
+            right_color: [values[7], values[8], values[9], values[10]],
+            blend,
+            coloring,
+        };
+        if !(segment.left <= segment.middle && segment.middle <= segment.right) {
+            return parse_error(
+                line_no,
+                "segment positions must satisfy left <= middle <= right",
+            );
+        }
+        segments.push(segment);
+    }
+
+    if segments.len() != count {
+        return Err(PaletteError::Format(format!(
+            "expected {} segments, found {}",
+            count,
+            segments.len()
+        )));
+    }
+
+    let colors: Vec<LinearColor> = (0..SAMPLES)
+        .map(|i| {
+            let pos = i as f32 / (SAMPLES - 1) as f32;
+            let segment = segments
+                .iter()
+                .find(|s| pos <= s.right)
+                .unwrap_or(&segments[segments.len() - 1]);
+            segment.color_at(pos)
+        })
+        .collect();
+    Ok(Gradient::evenly_spaced(&colors))
+}
+
+/// Cosine palette `a + b * cos(2π (c * t + d))`, each parameter holding the
+/// red, green and blue terms.
+pub fn cosine(a: [f32; 3], b: [f32; 3], c: [f32; 3], d: [f32; 3]) -> Gradient {
+    let colors: Vec<LinearColor> = (0..SAMPLES)
+        .map(|i| {
+            let t = i as f32 / (SAMPLES - 1) as f32;
+            let channel =
+                |k: usize| a[k] + b[k] * (std::f32::consts::TAU * (c[k] * t + d[k])).cos();
+            let [r, g, b] = [0, 1, 2].map(|k| channel(k).clamp(0.0, 1.0));
+            LinearColor::from_srgb([r, g, b, 1.0])
+        })
+        .collect();
+    Gradient::evenly_spaced(&colors)
+}
+
+/// Parse the twelve comma separated `a`, `b`, `c` and `d` terms of a cosine
+/// palette, e.g. `0.5,0.5,0.5,0.5,0.5,0.5,1,1,1,0,0.33,0.67`.
+pub fn parse_cosine(spec: &str) -> Result<Gradient, PaletteError> {
+    let values = spec
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| PaletteError::Format(format!("invalid cosine palette term: {}", err)))?;
+
+    let [ar, ag, ab, br, bg, bb, cr, cg, cb, dr, dg, db] = values[..] else {
+        return Err(PaletteError::Format(format!(
+            "a cosine palette needs 12 terms (a, b, c and d for r, g and b), got {}",
+            values.len()
+        )));
+    };
+    Ok(cosine(
+        [ar, ag, ab],
+        [br, bg, bb],
+        [cr, cg, cb],
+        [dr, dg, db],
+    ))
+}
+
+/// Random palette of `count` colors, always the same for a given `seed`.
+///
+/// The last color repeats the first one so the palette loops seamlessly.
+pub fn random(seed: u64, count: usize) -> Gradient {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut colors: Vec<LinearColor> = (0..count.max(2))
+        .map(|_| {
+            let (r, g, b) = hsv_to_rgb(
+                rng.gen_range(0.0..1.0),
+                rng.gen_range(0.4..1.0),
+                rng.gen_range(0.2..1.0),
+            );
+            LinearColor::from_srgb([r, g, b, 1.0])
+        })
+        .collect();
+    colors.push(colors[0]);
+    Gradient::evenly_spaced(&colors)
+}
+
+fn gradient_from_stops(stops: Vec<(f32, PixelColor)>) -> Result<Gradient, PaletteError> {
+    Gradient::new(stops).map_err(PaletteError::Format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(r: u8, g: u8, b: u8) -> LinearColor {
+        PixelColor::new(r, g, b, 255).into()
+    }
+
+    fn positions(gradient: &Gradient) -> Vec<f32> {
+        gradient.stops().iter().map(|&(p, _)| p).collect()
+    }
+
+    fn colors(gradient: &Gradient) -> Vec<LinearColor> {
+        gradient.stops().iter().map(|&(_, c)| c).collect()
+    }
+
+    fn assert_srgb(color: LinearColor, expected: [f32; 3]) {
+        let [r, g, b, _] = color.to_srgb();
+        for (value, expected) in [r, g, b].into_iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 0.01,
+                "{:?} != {:?}",
+                [r, g, b],
+                expected
+            );
+        }
+    }
+
+    fn line_of(err: PaletteError) -> usize {
+        match err {
+            PaletteError::Parse { line, .. } => line,
+            err => panic!("expected a parse error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn map_spreads_colors_evenly() {
+        let gradient = parse_map("0 0 0 black\n\n255 128 0\n10 20 30 comment").unwrap();
+        assert_eq!(positions(&gradient), [0.0, 0.5, 1.0]);
+        assert_eq!(
+            colors(&gradient),
+            [rgb(0, 0, 0), rgb(255, 128, 0), rgb(10, 20, 30)]
+        );
+    }
+
+    #[test]
+    fn map_errors() {
+        assert_eq!(line_of(parse_map("0 0 0\n1 2\n").unwrap_err()), 2);
+        assert_eq!(line_of(parse_map("0 0 0\n0 0 256\n").unwrap_err()), 2);
+        assert!(matches!(parse_map("0 0 0"), Err(PaletteError::Format(_))));
+        let too_many = "1 2 3\n".repeat(257);
+        assert!(matches!(parse_map(&too_many), Err(PaletteError::Format(_))));
+    }
+
+    #[test]
+    fn ugr_reads_the_first_gradient_and_wraps() {
+        let content = "\
+first {
+gradient:
+  title=\"first\" smooth=no
+  index=0 color=255
+  index=200 color=16711680
+}
+second {
+gradient:
+  index=100 color=65280
+}";
+        let gradient = parse_ugr(content).unwrap();
+        assert_eq!(positions(&gradient), [0.0, 0.5, 1.0]);
+        // Colors are stored as BGR integers
+        assert_eq!(
+            colors(&gradient),
+            [rgb(255, 0, 0), rgb(0, 0, 255), rgb(255, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn ugr_errors() {
+        assert!(matches!(
+            parse_ugr("index=0 color=255"),
+            Err(PaletteError::Format(_))
+        ));
+        let err = parse_ugr("g {\ngradient:\n  index=400 color=0\n}").unwrap_err();
+        assert_eq!(line_of(err), 3);
+        let err = parse_ugr("g {\ngradient:\n  color=0\n}").unwrap_err();
+        assert_eq!(line_of(err), 3);
+    }
+
+    #[test]
+    fn grad_reads_hex_and_decimal_stops() {
+        let content = "\
+# black to magenta to yellow
+0.0  #000000
+0.3  255 0 255
+
+1.0  #ffff0080
+";
+        let gradient = parse_grad(content).unwrap();
+        assert_eq!(positions(&gradient), [0.0, 0.3, 1.0]);
+        let colors = colors(&gradient);
+        assert_eq!(colors[..2], [rgb(0, 0, 0), rgb(255, 0, 255)]);
+        assert_eq!(colors[2], PixelColor::new(255, 255, 0, 128).into());
+    }
+
+    #[test]
+    fn grad_errors() {
+        assert_eq!(
+            line_of(parse_grad("0.5 #000000\n0.2 #ffffff").unwrap_err()),
+            2
+        );
+        assert_eq!(line_of(parse_grad("1.5 #000000").unwrap_err()), 1);
+        assert_eq!(line_of(parse_grad("0 #00000g").unwrap_err()), 1);
+        assert_eq!(line_of(parse_grad("0 1 2").unwrap_err()), 1);
+        assert_eq!(line_of(parse_grad("0 0 0 300").unwrap_err()), 1);
+    }
+
+    /// One GIMP gradient segment from `left` to `right` color over `[0, 1]`.
+    fn ggr(left: [f32; 3], right: [f32; 3], blend: u8, coloring: u8) -> Gradient {
+        let [r0, g0, b0] = left;
+        let [r1, g1, b1] = right;
+        parse_ggr(&format!(
+            "GIMP Gradient\nName: test\n1\n\
+             0 0.5 1 {r0} {g0} {b0} 1 {r1} {g1} {b1} 1 {blend} {coloring}\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn ggr_segments_are_resampled() {
+        let gradient = parse_ggr(
+            "GIMP Gradient\nName: two\n2\n\
+             0 0.25 0.5 0 0 0 1 1 0 0 1 0 0\n\
+             0.5 0.75 1 1 0 0 1 1 1 1 1 0 0\n",
+        )
+        .unwrap();
+        let positions = positions(&gradient);
+        assert_eq!(positions.len(), SAMPLES);
+        assert_eq!((positions[0], positions[SAMPLES - 1]), (0.0, 1.0));
+        assert_srgb(gradient.sample(0.0), [0.0, 0.0, 0.0]);
+        assert_srgb(gradient.sample(0.5), [1.0, 0.0, 0.0]);
+        assert_srgb(gradient.sample(1.0), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn ggr_blend_modes() {
+        let (black, white) = ([0.0; 3], [1.0; 3]);
+        let linear = ggr(black, white, 0, 0);
+        assert_srgb(linear.sample(0.25), [0.25; 3]);
+
+        let sine = ggr(black, white, 2, 0);
+        let expected = (1.0 - std::f32::consts::FRAC_1_SQRT_2) / 2.0;
+        assert_srgb(sine.sample(0.25), [expected; 3]);
+
+        let sphere_increasing = ggr(black, white, 3, 0);
+        assert_srgb(sphere_increasing.sample(0.5), [0.75f32.sqrt(); 3]);
+
+        let sphere_decreasing = ggr(black, white, 4, 0);
+        assert_srgb(sphere_decreasing.sample(0.5), [1.0 - 0.75f32.sqrt(); 3]);
+
+        let step = ggr(black, white, 5, 0);
+        assert_srgb(step.sample(0.45), black);
+        assert_srgb(step.sample(0.55), white);
+    }
+
+    #[test]
+    fn ggr_coloring_modes() {
+        let (red, blue) = ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]);
+        // Plain RGB goes through purple, increasing hue through green and
+        // decreasing hue through magenta
+        assert_srgb(ggr(red, blue, 0, 0).sample(0.5), [0.5, 0.0, 0.5]);
+        assert_srgb(ggr(red, blue, 0, 1).sample(0.5), [0.0, 1.0, 0.0]);
+        assert_srgb(ggr(red, blue, 0, 2).sample(0.5), [1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn ggr_errors() {
+        assert_eq!(line_of(parse_ggr("GIMP Palette\n1\n").unwrap_err()), 1);
+        assert_eq!(
+            line_of(parse_ggr("GIMP Gradient\nName: x\nzero\n").unwrap_err()),
+            3
+        );
+        let short = "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1\n";
+        assert_eq!(line_of(parse_ggr(short).unwrap_err()), 3);
+        let blend = "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 6 0\n";
+        assert_eq!(line_of(parse_ggr(blend).unwrap_err()), 3);
+        let order = "GIMP Gradient\n1\n0.5 0.2 1 0 0 0 1 1 1 1 1 0 0\n";
+        assert_eq!(line_of(parse_ggr(order).unwrap_err()), 3);
+        let missing = "GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n";
+        assert!(matches!(parse_ggr(missing), Err(PaletteError::Format(_))));
+    }
+
+    #[test]
+    fn cosine_terms() {
+        // Red peaks at both ends, green is shifted by a third of a turn and
+        // blue stays flat
+        let gradient = parse_cosine("0.5,0.5,0.5, 0.5,0.5,0, 1,1,1, 0,0.25,0").unwrap();
+        assert_srgb(gradient.sample(0.0), [1.0, 0.5, 0.5]);
+        assert_srgb(gradient.sample(0.25), [0.5, 0.0, 0.5]);
+        assert_srgb(gradient.sample(0.5), [0.0, 0.5, 0.5]);
+        assert_srgb(gradient.sample(1.0), [1.0, 0.5, 0.5]);
+
+        // Terms out of range are clamped
+        let bright = cosine([1.0; 3], [1.0; 3], [0.0; 3], [0.0; 3]);
+        assert_srgb(bright.sample(0.5), [1.0; 3]);
+
+        assert!(matches!(
+            parse_cosine("0.5,0.5,0.5,0.5,0.5,0.5,1,1,1,0,0.33"),
+            Err(PaletteError::Format(_))
+        ));
+        assert!(matches!(
+            parse_cosine("0.5,0.5,0.5,0.5,0.5,0.5,1,1,1,0,0.33,x"),
+            Err(PaletteError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn random_palettes_follow_the_seed() {
+        let palette = random(42, 8);
+        assert_eq!(positions(&palette).len(), 9);
+        assert_eq!(colors(&palette), colors(&random(42, 8)));
+        assert_ne!(colors(&palette), colors(&random(43, 8)));
+
+        // The last color loops back to the first one
+        let colors = colors(&palette);
+        assert_eq!(colors[0], colors[8]);
+
+        assert_eq!(positions(&random(7, 0)).len(), 3);
+    }
+}
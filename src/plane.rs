@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+use crate::complex::Complex;
+
+/// Non-linear mapping applied between the (rotated, transformed) view and
+/// the formula input, to look at the plane from another angle.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum PlaneTransform {
+    #[default]
+    Identity,
+    /// `c = 1 / (w - mu) + shift`, brings infinity to the center of the view.
+    Inversion {
+        mu: Complex<f64>,
+        shift: Complex<f64>,
+    },
+    /// Exponential map `c = center + e^w`: the horizontal axis is the log of
+    /// the distance to `center` and the vertical axis the angle around it,
+    /// which unrolls spirals into straight lines.
+    LogPolar { center: Complex<f64> },
+    /// The view shows a unit Riemann sphere (orthographic projection),
+    /// tilted by `tilt` radians around the horizontal axis then projected
+    /// stereographically on the plane. Untilted, the origin is at the center
+    /// and the escape circle on the rim. `shift` is added to the result.
+    Sphere { tilt: f64, shift: Complex<f64> },
+}
+
+/// Kind of plane transform, as selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaneKind {
+    #[default]
+    Identity,
+    Inversion,
+    LogPolar,
+    Sphere,
+}
+
+impl FromStr for PlaneKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "identity" => Ok(Self::Identity),
+            "inversion" => Ok(Self::Inversion),
+            "log-polar" => Ok(Self::LogPolar),
+            "sphere" => Ok(Self::Sphere),
+            _ => Err(format!(
+                "unknown plane transform '{}' (expected identity, inversion, log-polar or sphere)",
+                s
+            )),
+        }
+    }
+}
+
+impl PlaneTransform {
+    /// Build a transform of the given kind. `mu` is the inversion pole or
+    /// the log-polar center, `shift` is added after inversion or projection
+    /// and `tilt` (radians) turns the sphere.
+    pub fn new(kind: PlaneKind, mu: Complex<f64>, shift: Complex<f64>, tilt: f64) -> Self {
+        match kind {
+            PlaneKind::Identity => PlaneTransform::Identity,
+            PlaneKind::Inversion => PlaneTransform::Inversion { mu, shift },
+            PlaneKind::LogPolar => PlaneTransform::LogPolar { center: mu },
+            PlaneKind::Sphere => PlaneTransform::Sphere { tilt, shift },
+        }
+    }
+
+    /// Center and scale of a view framing the whole set through this
+    /// transform, `None` to keep the usual view.
+    pub fn default_view(&self) -> Option<(Complex<f64>, f64)> {
+        match self {
+            PlaneTransform::Identity => None,
+            PlaneTransform::Inversion { .. } => Some((Complex::new(0.0, 0.0), 4.0)),
+            // Radii from about e^-6 to e^2 around the center, over a full turn
+            PlaneTransform::LogPolar { .. } => {
+                Some((Complex::new(-2.0, 0.0), std::f64::consts::TAU))
+            }
+            PlaneTransform::Sphere { .. } => Some((Complex::new(0.0, 0.0), 2.2)),
+        }
+    }
+
+    /// Map a view point to the formula input. Points sent to infinity (the
+    /// pole of an inversion, the north pole of the sphere) come out
+    /// infinite, points with no image (outside of the sphere) NaN.
+    pub fn apply(&self, w: Complex<f64>) -> Complex<f64> {
+        match *self {
+            PlaneTransform::Identity => w,
+            PlaneTransform::Inversion { mu, shift } => Complex::new(1.0, 0.0) / (w - mu) + shift,
+            PlaneTransform::LogPolar { center } => center + w.exp(),
+            PlaneTransform::Sphere { tilt, shift } => {
+                let r2 = w.norm();
+                if r2 > 1.0 {
+                    return Complex::new(f64::NAN, f64::NAN);
+                }
+
+                // Visible hemisphere faces the south pole (the origin), then
+                // tilt it around the x axis
+                let (x, y, z) = (w.re, w.im, -(1.0 - r2).sqrt());
+                let (sin, cos) = tilt.sin_cos();
+                let (y, z) = (y * cos - z * sin, y * sin + z * cos);
+
+                // Stereographic projection from the north pole (0, 0, 1),
+                // scaled to put the equator on the escape circle |c| = 2
+                let d = (1.0 - z) / 2.0;
+                if d == 0.0 {
+                    return Complex::new(f64::INFINITY, 0.0);
+                }
+                Complex::new(x / d, y / d) + shift
+            }
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use libloading::Library;
+
+use crate::complex::Complex;
+use crate::mandelbrot::Sample;
+
+/// Version of `plugins/mandelbrot_plugin.h` we implement.
+pub const ABI_VERSION: u32 = 1;
+/// Root of the samples without one.
+const NO_ROOT: u32 = u32::MAX;
+
+/// `MandelbrotPluginInfo`
+#[repr(C)]
+struct Info {
+    abi_version: u32,
+    name: *const c_char,
+    description: *const c_char,
+    center_re: f64,
+    center_im: f64,
+    scale: f64,
+}
+
+/// `MandelbrotSample`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawSample {
+    iter: u32,
+    converged: u32,
+    fraction: f32,
+    root: u32,
+}
+
+impl RawSample {
+    /// What a plugin that writes nothing returns.
+    fn unset(max_iter: u32) -> Self {
+        Self {
+            iter: max_iter,
+            converged: 0,
+            fraction: 0.0,
+            root: NO_ROOT,
+        }
+    }
+
+    fn into_sample(self, max_iter: u32) -> Sample {
+        Sample {
+            iter: self.iter.min(max_iter),
+            ..self.into()
+        }
+    }
+}
+
+impl From<RawSample> for Sample {
+    fn from(raw: RawSample) -> Self {
+        Sample {
+            iter: raw.iter,
+            converged: raw.converged != 0,
+            fraction: if raw.fraction.is_finite() {
+                raw.fraction.clamp(0.0, 1.0)
+            } else {
+                0.0
+            },
+            root: match raw.root {
+                NO_ROOT => None,
+                root => u16::try_from(root).ok(),
+            },
+        }
+    }
+}
+
+type InfoFn = unsafe extern "C" fn() -> *const Info;
+type InitFn = unsafe extern "C" fn(*const c_char, *mut *mut c_void) -> i32;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type IteratePixelFn = unsafe extern "C" fn(*const c_void, f64, f64, u32, *mut RawSample);
+type IterateRowFn =
+    unsafe extern "C" fn(*const c_void, *const f64, *const f64, usize, u32, *mut RawSample);
+
+#[derive(Debug)]
+pub enum PluginError {
+    Load(libloading::Error),
+    /// A required function is not exported.
+    Missing(&'static str),
+    Abi {
+        found: u32,
+    },
+    Init(i32),
+    Format(String),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Load(err) => write!(f, "{}", err),
+            PluginError::Missing(name) => write!(f, "the plugin does not export {}", name),
+            PluginError::Abi { found } => write!(
+                f,
+                "the plugin was built for ABI version {}, not {}",
+                found, ABI_VERSION
+            ),
+            PluginError::Init(code) => write!(f, "the plugin failed to start (code {})", code),
+            PluginError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<libloading::Error> for PluginError {
+    fn from(err: libloading::Error) -> Self {
+        PluginError::Load(err)
+    }
+}
+
+/// Formula from a shared library implementing the C interface of
+/// `plugins/mandelbrot_plugin.h`.
+pub struct Plugin {
+    path: PathBuf,
+    params: String,
+    name: String,
+    description: String,
+    view: (Complex<f64>, f64),
+    state: *mut c_void,
+    /// At least one of them is exported.
+    iterate_pixel: Option<IteratePixelFn>,
+    iterate_row: Option<IterateRowFn>,
+    free: Option<FreeFn>,
+    /// Dropped last, the functions above point into it.
+    _library: Library,
+}
+
+// The interface requires the iterate functions to accept concurrent calls
+// with the state, which they do not modify.
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("path", &self.path)
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+impl Plugin {
+    /// Load the plugin at `path` and start it with `params`.
+    pub fn load(path: &Path, params: &str) -> Result<Self, PluginError> {
+        // Running the initializers of a library is unsafe: the user vouches
+        // for the plugin by loading it
+        let library = unsafe { Library::new(path)? };
+        let info: InfoFn = symbol(&library, "mandelbrot_plugin_info")
+            .ok_or(PluginError::Missing("mandelbrot_plugin_info"))?;
+        let init: Option<InitFn> = symbol(&library, "mandelbrot_plugin_init");
+        let free: Option<FreeFn> = symbol(&library, "mandelbrot_plugin_free");
+        let iterate_pixel: Option<IteratePixelFn> =
+            symbol(&library, "mandelbrot_plugin_iterate_pixel");
+        let iterate_row: Option<IterateRowFn> = symbol(&library, "mandelbrot_plugin_iterate_row");
+        if iterate_pixel.is_none() && iterate_row.is_none() {
+            return Err(PluginError::Missing(
+                "mandelbrot_plugin_iterate_pixel or mandelbrot_plugin_iterate_row",
+            ));
+        }
+
+        let info = unsafe { info().as_ref() }
+            .ok_or_else(|| PluginError::Format("the plugin has no info".into()))?;
+        if info.abi_version != ABI_VERSION {
+            return Err(PluginError::Abi {
+                found: info.abi_version,
+            });
+        }
+        let text = |text: *const c_char| {
+            if text.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(text) }
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        };
+        let name = text(info.name);
+        let description = text(info.description);
+        let view = (Complex::new(info.center_re, info.center_im), info.scale);
+        if !(view.0.re.is_finite() && view.0.im.is_finite() && view.1 > 0.0) {
+            return Err(PluginError::Format(format!(
+                "the plugin has an invalid view: center {}, {} and scale {}",
+                view.0.re, view.0.im, view.1
+            )));
+        }
+
+        let mut state = ptr::null_mut();
+        if let Some(init) = init {
+            let text = CString::new(params).map_err(|_| {
+                PluginError::Format("the plugin parameters contain a null character".into())
+            })?;
+            let code = unsafe { init(text.as_ptr(), &mut state) };
+            if code != 0 {
+                return Err(PluginError::Init(code));
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            params: params.to_owned(),
+            name,
+            description,
+            view,
+            state,
+            iterate_pixel,
+            iterate_row,
+            free,
+            _library: library,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Center and scale of the view the plugin opens on.
+    pub fn default_view(&self) -> (Complex<f64>, f64) {
+        self.view
+    }
+
+    /// Whether the plugin evaluates rows of points in one call.
+    pub fn takes_rows(&self) -> bool {
+        self.iterate_row.is_some()
+    }
+
+    /// Evaluate the plugin at `c`. Plugins working by rows only get a row of
+    /// one point.
+    pub fn sample(&self, c: Complex<f64>, max_iter: u32) -> Sample {
+        let mut raw = RawSample::unset(max_iter);
+        unsafe {
+            match (self.iterate_pixel, self.iterate_row) {
+                (Some(iterate), _) => iterate(self.state, c.re, c.im, max_iter, &mut raw),
+                (None, Some(iterate)) => iterate(self.state, &c.re, &c.im, 1, max_iter, &mut raw),
+                (None, None) => unreachable!("checked on load"),
+            }
+        }
+        raw.into_sample(max_iter)
+    }
+
+    /// Evaluate the plugin at each of `points` into `out`, in one call for
+    /// the plugins working by rows.
+    pub fn sample_row(&self, points: &[Complex<f64>], max_iter: u32, out: &mut [Sample]) {
+        debug_assert_eq!(points.len(), out.len());
+        let Some(iterate) = self.iterate_row else {
+            for (sample, &c) in out.iter_mut().zip(points) {
+                *sample = self.sample(c, max_iter);
+            }
+            return;
+        };
+        let re: Vec<f64> = points.iter().map(|c| c.re).collect();
+        let im: Vec<f64> = points.iter().map(|c| c.im).collect();
+        let mut raw = vec![RawSample::unset(max_iter); points.len()];
+        unsafe {
+            iterate(
+                self.state,
+                re.as_ptr(),
+                im.as_ptr(),
+                points.len(),
+                max_iter,
+                raw.as_mut_ptr(),
+            )
+        };
+        for (sample, raw) in out.iter_mut().zip(raw) {
+            *sample = raw.into_sample(max_iter);
+        }
+    }
+}
+
+/// Function `name` of `library`, as the signature `T` the header gives it.
+/// The pointer outlives the symbol, the library must be kept loaded.
+fn symbol<T: Copy>(library: &Library, name: &str) -> Option<T> {
+    unsafe { library.get::<T>(name.as_bytes()) }
+        .ok()
+        .map(|symbol| *symbol)
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(free) = self.free {
+            unsafe { free(self.state) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::formula::Formula;
+    use crate::gradient::{Gradient, Mapping};
+    use crate::mandelbrot::{MandelbrotUniverse, PixelColor, ViewPort};
+
+    /// Build the C `source` into a library in the temporary directory, `None`
+    /// when there is no C compiler.
+    fn build(name: &str, source: &Path) -> Option<PathBuf> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let library = std::env::temp_dir().join(format!(
+            "mandelbrot-plugin-{}-{}.so",
+            std::process::id(),
+            name
+        ));
+        let status = Command::new("cc")
+            .args(["-O2", "-shared", "-fPIC", "-I"])
+            .arg(root.join("plugins"))
+            .arg("-o")
+            .arg(&library)
+            .arg(source)
+            .status();
+        match status {
+            Ok(status) => {
+                assert!(status.success(), "cc failed on {}", source.display());
+                Some(library)
+            }
+            Err(err) => {
+                eprintln!("skipped, no C compiler: {}", err);
+                None
+            }
+        }
+    }
+
+    fn lambda() -> Option<Plugin> {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("plugins/example/lambda.c");
+        let library = build("lambda", &source)?;
+        let plugin = Plugin::load(&library, "3").unwrap();
+        // Loaded and mapped, the file is no longer needed
+        let _ = fs::remove_file(&library);
+        Some(plugin)
+    }
+
+    /// `z = c z (1 - z)` from 1/2, the formula of the example plugin.
+    fn lambda_iterations(c: Complex<f64>, radius: f64, max_iter: u32) -> u32 {
+        let mut z = Complex::new(0.5, 0.0);
+        let mut n = 0;
+        while z.norm() <= radius * radius && n < max_iter {
+            z = c * (z - z * z);
+            n += 1;
+        }
+        n
+    }
+
+    fn points() -> Vec<Complex<f64>> {
+        (0..200)
+            .map(|i| Complex::new(-2.0 + 0.03 * i as f64, 1.5 - 0.017 * i as f64))
+            .collect()
+    }
+
+    #[test]
+    fn example_plugin_matches_rust() {
+        let Some(plugin) = lambda() else {
+            return;
+        };
+        assert_eq!(plugin.name(), "lambda");
+        assert_eq!(plugin.default_view(), (Complex::new(1.0, 0.0), 5.0));
+        assert!(plugin.takes_rows());
+
+        let points = points();
+        let mut row = vec![Sample::default(); points.len()];
+        plugin.sample_row(&points, 80, &mut row);
+        for (&c, row) in points.iter().zip(row) {
+            let expected = Sample::from(lambda_iterations(c, 3.0, 80));
+            assert_eq!(plugin.sample(c, 80), expected, "{:?}", c);
+            assert_eq!(row, expected, "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn universes_hand_rows_to_the_plugin() {
+        let Some(plugin) = lambda().map(Arc::new) else {
+            return;
+        };
+        let gradient = Gradient::evenly_spaced(&[PixelColor::WHITE]);
+        let compute = |threads: usize, rows: bool| {
+            let sampled = plugin.clone();
+            let mut universe = MandelbrotUniverse::new(
+                37,
+                23,
+                threads,
+                &gradient,
+                Mapping::default(),
+                60,
+                move |c, max_iter| sampled.sample(c, max_iter),
+            );
+            if rows {
+                Formula::Plugin(plugin.clone()).use_in(&mut universe);
+            }
+            universe.set_view(ViewPort::new(Complex::new(1.0, 0.0), 5.0));
+            universe.compute();
+            universe.iterations()
+        };
+        let expected = compute(1, false);
+        assert_eq!(compute(1, true), expected);
+        assert_eq!(compute(3, true), expected);
+        assert!(expected.samples.iter().any(|sample| sample.iter < 60));
+    }
+
+    #[test]
+    fn plugins_without_iterate_are_refused() {
+        let source =
+            std::env::temp_dir().join(format!("mandelbrot-plugin-{}-empty.c", std::process::id()));
+        fs::write(
+            &source,
+            "#include \"mandelbrot_plugin.h\"\n\
+             static const MandelbrotPluginInfo INFO = {MANDELBROT_PLUGIN_ABI, \"empty\", 0, 0, 0, 1};\n\
+             const MandelbrotPluginInfo *mandelbrot_plugin_info(void) { return &INFO; }\n",
+        )
+        .unwrap();
+        let library = build("empty", &source);
+        let _ = fs::remove_file(&source);
+        let Some(library) = library else {
+            return;
+        };
+        let err = Plugin::load(&library, "").unwrap_err();
+        let _ = fs::remove_file(&library);
+        assert_eq!(
+            err.to_string(),
+            "the plugin does not export mandelbrot_plugin_iterate_pixel or \
+             mandelbrot_plugin_iterate_row"
+        );
+    }
+}
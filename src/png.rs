@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+
+use crate::deflate::ZlibEncoder;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Compressed data gathered before writing an IDAT chunk.
+const CHUNK_SIZE: usize = 1 << 16;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// CRC-32 of the concatenation of `parts`, as used by PNG chunks.
+pub fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+/// 8 or 16 bit RGB PNG written row by row, so images larger than memory can
+/// be streamed to disk.
+pub struct PngWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    /// Bytes per pixel.
+    bpp: usize,
+    rows: u32,
+    previous: Vec<u8>,
+    encoder: ZlibEncoder,
+    compressed: Vec<u8>,
+}
+
+impl<W: Write> PngWriter<W> {
+    pub fn new(writer: W, width: u32, height: u32) -> io::Result<Self> {
+        Self::with_depth(writer, width, height, 8)
+    }
+
+    /// PNG of `depth` bits per channel, 8 or 16.
+    pub fn with_depth(mut writer: W, width: u32, height: u32, depth: u8) -> io::Result<Self> {
+        debug_assert!(depth == 8 || depth == 16);
+        writer.write_all(&SIGNATURE)?;
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // RGB, deflate, adaptive filtering, no interlacing
+        header.extend_from_slice(&[depth, 2, 0, 0, 0]);
+        write_chunk(&mut writer, b"IHDR", &header)?;
+
+        let bpp = 3 * depth as usize / 8;
+        Ok(Self {
+            writer,
+            width,
+            height,
+            bpp,
+            rows: 0,
+            previous: vec![0; width as usize * bpp],
+            encoder: ZlibEncoder::new(),
+            compressed: Vec::new(),
+        })
+    }
+
+    /// Append the next row, `width` RGB pixels, of 16 bit big-endian
+    /// channels for 16 bit images.
+    pub fn write_row(&mut self, rgb: &[u8]) -> io::Result<()> {
+        debug_assert_eq!(rgb.len(), self.width as usize * self.bpp);
+        if self.rows == self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many rows for the PNG size",
+            ));
+        }
+
+        let (filter, row) = filter_row(rgb, &self.previous, self.bpp);
+        self.encoder.write(&[filter]);
+        self.encoder.write(&row);
+        self.previous.copy_from_slice(rgb);
+        self.rows += 1;
+
+        self.compressed.extend(self.encoder.take_output());
+        if self.compressed.len() >= CHUNK_SIZE {
+            write_chunk(&mut self.writer, b"IDAT", &self.compressed)?;
+            self.compressed.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the end of the image, every row must have been written.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.rows != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("PNG has {} rows out of {}", self.rows, self.height),
+            ));
+        }
+        self.compressed.extend(self.encoder.finish());
+        write_chunk(&mut self.writer, b"IDAT", &self.compressed)?;
+        write_chunk(&mut self.writer, b"IEND", &[])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Filter a row of `bpp` bytes per pixel with each PNG filter and keep the
+/// one with the smallest sum of absolute values, which usually compresses
+/// best.
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize) -> (u8, Vec<u8>) {
+    let left = |i: usize| if i >= bpp { row[i - bpp] } else { 0 };
+    let up_left = |i: usize| if i >= bpp { previous[i - bpp] } else { 0 };
+
+    let filters: [&dyn Fn(usize) -> u8; 5] = [
+        &|i| row[i],
+        &|i| row[i].wrapping_sub(left(i)),
+        &|i| row[i].wrapping_sub(previous[i]),
+        &|i| row[i].wrapping_sub(((left(i) as u16 + previous[i] as u16) / 2) as u8),
+        &|i| row[i].wrapping_sub(paeth(left(i), previous[i], up_left(i))),
+    ];
+
+    filters
+        .iter()
+        .enumerate()
+        .map(|(kind, filter)| {
+            let filtered: Vec<u8> = (0..row.len()).map(filter).collect();
+            (kind as u8, filtered)
+        })
+        .min_by_key(|(_, filtered)| {
+            filtered
+                .iter()
+                .map(|&b| (b as i8).unsigned_abs() as u64)
+                .sum::<u64>()
+        })
+        .expect("there are filters")
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
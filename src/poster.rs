@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::Checkpoint;
+use crate::export;
+use crate::mandelbrot::{MandelbrotUniverse, ViewPort};
+use crate::png::PngWriter;
+use crate::tiff::TiffWriter;
+
+/// Image far larger than memory, rendered tile by tile and streamed to a
+/// PNG or a tiled BigTIFF (`.tif` or `.tiff`).
+pub struct Poster {
+    width: u32,
+    height: u32,
+    tile: u32,
+    view: ViewPort,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl Poster {
+    /// A `width`×`height` image of `view`, computed in `tile`×`tile` pieces.
+    pub fn new(width: u32, height: u32, tile: u32, view: ViewPort) -> Self {
+        Self {
+            width,
+            height,
+            tile,
+            view,
+            checkpoint: None,
+        }
+    }
+
+    /// Save the completed tiles to `checkpoint` as the render goes, and
+    /// start from the tiles it already holds.
+    pub fn set_checkpoint(&mut self, checkpoint: Checkpoint) {
+        if checkpoint.tiles() > 0 {
+            let (across, down) = self.tiles();
+            log::info!(
+                "Resuming from {} tiles out of {}",
+                checkpoint.tiles(),
+                across as u64 * down as u64
+            );
+        }
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// Number of tiles across and down.
+    fn tiles(&self) -> (u32, u32) {
+        (
+            self.width.div_ceil(self.tile),
+            self.height.div_ceil(self.tile),
+        )
+    }
+
+    /// Render with `universe` and write to `path`. The universe is resized to
+    /// each tile, its other settings are kept. The checkpoint is removed once
+    /// the image is complete.
+    pub fn render(&mut self, universe: &mut MandelbrotUniverse, path: &Path) -> io::Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let writer = BufWriter::new(File::create(path)?);
+        match extension.as_deref() {
+            Some("tif" | "tiff") => self.render_tiff(universe, writer)?,
+            _ => self.render_png(universe, writer)?,
+        }
+        match self.checkpoint.take() {
+            Some(checkpoint) => checkpoint.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// PNG rows must be written in order: render a band of tiles, then
+    /// stream its rows. Memory use is one band, `width`×`tile` pixels.
+    fn render_png(
+        &mut self,
+        universe: &mut MandelbrotUniverse,
+        writer: BufWriter<File>,
+    ) -> io::Result<()> {
+        let mut png = PngWriter::new(writer, self.width, self.height)?;
+        let mut progress = Progress::new(self.width as u64 * self.height as u64);
+        let row_len = self.width as usize * 3;
+
+        for band_y in (0..self.height).step_by(self.tile as usize) {
+            let band_height = self.tile.min(self.height - band_y);
+            let mut band = vec![0; row_len * band_height as usize];
+            for tile_x in (0..self.width).step_by(self.tile as usize) {
+                let tile_width = self.tile.min(self.width - tile_x);
+                let rgb = self.render_tile(
+                    universe,
+                    &mut progress,
+                    (tile_x, band_y),
+                    (tile_width, band_height),
+                )?;
+                for (y, row) in rgb.chunks_exact(tile_width as usize * 3).enumerate() {
+                    let start = y * row_len + tile_x as usize * 3;
+                    band[start..start + row.len()].copy_from_slice(row);
+                }
+            }
+            for row in band.chunks_exact(row_len) {
+                png.write_row(row)?;
+            }
+        }
+        png.finish()?;
+        Ok(())
+    }
+
+    /// BigTIFF tiles are independent: memory use is a single tile.
+    fn render_tiff(
+        &mut self,
+        universe: &mut MandelbrotUniverse,
+        writer: BufWriter<File>,
+    ) -> io::Result<()> {
+        let mut tiff = TiffWriter::new(writer, self.width, self.height, self.tile)?;
+        let mut progress = Progress::new(self.width as u64 * self.height as u64);
+
+        for tile_y in (0..self.height).step_by(self.tile as usize) {
+            let tile_height = self.tile.min(self.height - tile_y);
+            for tile_x in (0..self.width).step_by(self.tile as usize) {
+                let tile_width = self.tile.min(self.width - tile_x);
+                let rgb = self.render_tile(
+                    universe,
+                    &mut progress,
+                    (tile_x, tile_y),
+                    (tile_width, tile_height),
+                )?;
+                tiff.write_tile(&rgb, tile_width, tile_height)?;
+            }
+        }
+        tiff.finish()?;
+        Ok(())
+    }
+
+    /// RGB pixels of the tile at `(x, y)`, computed or read back from the
+    /// checkpoint.
+    fn render_tile(
+        &mut self,
+        universe: &mut MandelbrotUniverse,
+        progress: &mut Progress,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> io::Result<Vec<u8>> {
+        universe.set_size(width, height);
+        let pixels = width as u64 * height as u64;
+        let saved = match &mut self.checkpoint {
+            Some(checkpoint) => checkpoint.next_saved()?,
+            None => None,
+        };
+        if let Some(iterations) = saved {
+            universe.set_iterations(iterations).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checkpoint tile does not fit the render: {}", err),
+                )
+            })?;
+            progress.resume(pixels);
+        } else {
+            universe.set_view(self.view.tile(x, y, width, height, self.width, self.height));
+            universe.compute();
+            if let Some(checkpoint) = &mut self.checkpoint {
+                checkpoint.append(&universe.iterations())?;
+            }
+            progress.advance(pixels);
+        }
+
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        universe.render(&mut rgba);
+        Ok(export::rgba_to_rgb(&rgba).collect())
+    }
+}
+
+/// Logs the completion and the estimated time left, at most once a second.
+pub struct Progress {
+    total: u64,
+    done: u64,
+    /// Pixels read back from a checkpoint, they do not count in the speed.
+    resumed: u64,
+    start: Instant,
+    last_report: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: u64) -> Self {
+        Self {
+            total,
+            done: 0,
+            resumed: 0,
+            start: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    /// `pixels` read back from a checkpoint.
+    pub fn resume(&mut self, pixels: u64) {
+        self.resumed += pixels;
+        self.advance(pixels);
+    }
+
+    /// `pixels` done.
+    pub fn advance(&mut self, pixels: u64) {
+        self.done += pixels;
+        let now = Instant::now();
+        let finished = self.done >= self.total;
+        if !finished
+            && self
+                .last_report
+                .is_some_and(|last| now - last < Duration::from_secs(1))
+        {
+            return;
+        }
+        self.last_report = Some(now);
+
+        let elapsed = now - self.start;
+        let percent = 100.0 * self.done as f64 / self.total.max(1) as f64;
+        let computed = self.done - self.resumed;
+        if finished {
+            log::info!("Rendered in {}", format_duration(elapsed));
+        } else if computed == 0 {
+            log::info!("{:.1}% read from the checkpoint", percent);
+        } else {
+            let eta = elapsed.mul_f64((self.total - self.done) as f64 / computed as f64);
+            log::info!("{:.1}% rendered, {} left", percent, format_duration(eta));
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
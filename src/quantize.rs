@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+/// Count of each distinct RGB color.
+pub type Histogram = HashMap<[u8; 3], u32>;
+
+pub fn add_to_histogram(histogram: &mut Histogram, rgba: &[u8]) {
+    for pixel in rgba.chunks_exact(4) {
+        *histogram.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+    }
+}
+
+/// Reduce the colors of `histogram` to at most `max_colors` with the median
+/// cut: the box of colors with the widest channel range is split at its
+/// weighted median until there are enough boxes, each box then gives its
+/// average color.
+pub fn median_cut(histogram: &Histogram, max_colors: usize) -> Vec<[u8; 3]> {
+    let colors: Vec<([u8; 3], u32)> = histogram.iter().map(|(&c, &n)| (c, n)).collect();
+    if colors.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    // Widest channel of a box, as (range, channel)
+    let widest = |colors: &[([u8; 3], u32)]| {
+        (0..3)
+            .map(|c| {
+                let (min, max) = colors.iter().fold((255, 0), |(min, max), (color, _)| {
+                    (color[c].min(min), color[c].max(max))
+                });
+                (max - min, c)
+            })
+            .max()
+            .expect("three channels")
+    };
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let Some((index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (widest(colors), i))
+            .max()
+            .map(|((_, channel), i)| (i, channel))
+        else {
+            break;
+        };
+
+        // Sorted by the whole color after the channel: ties come out in the
+        // same order whatever the order of the histogram
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|&(color, _)| (color[channel], color));
+        let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+        let mut count = 0;
+        let median = colors
+            .iter()
+            .position(|&(_, n)| {
+                count += n as u64;
+                count * 2 >= total
+            })
+            .unwrap_or(0);
+        // Both halves keep at least one color
+        let split = (median + 1).clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+            [0, 1, 2].map(|c| {
+                let sum: u64 = colors
+                    .iter()
+                    .map(|&(color, n)| color[c] as u64 * n as u64)
+                    .sum();
+                ((sum + total / 2) / total) as u8
+            })
+        })
+        .collect()
+}
+
+/// Index of the closest palette color of each pixel.
+pub fn map_to_palette(rgba: &[u8], palette: &[[u8; 3]]) -> Vec<u8> {
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    rgba.chunks_exact(4)
+        .map(|pixel| {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            *cache
+                .entry(color)
+                .or_insert_with(|| nearest(color, palette))
+        })
+        .collect()
+}
+
+fn nearest(color: [u8; 3], palette: &[[u8; 3]]) -> u8 {
+    let distance = |other: &[u8; 3]| {
+        (0..3)
+            .map(|c| (color[c] as i32 - other[c] as i32).pow(2))
+            .sum::<i32>()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, other)| distance(other))
+        .map_or(0, |(i, _)| i as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(colors: &[([u8; 3], u32)]) -> Histogram {
+        colors.iter().copied().collect()
+    }
+
+    #[test]
+    fn median_cut_keeps_few_colors() {
+        let colors = [([255, 0, 0], 10), ([0, 255, 0], 1), ([0, 0, 255], 5)];
+        let mut palette = median_cut(&histogram(&colors), 256);
+        palette.sort();
+        assert_eq!(palette, [[0, 0, 255], [0, 255, 0], [255, 0, 0]]);
+        assert_eq!(median_cut(&Histogram::new(), 16), [[0, 0, 0]]);
+    }
+
+    #[test]
+    fn median_cut_averages_by_count() {
+        let colors = [([0, 0, 0], 3), ([100, 0, 0], 1), ([200, 200, 200], 4)];
+        let palette = median_cut(&histogram(&colors), 1);
+        assert_eq!(palette, [[113, 100, 100]]);
+        assert_eq!(median_cut(&histogram(&colors), 2).len(), 2);
+    }
+
+    #[test]
+    fn median_cut_is_deterministic() {
+        // Many colors tied on every channel, in a different order each time
+        let colors: Vec<([u8; 3], u32)> = (0..4096u32)
+            .map(|i| {
+                let color = [
+                    (i % 16) as u8 * 16,
+                    (i / 16 % 16) as u8 * 16,
+                    (i / 256) as u8 * 16,
+                ];
+                (color, 1 + i % 7)
+            })
+            .collect();
+        let first = median_cut(&histogram(&colors), 64);
+        assert_eq!(first.len(), 64);
+        for _ in 0..8 {
+            // A new hasher each time: another iteration order
+            let mut reversed = colors.clone();
+            reversed.reverse();
+            assert_eq!(median_cut(&histogram(&colors), 64), first);
+            assert_eq!(median_cut(&histogram(&reversed), 64), first);
+        }
+    }
+
+    #[test]
+    fn pixels_map_to_the_nearest_color() {
+        let palette = [[0, 0, 0], [255, 255, 255], [250, 0, 0]];
+        let rgba = [10, 10, 10, 255, 240, 10, 10, 255, 200, 220, 210, 0];
+        assert_eq!(map_to_palette(&rgba, &palette), [0, 2, 1]);
+
+        let mut histogram = Histogram::new();
+        add_to_histogram(&mut histogram, &rgba);
+        add_to_histogram(&mut histogram, &rgba[..4]);
+        assert_eq!(histogram[&[10, 10, 10]], 2);
+        assert_eq!(histogram.len(), 3);
+    }
+}
@@ -1,35 +1,215 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use pixels::{Error, Pixels, SurfaceTexture};
 
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
+use crate::buddhabrot::Buddhabrot;
+use crate::complex::Complex;
+use crate::formula::Formula;
+use crate::gradient::WrapMode;
 use crate::mandelbrot::MandelbrotUniverse;
+use crate::sampling::{SamplePattern, Sampler};
 
-pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Result<(), Error> {
-    let event_loop = EventLoop::new();
+/// Palette cycling speed at startup, in palette lengths per second.
+const CYCLE_SPEED: f32 = 0.1;
+const CYCLE_SPEED_STEP: f32 = 1.5;
+
+/// View rotation applied by the Q and E keys, in radians.
+const ROTATION_STEP: f64 = std::f64::consts::PI / 36.0;
+
+/// Change of the formula parameter applied by the arrow keys.
+const PARAMETER_STEP: f64 = 0.01;
+
+/// Time between two refreshes of a density render.
+const DENSITY_REFRESH: Duration = Duration::from_millis(500);
+
+struct PaletteCycling {
+    enabled: bool,
+    speed: f32,
+    last_update: Instant,
+}
+
+impl PaletteCycling {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            speed: CYCLE_SPEED,
+            last_update: Instant::now(),
+        }
+    }
+
+    fn toggle(&mut self, universe: &mut MandelbrotUniverse) {
+        self.enabled = !self.enabled;
+        self.last_update = Instant::now();
+
+        let mut mapping = universe.mapping();
+        if self.enabled && mapping.wrap == WrapMode::Clamp {
+            // A clamped palette would stop moving once shifted past its end
+            log::info!("Palette cycling switches the palette wrap mode to repeat");
+            mapping.wrap = WrapMode::Repeat;
+            universe.set_mapping(mapping);
+        }
+        log::info!(
+            "Palette cycling {}",
+            if self.enabled { "started" } else { "stopped" }
+        );
+    }
+
+    /// Shift the palette by the time elapsed since the last update.
+    fn update(&mut self, universe: &mut MandelbrotUniverse) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        if self.enabled {
+            let mut mapping = universe.mapping();
+            mapping.offset = (mapping.offset + self.speed * elapsed).rem_euclid(1.0);
+            universe.set_mapping(mapping);
+        }
+    }
+}
+
+/// Switch the universe to `formula`, framing its whole set unless the plane
+/// transform has its own view.
+fn set_formula(universe: &mut MandelbrotUniverse, formula: &Formula) {
+    log::info!("Formula: {:?}", formula);
+    let (center, scale) = formula.default_view();
+    formula.use_in(universe);
+
+    let mut view = *universe.view();
+    if view.plane().default_view().is_none() {
+        view.set_center(center);
+        view.set_scale(scale);
+        universe.set_view(view);
+    }
+    universe.compute();
+}
+
+/// Shift the parameter of `formula`, if it has one, and recompute the view.
+fn shift_parameter(universe: &mut MandelbrotUniverse, formula: &mut Formula, delta: Complex<f64>) {
+    let Some(parameter) = formula.parameter() else {
+        return;
+    };
+    let parameter = parameter + delta;
+    log::info!("Parameter: {}, {}", parameter.re, parameter.im);
+    formula.set_parameter(parameter);
+    formula.use_in(universe);
+    universe.compute();
+}
+
+/// Window of `width`×`height` logical pixels, drawn from a frame of that
+/// many pixels.
+fn open_window(
+    event_loop: &EventLoop<()>,
+    width: u32,
+    height: u32,
+) -> Result<(Window, Pixels), Error> {
     let window = {
         let size = LogicalSize::new(width as f64, height as f64);
         WindowBuilder::new()
             .with_title("Mandelbrot")
             .with_inner_size(size)
-            .build(&event_loop)
+            .build(event_loop)
             .unwrap()
     };
 
-    let mut pixels = {
+    let pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
         Pixels::new(width, height, surface_texture)?
     };
+    Ok((window, pixels))
+}
+
+/// Open the viewer on a density render, traced on `threads` threads in the
+/// background for as long as the window is open. The image is refreshed
+/// every `DENSITY_REFRESH`.
+pub fn render_density(buddhabrot: Arc<Buddhabrot>, threads: usize) -> Result<(), Error> {
+    let (width, height) = buddhabrot.size();
+    let event_loop = EventLoop::new();
+    let (window, mut pixels) = open_window(&event_loop, width, height)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let buddhabrot = buddhabrot.clone();
+        let stop = stop.clone();
+        thread::spawn(move || buddhabrot.run_until(threads, &stop));
+    }
+
+    let start = Instant::now();
+    let mut next_refresh = start;
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::RedrawRequested(_) => {
+            if let Err(err) = pixels.render() {
+                log::error!("pixels.render: {}", err);
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+        Event::MainEventsCleared => {
+            let now = Instant::now();
+            if now >= next_refresh {
+                buddhabrot.render(pixels.frame_mut());
+                log::debug!("{} in {:.1?}", buddhabrot.statistics(), now - start);
+                next_refresh = now + DENSITY_REFRESH;
+                window.request_redraw();
+            }
+            *control_flow = ControlFlow::WaitUntil(next_refresh);
+        }
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::Resized(new_size) => {
+                if let Err(err) = pixels.resize_surface(new_size.width, new_size.height) {
+                    log::error!("pixels.resize_surface: {}", err);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::Escape) =>
+            {
+                *control_flow = ControlFlow::Exit;
+            }
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            _ => {}
+        },
+        Event::LoopDestroyed => stop.store(true, Ordering::Relaxed),
+        _ => {}
+    });
+}
+
+/// Open the viewer on `universe`, computed with the first of `formulas`. The
+/// F key switches to the next one, the arrow keys change its parameter.
+pub fn render(
+    mut universe: MandelbrotUniverse,
+    width: u32,
+    height: u32,
+    mut formulas: Vec<Formula>,
+) -> Result<(), Error> {
+    let event_loop = EventLoop::new();
+    let (window, mut pixels) = open_window(&event_loop, width, height)?;
 
     let mut last_mouse_pos = (0, 0);
 
     let mut is_left_mouse_button_pressed = false;
     let mut is_right_mouse_button_pressed = false;
+    let mut cycling = PaletteCycling::new();
+    let mut current = 0;
+
+    // Supersampling toggled with the A key, defaults to 2×2 rotated grid
+    // when started without anti-aliasing
+    let mut aa_sampler = if universe.sampler().count() > 1 {
+        universe.sampler().clone()
+    } else {
+        Sampler::new(SamplePattern::RotatedGrid, 2, 0)
+    };
 
     event_loop.run(move |event, _, control_flow| {
         // Handle events
@@ -41,7 +221,6 @@ pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Resu
                     Err(err) => {
                         log::error!("pixels.render: {}", err);
                         *control_flow = ControlFlow::Exit;
-                        return;
                     }
                 }
             }
@@ -50,6 +229,7 @@ pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Resu
                 // print!("\x1b[1A\x1b[2K");
                 // println!("FPS: {}", 1.0 / elapsed.as_secs_f64());
 
+                cycling.update(&mut universe);
                 window.request_redraw();
             }
             Event::WindowEvent {
@@ -66,14 +246,12 @@ pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Resu
                                 Err(err) => {
                                     log::error!("pixels.resize_surface: {}", err);
                                     *control_flow = ControlFlow::Exit;
-                                    return;
                                 }
                             };
                         }
                         Err(err) => {
                             log::error!("pixels.resize_buffer: {}", err);
                             *control_flow = ControlFlow::Exit;
-                            return;
                         }
                     }
                 }
@@ -84,17 +262,25 @@ pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Resu
                         let (dx, dy) = (x - last_mouse_pos.0 as f64, y - last_mouse_pos.1 as f64);
                         universe.translate(-dx, -dy);
                         window.request_redraw();
+                    } else if is_right_mouse_button_pressed {
+                        // Rotate around the window center, following the mouse
+                        let (width, height) = universe.size();
+                        let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+                        let angle = |x: f64, y: f64| (y - cy).atan2(x - cx);
+                        let delta =
+                            angle(x, y) - angle(last_mouse_pos.0 as f64, last_mouse_pos.1 as f64);
+                        universe.rotate(-delta);
+                        window.request_redraw();
                     }
 
                     last_mouse_pos = (x as _, y as _);
                 }
                 WindowEvent::MouseInput { state, button, .. } => match button {
                     winit::event::MouseButton::Left => {
-                        is_left_mouse_button_pressed = state == winit::event::ElementState::Pressed;
+                        is_left_mouse_button_pressed = state == ElementState::Pressed;
                     }
                     winit::event::MouseButton::Right => {
-                        is_right_mouse_button_pressed =
-                            state == winit::event::ElementState::Pressed;
+                        is_right_mouse_button_pressed = state == ElementState::Pressed;
                     }
                     _ => {}
                 },
@@ -119,13 +305,64 @@ pub fn render(mut universe: MandelbrotUniverse, width: u32, height: u32) -> Resu
                 } => match input.virtual_keycode {
                     Some(VirtualKeyCode::Escape) => {
                         *control_flow = ControlFlow::Exit;
-                        return;
                     }
+                    Some(key) if input.state == ElementState::Pressed => match key {
+                        VirtualKeyCode::C => cycling.toggle(&mut universe),
+                        VirtualKeyCode::Q => {
+                            universe.rotate(-ROTATION_STEP);
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::E => {
+                            universe.rotate(ROTATION_STEP);
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::D => {
+                            universe.set_show_refined(!universe.show_refined());
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::A => {
+                            let sampler =
+                                std::mem::replace(&mut aa_sampler, universe.sampler().clone());
+                            log::info!("Anti-aliasing: {} samples per pixel", sampler.count());
+                            universe.set_sampler(sampler);
+                            universe.compute();
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::F => {
+                            current = (current + 1) % formulas.len();
+                            set_formula(&mut universe, &formulas[current]);
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::Left
+                        | VirtualKeyCode::Right
+                        | VirtualKeyCode::Up
+                        | VirtualKeyCode::Down => {
+                            let delta = match key {
+                                VirtualKeyCode::Left => Complex::new(-PARAMETER_STEP, 0.0),
+                                VirtualKeyCode::Right => Complex::new(PARAMETER_STEP, 0.0),
+                                VirtualKeyCode::Up => Complex::new(0.0, PARAMETER_STEP),
+                                _ => Complex::new(0.0, -PARAMETER_STEP),
+                            };
+                            shift_parameter(&mut universe, &mut formulas[current], delta);
+                            window.request_redraw();
+                        }
+                        VirtualKeyCode::R => {
+                            cycling.speed = -cycling.speed;
+                        }
+                        VirtualKeyCode::Plus
+                        | VirtualKeyCode::Equals
+                        | VirtualKeyCode::NumpadAdd => {
+                            cycling.speed *= CYCLE_SPEED_STEP;
+                        }
+                        VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                            cycling.speed /= CYCLE_SPEED_STEP;
+                        }
+                        _ => {}
+                    },
                     _ => {}
                 },
                 WindowEvent::CloseRequested => {
                     *control_flow = ControlFlow::Exit;
-                    return;
                 }
                 _ => {}
             },
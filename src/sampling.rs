@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Jittered offsets are drawn for a tile of pixels repeated over the image.
+const JITTER_TILE: u32 = 64;
+/// Most samples per side of a pixel. The jittered pattern keeps
+/// `JITTER_TILE²·N²` offsets, 32 MB at this size.
+pub const MAX_AA: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplePattern {
+    /// Regular N×N grid.
+    #[default]
+    Grid,
+    /// N×N grid rotated by atan(1/2), no two samples share a row or column.
+    RotatedGrid,
+    /// One random sample in each cell of an N×N grid.
+    Jittered,
+}
+
+impl FromStr for SamplePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grid" => Ok(Self::Grid),
+            "rotated" => Ok(Self::RotatedGrid),
+            "jittered" => Ok(Self::Jittered),
+            _ => Err(format!(
+                "unknown sample pattern '{}' (expected grid, rotated or jittered)",
+                s
+            )),
+        }
+    }
+}
+
+/// Sub-pixel sample positions used for supersampling.
+///
+/// Offsets are in pixels, in `[-0.5, 0.5)` around the pixel sample point.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pattern: SamplePattern,
+    size: u32,
+    offsets: Vec<(f64, f64)>,
+}
+
+impl Sampler {
+    /// `size`×`size` samples per pixel, `seed` is only used by
+    /// [`SamplePattern::Jittered`].
+    pub fn new(pattern: SamplePattern, size: u32, seed: u64) -> Self {
+        let size = size.max(1);
+        let n = size as f64;
+        let cell = |i: u32| (i as f64 + 0.5) / n - 0.5;
+
+        let offsets = match pattern {
+            SamplePattern::Grid => grid(size).map(|(i, j)| (cell(i), cell(j))).collect(),
+            SamplePattern::RotatedGrid => {
+                // Rotating by atan(1/2) and scaling by 1 / cos gives the
+                // classic RGSS pattern for 2×2, wrap to stay in the pixel.
+                let (sin, cos) = (0.5f64).atan().sin_cos();
+                grid(size)
+                    .map(|(i, j)| {
+                        let (x, y) = (cell(i), cell(j));
+                        let (x, y) = ((x * cos - y * sin) / cos, (x * sin + y * cos) / cos);
+                        (x - x.round(), y - y.round())
+                    })
+                    .collect()
+            }
+            SamplePattern::Jittered => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..JITTER_TILE * JITTER_TILE)
+                    .flat_map(|_| grid(size).collect::<Vec<_>>())
+                    .map(|(i, j)| {
+                        (
+                            (i as f64 + rng.gen::<f64>()) / n - 0.5,
+                            (j as f64 + rng.gen::<f64>()) / n - 0.5,
+                        )
+                    })
+                    .collect()
+            }
+        };
+
+        Self {
+            pattern,
+            size,
+            offsets,
+        }
+    }
+
+    /// Number of samples per pixel.
+    pub fn count(&self) -> usize {
+        self.size as usize * self.size as usize
+    }
+
+    /// Sample offsets for the pixel at `(x, y)`.
+    pub fn offsets(&self, x: u32, y: u32) -> &[(f64, f64)] {
+        match self.pattern {
+            SamplePattern::Jittered => {
+                let tile = (y % JITTER_TILE) * JITTER_TILE + x % JITTER_TILE;
+                let start = tile as usize * self.count();
+                &self.offsets[start..start + self.count()]
+            }
+            _ => &self.offsets,
+        }
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new(SamplePattern::Grid, 1, 0)
+    }
+}
+
+fn grid(size: u32) -> impl Iterator<Item = (u32, u32)> {
+    (0..size).flat_map(move |j| (0..size).map(move |i| (i, j)))
+}
+
+/// Adaptive anti-aliasing: after a first pass, only pixels standing out from
+/// their neighbours are sampled again with `sampler`.
+#[derive(Debug, Clone)]
+pub struct AdaptiveAa {
+    pub sampler: Sampler,
+    /// Largest sRGB channel difference (in `[0, 1]`) tolerated between
+    /// neighbours.
+    pub color_threshold: f32,
+    /// Largest iteration count difference tolerated between neighbours.
+    pub iter_threshold: Option<u32>,
+}
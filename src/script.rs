@@ -0,0 +1,482 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Blob, Engine, EvalAltResult, FLOAT, INT};
+
+use crate::color::Dither;
+use crate::complex::Complex;
+use crate::export::{self, Y4mWriter};
+use crate::formula::{self, Formula, FormulaKind};
+use crate::gif::{GifPalette, GifWriter};
+use crate::gradient::{Gradient, Mapping};
+use crate::mandelbrot::{MandelbrotUniverse, ViewPort};
+use crate::palette;
+use crate::sampling::{SamplePattern, Sampler, MAX_AA};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// What a new universe starts with, taken from the command line.
+pub struct Settings {
+    pub gradient: Gradient,
+    pub mapping: Mapping,
+    pub max_iter: u32,
+    pub threads: usize,
+    pub view: ViewPort,
+    pub sampler: Sampler,
+    pub dither: Dither,
+    pub formula: Formula,
+    /// Every built-in formula, by [`FormulaKind::ALL`] order.
+    pub formulas: Vec<Formula>,
+}
+
+/// `Universe` of the scripts, shared by the copies of a variable.
+#[derive(Clone)]
+struct Universe(Rc<RefCell<State>>);
+
+struct State {
+    universe: MandelbrotUniverse,
+    formula: Formula,
+}
+
+/// `Animation` of the scripts, a GIF or Y4M file frames are appended to.
+#[derive(Clone)]
+struct Animation(Rc<RefCell<Option<Sink>>>);
+
+enum Sink {
+    Gif {
+        writer: GifWriter<BufWriter<File>>,
+        /// Delay between frames, in hundredths of a second.
+        delay: u16,
+        size: (u32, u32),
+    },
+    Y4m {
+        writer: Y4mWriter<BufWriter<File>>,
+        size: (u32, u32),
+    },
+    /// Waiting for the first frame, which gives the size.
+    Pending { path: String, fps: u32 },
+}
+
+fn script_error<T>(message: impl Into<String>) -> ScriptResult<T> {
+    Err(message.into().into())
+}
+
+fn io_error<T>(path: &str, err: io::Error) -> ScriptResult<T> {
+    script_error(format!("{}: {}", path, err))
+}
+
+/// A pixel size from a script integer.
+fn size(value: INT, name: &str) -> ScriptResult<u32> {
+    match u32::try_from(value) {
+        Ok(value) if value > 0 => Ok(value),
+        _ => script_error(format!(
+            "{} needs to be a positive integer, not {}",
+            name, value
+        )),
+    }
+}
+
+impl Universe {
+    fn new(settings: &Settings, width: INT, height: INT) -> ScriptResult<Self> {
+        let formula = settings.formula.clone();
+        let mut universe = MandelbrotUniverse::new(
+            size(width, "width")?,
+            size(height, "height")?,
+            settings.threads,
+            &settings.gradient,
+            settings.mapping,
+            settings.max_iter,
+            formula::mandelbrot_fast,
+        );
+        formula.use_in(&mut universe);
+        universe.set_view(settings.view);
+        universe.set_sampler(settings.sampler.clone());
+        universe.set_dither(settings.dither);
+        Ok(Self(Rc::new(RefCell::new(State { universe, formula }))))
+    }
+
+    fn set_formula(&mut self, formula: Formula, reframe: bool) {
+        let mut state = self.0.borrow_mut();
+        if reframe {
+            let (center, scale) = formula.default_view();
+            let mut view = *state.universe.view();
+            view.set_center(center);
+            view.set_scale(scale);
+            state.universe.set_view(view);
+        }
+        formula.use_in(&mut state.universe);
+        state.formula = formula;
+    }
+
+    /// RGBA pixels of the last compute.
+    fn rgba(&self) -> (Vec<u8>, u32, u32) {
+        let state = self.0.borrow();
+        let (width, height) = state.universe.size();
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        state.universe.render(&mut rgba);
+        (rgba, width, height)
+    }
+
+    /// Write the image to `path`, a PNG or, with a `.ppm` extension, a PPM.
+    fn save(&mut self, path: &str) -> ScriptResult<()> {
+        let (rgba, width, height) = self.rgba();
+        export::write_image(Path::new(path), width, height, &rgba)
+            .or_else(|err| io_error(path, err))
+    }
+
+    fn pixel(&self, x: INT, y: INT) -> ScriptResult<(u32, u32)> {
+        let (width, height) = self.0.borrow().universe.size();
+        match (u32::try_from(x), u32::try_from(y)) {
+            (Ok(x), Ok(y)) if x < width && y < height => Ok((x, y)),
+            _ => script_error(format!(
+                "pixel {}, {} is outside the {}x{} image",
+                x, y, width, height
+            )),
+        }
+    }
+}
+
+impl Animation {
+    fn new(path: &str, fps: INT) -> ScriptResult<Self> {
+        let lower = path.to_ascii_lowercase();
+        if !lower.ends_with(".gif") && !lower.ends_with(".y4m") {
+            return script_error(format!("{}: animations are .gif or .y4m files", path));
+        }
+        let fps = match u32::try_from(fps) {
+            Ok(fps) if fps > 0 => fps,
+            _ => return script_error(format!("fps needs to be a positive integer, not {}", fps)),
+        };
+        Ok(Self(Rc::new(RefCell::new(Some(Sink::Pending {
+            path: path.to_owned(),
+            fps,
+        })))))
+    }
+
+    fn add_frame(&mut self, universe: Universe) -> ScriptResult<()> {
+        let (rgba, width, height) = universe.rgba();
+        let mut sink = self.0.borrow_mut();
+        if let Some(Sink::Pending { path, fps }) = &*sink {
+            let (path, fps) = (path.clone(), *fps);
+            let file = File::create(&path).map(BufWriter::new);
+            let created = file.and_then(|file| {
+                if path.to_ascii_lowercase().ends_with(".gif") {
+                    Ok(Sink::Gif {
+                        writer: GifWriter::new(file, width, height, 0, GifPalette::Global)?,
+                        delay: (100.0 / fps as f64).round().max(1.0) as u16,
+                        size: (width, height),
+                    })
+                } else {
+                    Ok(Sink::Y4m {
+                        writer: Y4mWriter::new(file, width, height, fps)?,
+                        size: (width, height),
+                    })
+                }
+            });
+            *sink = Some(created.or_else(|err| io_error(&path, err))?);
+        }
+
+        let result = match &mut *sink {
+            Some(Sink::Gif {
+                writer,
+                delay,
+                size,
+            }) if *size == (width, height) => writer.write_frame(&rgba, *delay),
+            Some(Sink::Y4m { writer, size }) if *size == (width, height) => {
+                writer.write_frame(&rgba)
+            }
+            Some(Sink::Gif { size, .. } | Sink::Y4m { size, .. }) => {
+                return script_error(format!(
+                    "the frame is {}x{}, the animation {}x{}",
+                    width, height, size.0, size.1
+                ))
+            }
+            _ => return script_error("the animation is finished"),
+        };
+        result.or_else(|err| script_error(err.to_string()))
+    }
+
+    /// Complete the file, no frame can be added afterwards.
+    fn finish(&mut self) -> ScriptResult<()> {
+        let result = match self.0.borrow_mut().take() {
+            Some(Sink::Gif { writer, .. }) => writer.finish().and_then(|mut file| file.flush()),
+            // Each frame is flushed as it is written
+            Some(Sink::Y4m { .. }) => Ok(()),
+            Some(Sink::Pending { path, .. }) => {
+                return script_error(format!("{}: the animation has no frame", path))
+            }
+            None => Ok(()),
+        };
+        result.or_else(|err| script_error(err.to_string()))
+    }
+}
+
+/// Run the Rhai script at `path`.
+pub fn run(path: &Path, settings: Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Rc::new(settings);
+    let mut engine = Engine::new();
+    engine.on_print(|text| log::info!("{}", text));
+    engine.on_debug(|text, _, position| log::debug!("{} {}", position, text));
+
+    engine.register_type_with_name::<Universe>("Universe");
+    {
+        let settings = settings.clone();
+        engine.register_fn("universe", move |width: INT, height: INT| {
+            Universe::new(&settings, width, height)
+        });
+    }
+
+    // Computing and moving the view, as in the viewer
+    engine.register_fn("compute", |u: &mut Universe| {
+        u.0.borrow_mut().universe.compute()
+    });
+    engine.register_fn("zoom", |u: &mut Universe, factor: FLOAT, x: INT, y: INT| {
+        let (x, y) = u.pixel(x, y)?;
+        u.0.borrow_mut().universe.zoom(factor, x, y);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    engine.register_fn("translate", |u: &mut Universe, dx: FLOAT, dy: FLOAT| {
+        u.0.borrow_mut().universe.translate(dx, dy)
+    });
+    engine.register_fn("rotate", |u: &mut Universe, degrees: FLOAT| {
+        u.0.borrow_mut().universe.rotate(degrees.to_radians())
+    });
+    engine.register_fn("resize", |u: &mut Universe, width: INT, height: INT| {
+        let (width, height) = (size(width, "width")?, size(height, "height")?);
+        u.0.borrow_mut().universe.resize(width, height);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    engine.register_fn("set_center", |u: &mut Universe, re: FLOAT, im: FLOAT| {
+        let mut state = u.0.borrow_mut();
+        let mut view = *state.universe.view();
+        view.set_center(Complex::new(re, im));
+        state.universe.set_view(view);
+    });
+    engine.register_fn("set_scale", |u: &mut Universe, scale: FLOAT| {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return script_error(format!("scale needs to be above 0, not {}", scale));
+        }
+        let mut state = u.0.borrow_mut();
+        let mut view = *state.universe.view();
+        view.set_scale(scale);
+        state.universe.set_view(view);
+        Ok(())
+    });
+    engine.register_get("center_re", |u: &mut Universe| {
+        u.0.borrow().universe.view().center().re
+    });
+    engine.register_get("center_im", |u: &mut Universe| {
+        u.0.borrow().universe.view().center().im
+    });
+    engine.register_get("scale", |u: &mut Universe| {
+        u.0.borrow().universe.view().scale()
+    });
+    engine.register_get("width", |u: &mut Universe| {
+        u.0.borrow().universe.size().0 as INT
+    });
+    engine.register_get("height", |u: &mut Universe| {
+        u.0.borrow().universe.size().1 as INT
+    });
+
+    // Formula and coloring
+    engine.register_fn("set_max_iter", |u: &mut Universe, max_iter: INT| {
+        let max_iter = size(max_iter, "max_iter")?;
+        u.0.borrow_mut().universe.set_max_iter(max_iter);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    {
+        let settings = settings.clone();
+        engine.register_fn("set_formula", move |u: &mut Universe, name: &str| {
+            let kind: FormulaKind = name.parse()?;
+            let index = FormulaKind::ALL
+                .iter()
+                .position(|&known| known == kind)
+                .expect("every kind is listed");
+            u.set_formula(settings.formulas[index].clone(), true);
+            Ok::<_, Box<EvalAltResult>>(())
+        });
+    }
+    engine.register_fn("set_expression", |u: &mut Universe, text: &str| {
+        let program = text.parse()?;
+        u.set_formula(Formula::Expression(program), true);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    engine.register_fn("set_parameter", |u: &mut Universe, re: FLOAT, im: FLOAT| {
+        let mut formula = u.0.borrow().formula.clone();
+        if formula.parameter().is_none() {
+            return script_error("the formula has no parameter");
+        }
+        formula.set_parameter(Complex::new(re, im));
+        u.set_formula(formula, false);
+        Ok(())
+    });
+    engine.register_fn("set_palette", |u: &mut Universe, path: &str| {
+        let gradient = palette::load(Path::new(path))
+            .or_else(|err| script_error(format!("{}: {}", path, err)))?;
+        u.0.borrow_mut().universe.set_gradient(&gradient);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    engine.register_fn("set_palette_offset", |u: &mut Universe, offset: FLOAT| {
+        let mut state = u.0.borrow_mut();
+        let mapping = Mapping {
+            offset: offset as f32,
+            ..state.universe.mapping()
+        };
+        state.universe.set_mapping(mapping);
+    });
+    engine.register_fn("set_aa", |u: &mut Universe, samples: INT| {
+        let samples = size(samples, "anti-aliasing")?;
+        if samples > MAX_AA {
+            return script_error(format!(
+                "anti-aliasing takes 1 to {} samples per side, not {}",
+                MAX_AA, samples
+            ));
+        }
+        let sampler = Sampler::new(SamplePattern::RotatedGrid, samples, 0);
+        u.0.borrow_mut().universe.set_sampler(sampler);
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+
+    // Reading the results
+    engine.register_fn("iterations", |u: &mut Universe, x: INT, y: INT| {
+        let (x, y) = u.pixel(x, y)?;
+        Ok::<_, Box<EvalAltResult>>(u.0.borrow().universe.sample_at(x, y).iter as INT)
+    });
+    engine.register_fn("converged", |u: &mut Universe, x: INT, y: INT| {
+        let (x, y) = u.pixel(x, y)?;
+        Ok::<_, Box<EvalAltResult>>(u.0.borrow().universe.sample_at(x, y).converged)
+    });
+    engine.register_fn("image", |u: &mut Universe| -> Blob { u.rgba().0 });
+    engine.register_fn("save", Universe::save);
+
+    engine.register_type_with_name::<Animation>("Animation");
+    engine.register_fn("animation", Animation::new);
+    engine.register_fn("add_frame", Animation::add_frame);
+    engine.register_fn("finish", Animation::finish);
+
+    engine
+        .run_file(path.to_owned())
+        .map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::cli::Args;
+
+    /// Directory in the temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "mandelbrot-script-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn settings() -> Settings {
+        let args = Args::default();
+        let formula = |kind| {
+            Formula::new(
+                kind,
+                args.power,
+                &args.polynomial,
+                args.relaxation,
+                args.phoenix,
+                args.bailout,
+            )
+        };
+        Settings {
+            gradient: Gradient::evenly_spaced(&[crate::mandelbrot::PixelColor::WHITE]),
+            mapping: Mapping::default(),
+            max_iter: 100,
+            threads: 1,
+            view: ViewPort::new(Complex::new(-0.5, 0.0), 3.0),
+            sampler: Sampler::default(),
+            dither: Dither::default(),
+            formula: formula(FormulaKind::Mandelbrot),
+            formulas: FormulaKind::ALL.iter().map(|&kind| formula(kind)).collect(),
+        }
+    }
+
+    /// Run `source` as the script `name` in `dir`.
+    fn run_script(dir: &TempDir, name: &str, source: &str) -> Result<(), String> {
+        let path = dir.0.join(name);
+        fs::write(&path, source).unwrap();
+        run(&path, settings()).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn script_renders_and_saves() {
+        let dir = TempDir::new("renders");
+        let image = dir.0.join("center.png");
+        let movie = dir.0.join("zoom.gif");
+        let source = format!(
+            r#"
+            let u = universe(32, 24);
+            u.compute();
+            if u.iterations(16, 12) != 100 {{ throw "the center escaped"; }}
+            if u.iterations(0, 0) >= 100 {{ throw "the corner did not escape"; }}
+            if u.width != 32 || u.height != 24 {{ throw "wrong size"; }}
+            u.set_aa(2);
+            u.save("{}");
+
+            let movie = animation("{}", 10);
+            for frame in 0..3 {{
+                u.set_scale(u.scale / 2.0);
+                u.compute();
+                movie.add_frame(u);
+            }}
+            movie.finish();
+
+            u.set_formula("newton");
+            u.compute();
+            if !u.converged(16, 12) {{ throw "newton did not converge"; }}
+            "#,
+            image.display(),
+            movie.display()
+        );
+        run_script(&dir, "render.rhai", &source).unwrap();
+
+        let png = fs::read(&image).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[16..24], [0, 0, 0, 32, 0, 0, 0, 24]);
+        let gif = fs::read(&movie).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        // Three image descriptors, one per frame
+        let frames = gif.windows(2).filter(|w| w == b"\x00\x2c").count();
+        assert!(frames >= 3, "{} frames", frames);
+    }
+
+    #[test]
+    fn script_errors_are_reported() {
+        let dir = TempDir::new("errors");
+        let error = |source: &str| run_script(&dir, "error.rhai", source).unwrap_err();
+        assert!(error("universe(8, 8).set_aa(100000);")
+            .contains("anti-aliasing takes 1 to 16 samples per side, not 100000"));
+        assert!(error("universe(8, 8).set_aa(0);").contains("anti-aliasing needs to be"));
+        assert!(
+            error("let u = universe(8, 8); u.compute(); u.iterations(8, 0);")
+                .contains("pixel 8, 0 is outside the 8x8 image")
+        );
+        assert!(error("universe(0, 8);").contains("width needs to be a positive integer"));
+        assert!(error("universe(8, 8).set_formula(\"nope\");").contains("nope"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::deflate::ZlibEncoder;
+
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const LONG8: u16 = 16;
+
+/// Tiled 8 bit RGB BigTIFF, deflate compressed with horizontal prediction.
+/// Tiles are written one at a time in row-major order, so only one tile
+/// is ever held in memory whatever the image size.
+pub struct TiffWriter<W: Write + Seek> {
+    writer: W,
+    width: u32,
+    height: u32,
+    tile: u32,
+    /// Offset and size of each tile written so far.
+    tiles: Vec<(u64, u64)>,
+}
+
+impl<W: Write + Seek> TiffWriter<W> {
+    /// `tile` is the side of the square tiles, a multiple of 16.
+    pub fn new(mut writer: W, width: u32, height: u32, tile: u32) -> io::Result<Self> {
+        if tile == 0 || !tile.is_multiple_of(16) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TIFF tiles must be a multiple of 16 pixels",
+            ));
+        }
+
+        // Little endian BigTIFF, 8 byte offsets, IFD offset patched at the end
+        writer.write_all(b"II")?;
+        writer.write_all(&43u16.to_le_bytes())?;
+        writer.write_all(&8u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+            tile,
+            tiles: Vec::new(),
+        })
+    }
+
+    /// Number of tiles across and down.
+    pub fn tiles(&self) -> (u32, u32) {
+        (
+            self.width.div_ceil(self.tile),
+            self.height.div_ceil(self.tile),
+        )
+    }
+
+    /// Append the next tile, `width`×`height` RGB pixels. Tiles on the right
+    /// and bottom edges may be smaller than the tile size, they are padded.
+    pub fn write_tile(&mut self, rgb: &[u8], width: u32, height: u32) -> io::Result<()> {
+        let (across, down) = self.tiles();
+        if self.tiles.len() as u64 >= across as u64 * down as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many tiles for the TIFF size",
+            ));
+        }
+
+        let side = self.tile as usize;
+        let mut encoder = ZlibEncoder::new();
+        let mut row = vec![0; side * 3];
+        for y in 0..side {
+            row.fill(0);
+            if y < height as usize {
+                let line = width as usize * 3;
+                row[..line].copy_from_slice(&rgb[y * line..(y + 1) * line]);
+            }
+            // Horizontal predictor: each sample minus the one on its left
+            for i in (3..row.len()).rev() {
+                row[i] = row[i].wrapping_sub(row[i - 3]);
+            }
+            encoder.write(&row);
+        }
+        let data = encoder.finish();
+
+        let offset = self.writer.stream_position()?;
+        self.writer.write_all(&data)?;
+        self.tiles.push((offset, data.len() as u64));
+        Ok(())
+    }
+
+    /// Write the image directory, every tile must have been written.
+    pub fn finish(mut self) -> io::Result<W> {
+        let (across, down) = self.tiles();
+        if self.tiles.len() as u64 != across as u64 * down as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "TIFF has {} tiles out of {}",
+                    self.tiles.len(),
+                    across as u64 * down as u64
+                ),
+            ));
+        }
+
+        // Tile offsets and sizes, out of line, on a word boundary like the
+        // directory
+        if self.writer.stream_position()? % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let offsets_at = self.writer.stream_position()?;
+        for &(offset, _) in &self.tiles {
+            self.writer.write_all(&offset.to_le_bytes())?;
+        }
+        let counts_at = self.writer.stream_position()?;
+        for &(_, count) in &self.tiles {
+            self.writer.write_all(&count.to_le_bytes())?;
+        }
+
+        let count = self.tiles.len() as u64;
+        let short = |v: u16| v as u64;
+        let entries: [(u16, u16, u64, u64); 12] = [
+            (256, LONG, 1, self.width as u64),
+            (257, LONG, 1, self.height as u64),
+            // Three 8 bit samples, packed in the value field
+            (258, SHORT, 3, 8 | 8 << 16 | 8 << 32),
+            (259, SHORT, 1, short(8)),
+            (262, SHORT, 1, short(2)),
+            (277, SHORT, 1, short(3)),
+            (284, SHORT, 1, short(1)),
+            (317, SHORT, 1, short(2)),
+            (322, LONG, 1, self.tile as u64),
+            (323, LONG, 1, self.tile as u64),
+            (324, LONG8, count, offsets_at),
+            (325, LONG8, count, counts_at),
+        ];
+        // Single tile: the arrays fit in the entry itself
+        let entries = entries.map(|(tag, kind, n, value)| match (tag, n) {
+            (324, 1) => (tag, kind, n, self.tiles[0].0),
+            (325, 1) => (tag, kind, n, self.tiles[0].1),
+            _ => (tag, kind, n, value),
+        });
+
+        let ifd_at = self.writer.stream_position()?;
+        self.writer
+            .write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (tag, kind, n, value) in entries {
+            self.writer.write_all(&tag.to_le_bytes())?;
+            self.writer.write_all(&kind.to_le_bytes())?;
+            self.writer.write_all(&n.to_le_bytes())?;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.writer.write_all(&0u64.to_le_bytes())?;
+
+        self.writer.seek(SeekFrom::Start(8))?;
+        self.writer.write_all(&ifd_at.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::deflate::zlib_decompress;
+
+    fn u16_at(data: &[u8], at: u64) -> u16 {
+        let at = at as usize;
+        u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], at: u64) -> u64 {
+        let at = at as usize;
+        u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+    }
+
+    /// Directory entries as `(tag, type, count, value)`.
+    fn entries(data: &[u8]) -> Vec<(u16, u16, u64, u64)> {
+        let ifd = u64_at(data, 8);
+        (0..u64_at(data, ifd))
+            .map(|i| {
+                let at = ifd + 8 + i * 20;
+                (
+                    u16_at(data, at),
+                    u16_at(data, at + 2),
+                    u64_at(data, at + 4),
+                    u64_at(data, at + 12),
+                )
+            })
+            .collect()
+    }
+
+    fn value_of(entries: &[(u16, u16, u64, u64)], tag: u16) -> (u16, u64, u64) {
+        let &(_, kind, count, value) = entries.iter().find(|e| e.0 == tag).unwrap();
+        (kind, count, value)
+    }
+
+    /// Decompress a tile and undo the horizontal predictor.
+    fn tile_pixels(data: &[u8], offset: u64, size: u64, side: usize) -> Vec<u8> {
+        let compressed = &data[offset as usize..(offset + size) as usize];
+        let mut pixels = zlib_decompress(compressed).unwrap();
+        assert_eq!(pixels.len(), side * side * 3);
+        for row in pixels.chunks_mut(side * 3) {
+            for i in 3..row.len() {
+                row[i] = row[i].wrapping_add(row[i - 3]);
+            }
+        }
+        pixels
+    }
+
+    fn pixel(x: u32, y: u32) -> [u8; 3] {
+        [(x * 7) as u8, (y * 13) as u8, (x * y) as u8]
+    }
+
+    /// RGB pixels of the tile at `(tx, ty)`, cut at the image edges.
+    fn tile(tx: u32, ty: u32, side: u32, width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+        let w = side.min(width - tx * side);
+        let h = side.min(height - ty * side);
+        let rgb = (0..h)
+            .flat_map(|y| (0..w).flat_map(move |x| pixel(tx * side + x, ty * side + y)))
+            .collect();
+        (rgb, w, h)
+    }
+
+    #[test]
+    fn writes_tiled_bigtiff() {
+        let (width, height, side) = (20, 18, 16);
+        let mut tiff = TiffWriter::new(Cursor::new(Vec::new()), width, height, side).unwrap();
+        assert_eq!(tiff.tiles(), (2, 2));
+        for ty in 0..2 {
+            for tx in 0..2 {
+                let (rgb, w, h) = tile(tx, ty, side, width, height);
+                tiff.write_tile(&rgb, w, h).unwrap();
+            }
+        }
+        let data = tiff.finish().unwrap().into_inner();
+
+        assert_eq!(&data[..8], b"II\x2b\x00\x08\x00\x00\x00");
+        let ifd = u64_at(&data, 8);
+        assert!(ifd.is_multiple_of(2));
+        // The next directory offset ends the file
+        assert_eq!(ifd + 8 + 12 * 20 + 8, data.len() as u64);
+        assert_eq!(u64_at(&data, ifd + 8 + 12 * 20), 0);
+
+        let entries = entries(&data);
+        let tags: Vec<u16> = entries.iter().map(|e| e.0).collect();
+        assert_eq!(
+            tags,
+            [256, 257, 258, 259, 262, 277, 284, 317, 322, 323, 324, 325]
+        );
+        assert_eq!(value_of(&entries, 256), (LONG, 1, 20));
+        assert_eq!(value_of(&entries, 257), (LONG, 1, 18));
+        assert_eq!(value_of(&entries, 258), (SHORT, 3, 8 | 8 << 16 | 8 << 32));
+        assert_eq!(value_of(&entries, 259), (SHORT, 1, 8));
+        assert_eq!(value_of(&entries, 317), (SHORT, 1, 2));
+        assert_eq!(value_of(&entries, 322), (LONG, 1, 16));
+        assert_eq!(value_of(&entries, 323), (LONG, 1, 16));
+
+        let (kind, count, offsets_at) = value_of(&entries, 324);
+        assert_eq!((kind, count), (LONG8, 4));
+        let (kind, count, counts_at) = value_of(&entries, 325);
+        assert_eq!((kind, count), (LONG8, 4));
+        assert!(offsets_at.is_multiple_of(2));
+        assert_eq!(counts_at, offsets_at + 4 * 8);
+        assert_eq!(ifd, counts_at + 4 * 8);
+
+        // Tiles follow the header back to back, in row-major order
+        let mut next = 16;
+        for i in 0..4 {
+            let offset = u64_at(&data, offsets_at + i * 8);
+            let size = u64_at(&data, counts_at + i * 8);
+            assert_eq!(offset, next);
+            next = offset + size;
+
+            let (tx, ty) = (i as u32 % 2, i as u32 / 2);
+            let (rgb, w, h) = tile(tx, ty, side, width, height);
+            let pixels = tile_pixels(&data, offset, size, side as usize);
+            for y in 0..side as usize {
+                for x in 0..side as usize {
+                    let at = (y * side as usize + x) * 3;
+                    let expected = if x < w as usize && y < h as usize {
+                        let from = (y * w as usize + x) * 3;
+                        &rgb[from..from + 3]
+                    } else {
+                        &[0; 3][..]
+                    };
+                    assert_eq!(&pixels[at..at + 3], expected, "tile {} at {},{}", i, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_tile_is_inline() {
+        let mut tiff = TiffWriter::new(Cursor::new(Vec::new()), 5, 3, 16).unwrap();
+        let (rgb, w, h) = tile(0, 0, 16, 5, 3);
+        tiff.write_tile(&rgb, w, h).unwrap();
+        let data = tiff.finish().unwrap().into_inner();
+
+        let entries = entries(&data);
+        let (kind, count, offset) = value_of(&entries, 324);
+        assert_eq!((kind, count, offset), (LONG8, 1, 16));
+        let (_, _, size) = value_of(&entries, 325);
+        let pixels = tile_pixels(&data, offset, size, 16);
+        assert_eq!(&pixels[..15], &rgb[..15]);
+        assert_eq!(&pixels[16 * 3..16 * 3 + 15], &rgb[15..30]);
+    }
+
+    #[test]
+    fn tile_count_is_checked() {
+        assert!(TiffWriter::new(Cursor::new(Vec::new()), 8, 8, 12).is_err());
+        assert!(TiffWriter::new(Cursor::new(Vec::new()), 8, 8, 0).is_err());
+
+        let (rgb, w, h) = tile(0, 0, 16, 8, 8);
+        let mut tiff = TiffWriter::new(Cursor::new(Vec::new()), 8, 8, 16).unwrap();
+        tiff.write_tile(&rgb, w, h).unwrap();
+        assert!(tiff.write_tile(&rgb, w, h).is_err());
+
+        let tiff = TiffWriter::new(Cursor::new(Vec::new()), 20, 8, 16).unwrap();
+        assert!(tiff.finish().is_err());
+    }
+}